@@ -0,0 +1,183 @@
+// どこで: kong_ics / approve_manager の異常検知
+// 何を: キーごとに連続失敗を数え、しきい値を超えたら 1 回だけ警告を送り、正常に戻ったら復旧を送る
+// なぜ: tick の連続失敗・レッグ失敗・残高不足・approve 失敗・スナップショットの停止がログにしか残らず気付けないため
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::arb::Trade;
+use crate::config::AlertConfig;
+use crate::journal::now_ms;
+use crate::notify::{Notification, Notifier, Severity};
+
+#[derive(Debug, Default)]
+struct AlertState {
+    /// 連続した失敗回数
+    failures: u32,
+    /// 警告を送った後か（復旧を送るまで再送しない）
+    active: bool,
+}
+
+pub struct AlertManager {
+    notifier: Option<Arc<dyn Notifier>>,
+    config: AlertConfig,
+    states: Mutex<HashMap<String, AlertState>>,
+}
+
+impl AlertManager {
+    pub fn new(notifier: Option<Arc<dyn Notifier>>, config: AlertConfig) -> Self {
+        AlertManager {
+            notifier,
+            config,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    /// 失敗を 1 回数える。threshold 回続いたときだけ alert(連続回数) を送る。threshold 0 は無効
+    pub async fn failing(
+        &self,
+        key: &str,
+        threshold: u32,
+        alert: impl FnOnce(u32) -> Notification,
+    ) {
+        if threshold == 0 {
+            return;
+        }
+        let fire = {
+            let mut states = self.lock();
+            let state = states.entry(key.to_string()).or_default();
+            state.failures += 1;
+            if state.failures >= threshold && !state.active {
+                state.active = true;
+                Some(state.failures)
+            } else {
+                None
+            }
+        };
+        if let Some(failures) = fire {
+            let notification = alert(failures).with_dedup_key(key);
+            warn!("警告: {}", notification.text());
+            self.send(&notification).await;
+        }
+    }
+
+    /// 正常に戻ったことを記録する。警告中だったときだけ recovery を送る
+    pub async fn healthy(&self, key: &str, recovery: impl FnOnce() -> Notification) {
+        let was_active = {
+            let mut states = self.lock();
+            states.remove(key).map(|s| s.active).unwrap_or(false)
+        };
+        if was_active {
+            let notification = recovery().with_dedup_key(format!("{}|recovered", key));
+            info!("復旧: {}", notification.text());
+            self.send(&notification).await;
+        }
+    }
+
+    /// 警告中のキー
+    pub fn active(&self) -> Vec<String> {
+        let states = self.lock();
+        let mut keys: Vec<String> = states
+            .iter()
+            .filter(|(_, s)| s.active)
+            .map(|(k, _)| k.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// 警告ではない通知をそのまま送る
+    pub async fn send(&self, notification: &Notification) {
+        if let Some(notifier) = &self.notifier {
+            if let Err(e) = notifier.send(notification).await {
+                warn!("通知失敗: {}", e);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, AlertState>> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 残高が ALERT_MIN_BALANCES の下限を割ったら警告し、戻ったら復旧を送る
+    pub async fn check_balance(&self, account: &str, token: &str, balance_e8: u128) {
+        let Some(min_e8) = self.config.min_balance_e8(token) else {
+            return;
+        };
+        let key = format!("balance_low|{}|{}", account, token);
+        let balance = format!("{:.4} {}", balance_e8 as f64 / 1e8f64, token);
+        let min = format!("{:.4} {}", min_e8 as f64 / 1e8f64, token);
+        if balance_e8 < min_e8 {
+            self.failing(&key, 1, |_| {
+                Notification::new("balance_low", Severity::Warning, "残高が下限を下回りました")
+                    .with_field("identity", account)
+                    .with_field("残高", balance.clone())
+                    .with_field("下限", min.clone())
+            })
+            .await;
+        } else {
+            self.healthy(&key, || {
+                Notification::new(
+                    "balance_low_recovered",
+                    Severity::Recovered,
+                    "残高が下限を上回りました",
+                )
+                .with_field("identity", account)
+                .with_field("残高", balance)
+                .with_field("下限", min)
+            })
+            .await;
+        }
+    }
+}
+
+/// 各ペアのスナップショットが ALERT_SNAPSHOT_STALE_SECS 以上更新されていなければ警告する。
+/// tick が固まって戻らない場合も、ループの外から検出できるように別タスクで見る
+pub async fn watch_snapshots(alerts: Arc<AlertManager>, trades: Vec<Arc<Trade>>) {
+    let stale_secs = alerts.config().snapshot_stale_secs;
+    if stale_secs == 0 {
+        return;
+    }
+    let stale_ms = stale_secs as i64 * 1_000;
+    let interval = Duration::from_secs((stale_secs / 4).clamp(1, 15));
+    loop {
+        sleep(interval).await;
+        let now = now_ms();
+        for trade in &trades {
+            let age_ms = now - trade.last_snapshot_ms();
+            let key = format!("snapshot_stale|{}", trade.symbol());
+            if age_ms >= stale_ms {
+                alerts
+                    .failing(&key, 1, |_| {
+                        Notification::new(
+                            "snapshot_stale",
+                            Severity::Error,
+                            "スナップショットが更新されていません",
+                        )
+                        .with_pair(trade.symbol())
+                        .with_field("最終更新", format!("{} 秒前", age_ms / 1_000))
+                        .with_field("しきい値", format!("{} 秒", stale_secs))
+                    })
+                    .await;
+            } else {
+                alerts
+                    .healthy(&key, || {
+                        Notification::new(
+                            "snapshot_stale_recovered",
+                            Severity::Recovered,
+                            "スナップショットの更新が再開しました",
+                        )
+                        .with_pair(trade.symbol())
+                    })
+                    .await;
+            }
+        }
+    }
+}
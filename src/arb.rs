@@ -2,16 +2,35 @@
 // 何を: プール情報のキャッシュ、計算、スワップ実行、通知
 // なぜ: 上位(main)から見たときに単一目的で扱えるようにするため
 
-use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
+use tokio::time::{sleep, Duration};
 use tracing::{info, warn};
 
-use crate::config::{PairConfig, ICP_TRANSFER_FEE_E8};
-use crate::ic_client::agent::IcClient;
-use crate::ic_client::ics::{fetch_pool_snapshot as fetch_ics, IcsPoolSnapshot};
-use crate::ic_client::kong::{fetch_pool_snapshot as fetch_kong, KongPoolSnapshot};
-use crate::ic_client::swap::{swap_icps_deposit, swap_kong};
-use crate::notify::DiscordNotifier;
+use crate::alert::AlertManager;
+use crate::breaker::{PriceAnomaly, PriceBreaker};
+use crate::config::{BreakerConfig, CallTimeouts, PairConfig, ICP_LEDGER_RAW, ICP_TRANSFER_FEE_E8};
+use crate::control::TradingControl;
+use crate::health::HealthState;
+use crate::ic_client::agent::{IcClient, IcClientError, SubmittedUpdate};
+use crate::ic_client::ics::{fetch_pool_snapshot as fetch_ics, IcsError, IcsPoolSnapshot};
+use crate::ic_client::kong::{
+    fetch_pool_snapshot as fetch_kong, fetch_swap_fill, KongError, KongPoolSnapshot,
+};
+use crate::ic_client::ledger::{fetch_allowance, fetch_balance};
+use crate::ic_client::swap::{
+    finish_swap, submit_swap_icps_deposit, submit_swap_kong, SwapError, SwapReply,
+};
+use crate::journal::{
+    now_ms, Journal, OpportunityRecord, RequestStatus, SwapRecord, SwapRequestRecord,
+};
+use crate::metrics::metrics;
+use crate::notify::{Notification, Notifier, Severity};
+use crate::pnl::{realized_pnl, BalanceSnapshot, IdentitySwaps, PnlInput, SwapFills, SwapWindow};
+use crate::recorder::SnapshotRecorder;
+use crate::report::TradeStats;
+use crate::risk::{RiskManager, RiskPermit};
 
 #[derive(Debug)]
 pub enum TradeError {
@@ -19,12 +38,43 @@ pub enum TradeError {
     Logic(String),
 }
 
+/// スナップショット 1 件の更新結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refresh {
+    Updated,
+    Failed,
+    /// 応答の署名検証に失敗した（キャッシュは更新しない）
+    Unverified,
+}
+
+/// 再起動時に結果を確認しに行く未確定レッグの範囲。IC の request status はこれより早く消える
+const RECOVERY_WINDOW_MS: i64 = 15 * 60 * 1_000;
+
 pub struct Trade {
     config: PairConfig,
     client: Arc<IcClient>,
+    /// swap レッグ用。agent は client と共有し、タイムアウトと ingress expiry だけ短くする
+    swap_client: IcClient,
     kong_cache: RwLock<Option<KongPoolSnapshot>>,
     ics_cache: RwLock<Option<IcsPoolSnapshot>>,
-    notifier: Option<DiscordNotifier>,
+    notifier: Option<Arc<dyn Notifier>>,
+    journal: Option<Arc<Journal>>,
+    record_evaluations: bool,
+    pnl_settle_ms: u64,
+    /// 同じ identity の Trade で共有する（実現損益の残高差分に他のペアが混ざらないように）
+    identity_swaps: Arc<IdentitySwaps>,
+    recorder: Option<Arc<SnapshotRecorder>>,
+    health: Option<Arc<HealthState>>,
+    stats: Option<Arc<TradeStats>>,
+    alerts: Option<Arc<AlertManager>>,
+    control: Option<Arc<TradingControl>>,
+    risk: Option<Arc<RiskManager>>,
+    price_breaker: Option<PriceBreaker>,
+    /// 両方のスナップショットを最後に更新できた時刻（起動直後は作成時刻）
+    last_snapshot_ms: AtomicI64,
+    require_verified_queries: bool,
+    /// 検証が済まず取引を見送っている理由。変わったときだけログに出す
+    verification_refused: Mutex<Option<&'static str>>,
     fee_rate: f64,
     min_receive_factor: f64,
     profit_threshold_e8: f64,
@@ -35,7 +85,7 @@ impl Trade {
     pub fn new(
         config: PairConfig,
         client: Arc<IcClient>,
-        notifier: Option<DiscordNotifier>,
+        notifier: Option<Arc<dyn Notifier>>,
         fee_rate: f64,
         min_receive_factor: f64,
         profit_threshold_e8: f64,
@@ -43,10 +93,25 @@ impl Trade {
     ) -> Self {
         Trade {
             config,
+            swap_client: (*client).clone(),
             client,
             kong_cache: RwLock::new(None),
             ics_cache: RwLock::new(None),
             notifier,
+            journal: None,
+            record_evaluations: false,
+            pnl_settle_ms: 0,
+            identity_swaps: Arc::new(IdentitySwaps::default()),
+            recorder: None,
+            health: None,
+            stats: None,
+            alerts: None,
+            control: None,
+            risk: None,
+            price_breaker: None,
+            last_snapshot_ms: AtomicI64::new(now_ms()),
+            require_verified_queries: false,
+            verification_refused: Mutex::new(None),
             fee_rate,
             min_receive_factor,
             profit_threshold_e8,
@@ -54,6 +119,105 @@ impl Trade {
         }
     }
 
+    /// swap レッグの ingress expiry と結果待ちの上限を設定する。
+    /// 判定時の価格が古くなった頃には message が受理されなくなるよう短くしておく
+    pub fn with_swap_timeouts(mut self, ingress_expiry_secs: u64, timeout_ms: u64) -> Self {
+        let timeouts = CallTimeouts {
+            ingress_expiry_secs,
+            update_timeout_ms: timeout_ms,
+            ..self.client.timeouts()
+        };
+        self.swap_client = (*self.client).clone().with_timeouts(timeouts);
+        self
+    }
+
+    /// 取引ジャーナルを有効化する。record_evaluations=false なら実行した機会だけ残す
+    pub fn with_journal(mut self, journal: Arc<Journal>, record_evaluations: bool) -> Self {
+        self.journal = Some(journal);
+        self.record_evaluations = record_evaluations;
+        self
+    }
+
+    /// swap 後、実現損益を測るために残高を再取得するまでの待ち時間
+    pub fn with_pnl_settle_ms(mut self, pnl_settle_ms: u64) -> Self {
+        self.pnl_settle_ms = pnl_settle_ms;
+        self
+    }
+
+    /// 同じ identity で取引する Trade に同じものを渡す
+    pub fn with_identity_swaps(mut self, identity_swaps: Arc<IdentitySwaps>) -> Self {
+        self.identity_swaps = identity_swaps;
+        self
+    }
+
+    /// tick ごとのプールスナップショットを backtest 用に保存する
+    pub fn with_recorder(mut self, recorder: Arc<SnapshotRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// tick の成否を /healthz・/readyz に反映する
+    pub fn with_health(mut self, health: Arc<HealthState>) -> Self {
+        health.register(&self.config.symbol);
+        self.health = Some(health);
+        self
+    }
+
+    /// 判定・swap・実現損益を定期レポート用に集計する
+    pub fn with_stats(mut self, stats: Arc<TradeStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// tick の連続失敗・swap の失敗・残高不足を警告する
+    pub fn with_alerts(mut self, alerts: Arc<AlertManager>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// 一時停止中は swap しない（スナップショットの更新と判定は続ける）
+    pub fn with_control(mut self, control: Arc<TradingControl>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// swap の前に投入額・同時実行数の上限を確かめ、実現損益と中間トークン残高を渡す
+    pub fn with_risk(mut self, risk: Arc<RiskManager>) -> Self {
+        self.risk = Some(risk);
+        self
+    }
+
+    /// 2 会場の価格の乖離・中央値からの急変・大きすぎる見込みエッジがあれば swap せず警告する
+    pub fn with_price_breaker(mut self, config: BreakerConfig) -> Self {
+        self.price_breaker = Some(PriceBreaker::new(config));
+        self
+    }
+
+    /// 署名検証済みの query 応答だけで取引する。
+    /// 有効にすると、client が検証していない場合や、その tick のスナップショットが検証に失敗した場合は swap しない
+    pub fn with_verified_queries(mut self, required: bool) -> Self {
+        self.require_verified_queries = required;
+        self
+    }
+
+    /// 判定に使うパラメータ（backtest からも同じ判定を使う）
+    pub fn decision_params(&self) -> DecisionParams {
+        DecisionParams {
+            fee_rate: self.fee_rate,
+            ikiti_e8: self.config.ikiti_e8,
+            sns_fee_e8: self.config.sns_fee_e8,
+        }
+    }
+
+    /// BOB_ICP -> BOB（メトリクスやレポートのトークン名）
+    pub fn sns_label(&self) -> &str {
+        self.config
+            .symbol
+            .split('_')
+            .next()
+            .unwrap_or(&self.config.symbol)
+    }
+
     pub fn symbol(&self) -> &str {
         &self.config.symbol
     }
@@ -62,7 +226,11 @@ impl Trade {
         self.loop_interval_ms
     }
 
-    pub async fn update_kong_cache(&self) {
+    pub fn last_snapshot_ms(&self) -> i64 {
+        self.last_snapshot_ms.load(Ordering::Relaxed)
+    }
+
+    pub async fn update_kong_cache(&self) -> Refresh {
         match fetch_kong(
             &self.client,
             &self.config.kong_canister,
@@ -73,109 +241,331 @@ impl Trade {
             Ok(snapshot) => {
                 let mut guard = self.kong_cache.write().await;
                 *guard = Some(snapshot);
+                Refresh::Updated
             }
             Err(e) => {
+                metrics()
+                    .snapshot_fetch_failures
+                    .with_label_values(&[&self.config.symbol, "kong"])
+                    .inc();
                 warn!("{}: Kong 更新失敗: {}", self.config.symbol, e);
+                match e {
+                    KongError::Client(e) if e.is_verification_failure() => Refresh::Unverified,
+                    _ => Refresh::Failed,
+                }
             }
         }
     }
 
-    pub async fn update_ics_cache(&self) {
+    pub async fn update_ics_cache(&self) -> Refresh {
         match fetch_ics(&self.client, &self.config.icpswap_lp).await {
             Ok(snapshot) => {
                 let mut guard = self.ics_cache.write().await;
                 *guard = Some(snapshot);
+                Refresh::Updated
             }
             Err(e) => {
+                metrics()
+                    .snapshot_fetch_failures
+                    .with_label_values(&[&self.config.symbol, "ics"])
+                    .inc();
                 warn!("{}: ICS 更新失敗: {}", self.config.symbol, e);
+                match e {
+                    IcsError::Client(e) if e.is_verification_failure() => Refresh::Unverified,
+                    _ => Refresh::Failed,
+                }
             }
         }
     }
 
+    /// 取引判定に使えるだけの検証が済んでいなければ理由を返す
+    fn verification_refusal(&self, kong: Refresh, ics: Refresh) -> Option<&'static str> {
+        if !self.require_verified_queries {
+            None
+        } else if !self.client.verifies_queries() {
+            Some("query 応答の署名検証が無効")
+        } else if kong == Refresh::Unverified || ics == Refresh::Unverified {
+            Some("query 応答の署名検証に失敗")
+        } else {
+            None
+        }
+    }
+
+    /// 検証が済まず取引を見送っている理由。見送っていなければ None
+    pub fn verification_refused(&self) -> Option<&'static str> {
+        *self
+            .verification_refused
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 見送る理由が変わったときだけログに出す（tick ごとに同じ警告を出さない）
+    fn note_verification(&self, reason: Option<&'static str>) {
+        let mut current = self
+            .verification_refused
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if *current == reason {
+            return;
+        }
+        match reason {
+            Some(reason) => warn!(
+                "{}: {} のため、利益見込みがあっても取引しません",
+                self.config.symbol, reason
+            ),
+            None => info!(
+                "{}: query 応答の署名検証が通ったので取引を再開します",
+                self.config.symbol
+            ),
+        }
+        *current = reason;
+    }
+
     pub async fn tick(&self) -> Result<(), TradeError> {
+        let res = self.run_tick().await;
+        if let Some(health) = &self.health {
+            match &res {
+                Ok(true) => health.ok(&self.config.symbol),
+                Ok(false) => health.fail(&self.config.symbol, "スナップショット取得失敗"),
+                Err(e) => health.fail(&self.config.symbol, &format!("{:?}", e)),
+            }
+        }
+        if let Some(alerts) = &self.alerts {
+            self.alert_tick(alerts, &res).await;
+        }
+        res.map(|_| ())
+    }
+
+    /// スナップショットを更新できない・tick がエラーになる状態が続いたら警告する
+    async fn alert_tick(&self, alerts: &AlertManager, res: &Result<bool, TradeError>) {
+        let key = format!("tick_failures|{}", self.config.symbol);
+        let error = match res {
+            Ok(true) => {
+                alerts
+                    .healthy(&key, || {
+                        Notification::new(
+                            "tick_failures_recovered",
+                            Severity::Recovered,
+                            "tick が正常に戻りました",
+                        )
+                        .with_pair(&self.config.symbol)
+                    })
+                    .await;
+                return;
+            }
+            Ok(false) => "スナップショット取得失敗".to_string(),
+            Err(e) => format!("{:?}", e),
+        };
+        alerts
+            .failing(&key, alerts.config().tick_failures, |n| {
+                Notification::new(
+                    "tick_failures",
+                    Severity::Error,
+                    "tick が連続で失敗しています",
+                )
+                .with_pair(&self.config.symbol)
+                .with_field("連続失敗", n.to_string())
+                .with_field("最後のエラー", error)
+            })
+            .await;
+    }
+
+    /// 価格の異常が起きたら 1 回だけ警告し、収まったら復旧を送る
+    async fn alert_price(
+        &self,
+        alerts: &AlertManager,
+        kong: &KongPoolSnapshot,
+        ics: &IcsPoolSnapshot,
+        res: &Result<(), PriceAnomaly>,
+    ) {
+        let key = format!("price_anomaly|{}", self.config.symbol);
+        match res {
+            Ok(()) => {
+                alerts
+                    .healthy(&key, || {
+                        Notification::new(
+                            "price_anomaly_recovered",
+                            Severity::Recovered,
+                            "価格の異常が収まりました",
+                        )
+                        .with_pair(&self.config.symbol)
+                    })
+                    .await
+            }
+            Err(anomaly) => {
+                alerts
+                    .failing(&key, 1, |_| {
+                        Notification::new(
+                            "price_anomaly",
+                            Severity::Error,
+                            "価格が異常なため取引を止めています",
+                        )
+                        .with_pair(&self.config.symbol)
+                        .with_field("内容", anomaly.to_string())
+                        .with_field("Kong 価格", format!("{:.8}", kong.price_icp_per_sns))
+                        .with_field("ICS 価格", format!("{:.8}", ics.price_icp_per_sns))
+                    })
+                    .await
+            }
+        }
+    }
+
+    /// スナップショットを両方とも更新できたら Ok(true)
+    async fn run_tick(&self) -> Result<bool, TradeError> {
+        metrics()
+            .ticks
+            .with_label_values(&[&self.config.symbol])
+            .inc();
         // Kong/ICS を並列更新
-        tokio::join!(self.update_kong_cache(), self.update_ics_cache());
+        let (kong_refresh, ics_refresh) =
+            tokio::join!(self.update_kong_cache(), self.update_ics_cache());
+        let refreshed = kong_refresh == Refresh::Updated && ics_refresh == Refresh::Updated;
+        if refreshed {
+            self.last_snapshot_ms.store(now_ms(), Ordering::Relaxed);
+        }
 
         let kong_cache = { self.kong_cache.read().await.clone() };
         let ics_cache = { self.ics_cache.read().await.clone() };
         if kong_cache.is_none() || ics_cache.is_none() {
-            return Ok(());
+            return Ok(refreshed);
         }
 
         let kong = kong_cache.expect("checked above");
         let ics = ics_cache.expect("checked above");
-        let ikiti = self.config.ikiti_e8 as f64;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&self.config.symbol, &kong, &ics);
+        }
 
-        let result = cal_amount(
-            kong.icp_balance,
-            kong.sns_balance,
-            ics.token0_k,
-            ics.token1_k,
-        );
-        let mut result_abs = result.abs();
-        if result_abs > ikiti {
-            result_abs = ikiti;
-        }
-
-        let fee = self.fee_rate;
-        let (kekka, direction, output_a, output_b) = if result < 0f64 {
-            let output_icpswap = swap_icp_to_ckusdc(result_abs, ics.token1_k, ics.token0_k, fee);
-            let output_kong = kong_quote_const_prod(
-                output_icpswap,
-                &kong,
-                KongDirection::SnsToIcp,
-                ICP_TRANSFER_FEE_E8,
-            );
-            let delta = output_kong - result_abs;
-            (delta, SwapDirection::IcsToKong, output_icpswap, output_kong)
-        } else {
-            let output_kong = kong_quote_const_prod(
-                result_abs,
-                &kong,
-                KongDirection::IcpToSns,
-                self.config.sns_fee_e8,
-            );
-            let output_icpswap = swap_icp_to_ckusdc(output_kong, ics.token0_k, ics.token1_k, fee);
-            let delta = output_icpswap - result_abs;
-            (delta, SwapDirection::KongToIcs, output_kong, output_icpswap)
-        };
+        let opportunity = evaluate(&kong, &ics, &self.decision_params());
+        if opportunity.amount_in > 0f64 {
+            metrics()
+                .edge_bps
+                .with_label_values(&[&self.config.symbol, opportunity.direction.as_str()])
+                .observe(opportunity.expected_profit / opportunity.amount_in * 10_000f64);
+        }
 
-        // 方向に応じて中間トークンが SNS になるので名称を明示
-        let _mid_token = &self.config.token_sns;
         // 経路ログ（必要ならコメントを外す）
-        // match direction {
-        //     SwapDirection::IcsToKong => {
-        //         info!(
-        //             "{}: dir=IcsToKong in_icp {:.4} mid_sns({}) {:.4} out_icp {:.4} profit {:.4} ICP",
-        //             self.config.symbol,
-        //             result_abs / 1e8f64,
-        //             mid_token,
-        //             output_a / 1e8f64,
-        //             output_b / 1e8f64,
-        //             kekka / 1e8f64
-        //         );
-        //     }
-        //     SwapDirection::KongToIcs => {
-        //         info!(
-        //             "{}: dir=KongToIcs in_icp {:.4} mid_sns({}) {:.4} out_icp {:.4} profit {:.4} ICP",
-        //             self.config.symbol,
-        //             result_abs / 1e8f64,
-        //             mid_token,
-        //             output_a / 1e8f64,
-        //             output_b / 1e8f64,
-        //             kekka / 1e8f64
-        //         );
-        //     }
-        // }
+        // info!(
+        //     "{}: dir={:?} in_icp {:.4} mid_sns({}) {:.4} out_icp {:.4} profit {:.4} ICP",
+        //     self.config.symbol,
+        //     opportunity.direction,
+        //     opportunity.amount_in / 1e8f64,
+        //     self.config.token_sns,
+        //     opportunity.mid_amount / 1e8f64,
+        //     opportunity.final_amount / 1e8f64,
+        //     opportunity.expected_profit / 1e8f64
+        // );
+
+        // 価格の異常は取引しない tick でも見て、起きたら警告する
+        let anomaly = match &self.price_breaker {
+            Some(breaker) => {
+                let res = breaker.check(now_ms(), refreshed, &kong, &ics, &opportunity);
+                if let Some(alerts) = &self.alerts {
+                    self.alert_price(alerts, &kong, &ics, &res).await;
+                }
+                res.err()
+            }
+            None => None,
+        };
 
-        if kekka > self.profit_threshold_e8 {
+        // 見送る理由を順に確かめ、最初に当たったものだけ残す
+        let profitable = opportunity.expected_profit > self.profit_threshold_e8;
+        let unverified = self.verification_refusal(kong_refresh, ics_refresh);
+        self.note_verification(unverified);
+        let refusal = unverified.filter(|_| profitable);
+        let mut allowed = profitable && refusal.is_none();
+        let anomaly = anomaly.filter(|_| allowed);
+        allowed &= anomaly.is_none();
+        let paused = if allowed {
+            self.control
+                .as_ref()
+                .and_then(|c| c.paused(&self.config.symbol))
+        } else {
+            None
+        };
+        allowed &= paused.is_none();
+        let mut permit = None;
+        let mut breach = None;
+        if let (Some(risk), true) = (&self.risk, allowed) {
+            match risk
+                .acquire(&self.config.symbol, opportunity.amount_in)
+                .await
+            {
+                Ok(p) => permit = Some(p),
+                Err(b) => breach = Some(b),
+            }
+        }
+        let executed = allowed && breach.is_none();
+        if let Some(stats) = &self.stats {
+            stats.record_evaluation(&self.config.symbol, profitable, executed);
+        }
+        let opportunity_id = if profitable || self.record_evaluations {
+            self.journal_opportunity(OpportunityRecord {
+                ts_ms: now_ms(),
+                pair: self.config.symbol.clone(),
+                direction: opportunity.direction.as_str().to_string(),
+                amount_in_e8: opportunity.amount_in,
+                expected_mid_e8: opportunity.mid_amount,
+                expected_final_e8: opportunity.final_amount,
+                expected_profit_e8: opportunity.expected_profit,
+                executed,
+            })
+            .await
+        } else {
+            None
+        };
+
+        // 一時停止と同じく見送るだけで tick の失敗にはしない。理由は note_verification でログに出している
+        if refusal.is_some() {
+            metrics()
+                .trades_refused
+                .with_label_values(&[&self.config.symbol, "unverified"])
+                .inc();
+        }
+        if let Some(anomaly) = anomaly {
+            metrics()
+                .trades_refused
+                .with_label_values(&[&self.config.symbol, anomaly.reason()])
+                .inc();
+            warn!(
+                "{}: 利益見込み {:.4} ICP ですが価格が異常なため取引しません: {}",
+                self.config.symbol,
+                opportunity.expected_profit / 1e8f64,
+                anomaly
+            );
+        }
+        if let Some(pause) = paused {
+            metrics()
+                .trades_refused
+                .with_label_values(&[&self.config.symbol, "paused"])
+                .inc();
+            info!(
+                "{}: 利益見込み {:.4} ICP ですが一時停止中のため取引しません ({})",
+                self.config.symbol,
+                opportunity.expected_profit / 1e8f64,
+                pause.reason
+            );
+        }
+        if let Some(breach) = breach {
+            metrics()
+                .trades_refused
+                .with_label_values(&[&self.config.symbol, breach.reason()])
+                .inc();
+            info!(
+                "{}: 利益見込み {:.4} ICP ですが取引しません: {}",
+                self.config.symbol,
+                opportunity.expected_profit / 1e8f64,
+                breach
+            );
+        }
+        if executed {
             info!(
                 "{}: 利益見込み {:.4} ICP (dir={:?})",
                 self.config.symbol,
-                kekka / 1e8f64,
-                direction
+                opportunity.expected_profit / 1e8f64,
+                opportunity.direction
             );
-            self.execute_swaps(result_abs, output_a, output_b, direction)
+            self.execute_swaps(&opportunity, opportunity_id, permit)
                 .await?;
         }
         // しきい値未達ログ（必要ならコメントを外す）
@@ -183,157 +573,744 @@ impl Trade {
         //     info!(
         //         "{}: 利益しきい値未達 (profit {:.4} ICP, threshold {:.4} ICP)",
         //         self.config.symbol,
-        //         kekka / 1e8f64,
+        //         opportunity.expected_profit / 1e8f64,
         //         self.profit_threshold_e8 / 1e8f64
         //     );
         // }
 
-        Ok(())
+        Ok(refreshed)
     }
 
-    async fn execute_swaps(
+    /// 送信できたレッグの request id をジャーナルに残す（落ちても再起動後に結果を確認できるように）
+    async fn journal_request(
         &self,
-        amount_in: f64,
-        mid_amount: f64,
-        final_amount: f64,
-        direction: SwapDirection,
-    ) -> Result<(), TradeError> {
-        // 期待値から transfer fee を差し引き、min_receive_factor を掛けた最終最小受取を算出
-        fn calc_min(expected_out: f64, out_fee_e8: u128, factor: f64) -> u128 {
-            let after_fee = (expected_out - out_fee_e8 as f64).max(0f64);
-            (after_fee * factor).floor() as u128
+        leg: &str,
+        submitted: &Result<SubmittedUpdate, SwapError>,
+    ) -> Option<i64> {
+        let (Some(journal), Ok(submitted)) = (&self.journal, submitted) else {
+            return None;
+        };
+        let record = SwapRequestRecord {
+            id: 0,
+            swap_id: None,
+            pair: self.config.symbol.clone(),
+            leg: leg.to_string(),
+            canister: submitted.canister.clone(),
+            method: submitted.method.clone(),
+            request_id: submitted.request_id_hex(),
+            submitted_ms: submitted.submitted_ms,
+            status: RequestStatus::Pending.as_str().to_string(),
+            reply: None,
+            error: None,
+            finished_ms: None,
+        };
+        match journal.run(move |j| j.record_request(&record)).await {
+            Ok(id) => Some(id),
+            Err(e) => {
+                warn!(
+                    "{}: ジャーナル記録失敗 (request): {}",
+                    self.config.symbol, e
+                );
+                None
+            }
+        }
+    }
+
+    /// 送信済みレッグの結果を待ち、ジャーナルの状態を確定させる
+    async fn track_leg(
+        &self,
+        submitted: Result<SubmittedUpdate, SwapError>,
+        request_row: Option<i64>,
+    ) -> Result<SwapReply, SwapError> {
+        let submitted = submitted?;
+        let res = finish_swap(&self.swap_client, &submitted).await;
+        if let (Some(journal), Some(id)) = (&self.journal, request_row) {
+            self.finish_request(journal, id, &res).await;
+        }
+        res
+    }
+
+    async fn finish_request(
+        &self,
+        journal: &Arc<Journal>,
+        id: i64,
+        res: &Result<SwapReply, SwapError>,
+    ) {
+        let status = match res {
+            Ok(_) => RequestStatus::Replied,
+            Err(SwapError::Client(IcClientError::Timeout(_))) => RequestStatus::Unknown,
+            Err(_) => RequestStatus::Failed,
+        };
+        let reply = res.as_ref().ok().map(|r| r.decoded.clone());
+        let error = res.as_ref().err().map(|e| e.to_string());
+        let finished = journal
+            .run(move |j| j.finish_request(id, status, reply.as_deref(), error.as_deref()))
+            .await;
+        if let Err(e) = finished {
+            warn!(
+                "{}: ジャーナル記録失敗 (request): {}",
+                self.config.symbol, e
+            );
+        }
+    }
+
+    /// 前回プロセスが結果を確認できなかったレッグを、保存した request id から確認する。
+    /// IC が request status を保持している間だけ有効なので、RECOVERY_WINDOW_MS より古いものは見ない
+    pub async fn recover_unresolved_swaps(&self) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let since = now_ms() - RECOVERY_WINDOW_MS;
+        let pair = self.config.symbol.clone();
+        let rows = match journal
+            .run(move |j| j.unresolved_requests(&pair, since))
+            .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                warn!("{}: 未確定レッグの取得に失敗: {}", self.config.symbol, e);
+                return;
+            }
+        };
+        for row in rows {
+            let submitted = match SubmittedUpdate::restore(
+                &row.canister,
+                &row.method,
+                &row.request_id,
+                row.submitted_ms,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("{}: request id を復元できません: {}", self.config.symbol, e);
+                    continue;
+                }
+            };
+            let res = finish_swap(&self.swap_client, &submitted).await;
+            match &res {
+                Ok(reply) => info!(
+                    "{}: 未確定だった {} レッグ (request {}) の結果: {}",
+                    self.config.symbol, row.leg, row.request_id, reply
+                ),
+                Err(e) => warn!(
+                    "{}: 未確定だった {} レッグ (request {}) の結果: {}",
+                    self.config.symbol, row.leg, row.request_id, e
+                ),
+            }
+            self.finish_request(journal, row.id, &res).await;
         }
+    }
 
+    /// _permit は損益の記録まで持ち、その間は実行中の裁定として数える
+    async fn execute_swaps(
+        &self,
+        opportunity: &Opportunity,
+        opportunity_id: Option<i64>,
+        _permit: Option<RiskPermit<'_>>,
+    ) -> Result<(), TradeError> {
+        let Opportunity {
+            amount_in,
+            mid_amount,
+            final_amount,
+            direction,
+            ..
+        } = *opportunity;
         let amount_in_u = amount_in.round() as u128;
         let sns_fee = self.config.sns_fee_e8;
         let icp_fee = ICP_TRANSFER_FEE_E8;
+        // どちらの方向でも中間は SNS、最終は ICP
+        let min_mid = min_receive(mid_amount, sns_fee, self.min_receive_factor);
+        let min_final = min_receive(final_amount, icp_fee, self.min_receive_factor);
+        // デバッグ用ログ（必要になればコメントを外す）
+        // info!(
+        //     "{}: min calc ({:?}) expected_mid {:.4} expected_final {:.4} min_mid {} min_final {} sns_fee {} icp_fee {}",
+        //     self.config.symbol,
+        //     direction,
+        //     mid_amount / 1e8f64,
+        //     final_amount / 1e8f64,
+        //     min_mid,
+        //     min_final,
+        //     sns_fee,
+        //     icp_fee
+        // );
 
-        match direction {
-            SwapDirection::IcsToKong => {
-                // ICS leg 出力は SNS、Kong leg 出力は ICP
-                let min_mid = calc_min(mid_amount, sns_fee, self.min_receive_factor);
-                let min_final = calc_min(final_amount, icp_fee, self.min_receive_factor);
-                // デバッグ用ログ（必要になればコメントを外す）
-                // info!(
-                //     "{}: min calc (IcsToKong) expected_mid {:.4} expected_final {:.4} min_mid {} min_final {} sns_fee {} icp_fee {}",
-                //     self.config.symbol,
-                //     mid_amount / 1e8f64,
-                //     final_amount / 1e8f64,
-                //     min_mid,
-                //     min_final,
-                //     sns_fee,
-                //     icp_fee
-                // );
-
-                let ics_call = swap_icps_deposit(
-                    &self.client,
-                    &self.config.icpswap_lp,
-                    amount_in_u,
-                    min_mid,
-                    false,
-                    icp_fee,
-                    sns_fee,
-                );
-                let kong_call = swap_kong(
-                    &self.client,
-                    &self.config.kong_canister,
-                    &self.config.token_sns,
-                    &self.config.token_icp,
-                    min_mid,
-                    min_final,
-                );
-                let (ics_res, kong_res) = tokio::join!(ics_call, kong_call);
-                let mut errs = Vec::new();
-                match ics_res {
-                    Ok(val) => info!("{}: swap_icps ok decoded={}", self.config.symbol, val),
-                    Err(e) => errs.push(format!("swap_icps: {}", e)),
-                }
-                match kong_res {
-                    Ok(val) => info!("{}: swap_kong ok decoded={}", self.config.symbol, val),
-                    Err(e) => errs.push(format!("swap_kong: {}", e)),
+        let started_ms = now_ms();
+        // 同じ identity の他の swap が残高の差分に混ざらないか、事前残高を取る前から数える
+        let window = self.identity_swaps.begin();
+        // 実現損益用の事前残高は送信前に取り切る（並べて投げると約定後の残高を読むことがある）
+        let balances_before =
+            if self.journal.is_some() || self.stats.is_some() || self.risk.is_some() {
+                self.fetch_balances().await
+            } else {
+                None
+            };
+        let (kong_res, ics_res, request_ids, (kong_hex, ics_hex)) = async {
+            // 2 レッグとも先に送信だけ済ませ、request id を残してから個別に結果を待つ
+            let (kong_sub, ics_sub) = match direction {
+                SwapDirection::IcsToKong => {
+                    // ICS leg 出力は SNS、Kong leg 出力は ICP
+                    let ics_call = submit_swap_icps_deposit(
+                        &self.swap_client,
+                        &self.config.icpswap_lp,
+                        amount_in_u,
+                        min_mid,
+                        false,
+                        icp_fee,
+                        sns_fee,
+                    );
+                    let kong_call = submit_swap_kong(
+                        &self.swap_client,
+                        &self.config.kong_canister,
+                        &self.config.token_sns,
+                        &self.config.token_icp,
+                        min_mid,
+                        min_final,
+                    );
+                    let (ics_sub, kong_sub) = tokio::join!(ics_call, kong_call);
+                    (kong_sub, ics_sub)
                 }
-                if !errs.is_empty() {
-                    return Err(TradeError::Client(errs.join(" | ")));
+                SwapDirection::KongToIcs => {
+                    // Kong leg 出力は SNS、ICS leg 出力は ICP
+                    let kong_call = submit_swap_kong(
+                        &self.swap_client,
+                        &self.config.kong_canister,
+                        &self.config.token_icp,
+                        &self.config.token_sns,
+                        amount_in_u,
+                        min_mid,
+                    );
+                    let ics_call = submit_swap_icps_deposit(
+                        &self.swap_client,
+                        &self.config.icpswap_lp,
+                        min_mid,
+                        min_final,
+                        true,
+                        // Kong leg 出力は ICP なので out_fee は icp_fee
+                        sns_fee,
+                        icp_fee,
+                    );
+                    tokio::join!(kong_call, ics_call)
                 }
+            };
+            let (kong_req, ics_req) = tokio::join!(
+                self.journal_request("kong", &kong_sub),
+                self.journal_request("ics", &ics_sub)
+            );
+            let request_hex = (
+                kong_sub.as_ref().ok().map(|s| s.request_id_hex()),
+                ics_sub.as_ref().ok().map(|s| s.request_id_hex()),
+            );
+            let (kong_res, ics_res) = tokio::join!(
+                self.track_leg(kong_sub, kong_req),
+                self.track_leg(ics_sub, ics_req)
+            );
+            let request_ids: Vec<i64> = [kong_req, ics_req].into_iter().flatten().collect();
+            (kong_res, ics_res, request_ids, request_hex)
+        }
+        .await;
+        let finished_ms = now_ms();
+
+        let mut errs = Vec::new();
+        match &kong_res {
+            Ok(val) => info!("{}: swap_kong ok decoded={}", self.config.symbol, val),
+            Err(e) => {
+                metrics()
+                    .swap_failures
+                    .with_label_values(&[&self.config.symbol, "kong", e.class()])
+                    .inc();
+                errs.push(format!("swap_kong: {}", e))
             }
-            SwapDirection::KongToIcs => {
-                // Kong leg 出力は SNS、ICS leg 出力は ICP
-                let min_mid = calc_min(mid_amount, sns_fee, self.min_receive_factor);
-                let min_final = calc_min(final_amount, icp_fee, self.min_receive_factor);
-                // デバッグ用ログ（必要になればコメントを外す）
-                // info!(
-                //     "{}: min calc (KongToIcs) expected_mid {:.4} expected_final {:.4} min_mid {} min_final {} sns_fee {} icp_fee {}",
-                //     self.config.symbol,
-                //     mid_amount / 1e8f64,
-                //     final_amount / 1e8f64,
-                //     min_mid,
-                //     _min_final,
-                //     sns_fee,
-                //     icp_fee
-                // );
-
-                let kong_call = swap_kong(
-                    &self.client,
-                    &self.config.kong_canister,
-                    &self.config.token_icp,
-                    &self.config.token_sns,
-                    amount_in_u,
-                    min_mid,
-                );
-                let ics_call = swap_icps_deposit(
-                    &self.client,
-                    &self.config.icpswap_lp,
-                    min_mid,
-                    min_final,
-                    true,
-                    // Kong leg 出力は ICP なので out_fee は icp_fee
-                    sns_fee,
-                    icp_fee,
-                );
-                let (kong_res, ics_res) = tokio::join!(kong_call, ics_call);
-                let mut errs = Vec::new();
-                match kong_res {
-                    Ok(val) => info!("{}: swap_kong ok decoded={}", self.config.symbol, val),
-                    Err(e) => errs.push(format!("swap_kong: {}", e)),
+        }
+        match &ics_res {
+            Ok(val) => info!("{}: swap_icps ok decoded={}", self.config.symbol, val),
+            Err(e) => {
+                metrics()
+                    .swap_failures
+                    .with_label_values(&[&self.config.symbol, "ics", e.class()])
+                    .inc();
+                errs.push(format!("swap_icps: {}", e))
+            }
+        }
+        if errs.is_empty() {
+            metrics()
+                .swaps_executed
+                .with_label_values(&[&self.config.symbol, direction.as_str()])
+                .inc();
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_swap(&self.config.symbol, errs.is_empty());
+        }
+
+        let mut swap_id = None;
+        if let Some(journal) = &self.journal {
+            let record = SwapRecord {
+                id: 0,
+                opportunity_id,
+                pair: self.config.symbol.clone(),
+                direction: direction.as_str().to_string(),
+                amount_in_e8: amount_in_u,
+                expected_mid_e8: mid_amount,
+                expected_final_e8: final_amount,
+                min_mid_e8: min_mid,
+                min_final_e8: min_final,
+                kong_reply: kong_res.as_ref().ok().map(|r| r.decoded.clone()),
+                kong_error: kong_res.as_ref().err().map(|e| e.to_string()),
+                ics_reply: ics_res.as_ref().ok().map(|r| r.decoded.clone()),
+                ics_error: ics_res.as_ref().err().map(|e| e.to_string()),
+                started_ms,
+                finished_ms,
+            };
+            let recorded = journal
+                .run(move |j| {
+                    let id = j.record_swap(&record)?;
+                    Ok((id, j.link_requests(id, &request_ids)))
+                })
+                .await;
+            match recorded {
+                Ok((id, linked)) => {
+                    swap_id = Some(id);
+                    if let Err(e) = linked {
+                        warn!(
+                            "{}: ジャーナル記録失敗 (request): {}",
+                            self.config.symbol, e
+                        );
+                    }
                 }
-                match ics_res {
-                    Ok(val) => info!("{}: swap_icps ok decoded={}", self.config.symbol, val),
-                    Err(e) => errs.push(format!("swap_icps: {}", e)),
+                Err(e) => warn!("{}: ジャーナル記録失敗 (swap): {}", self.config.symbol, e),
+            }
+        }
+
+        // 片側でも約定していれば残高は動いているので損益を記録する
+        let realized_pnl_e8 = match balances_before {
+            Some(before) if kong_res.is_ok() || ics_res.is_ok() => {
+                // ICS レッグに払う中間は、ICS→Kong なら投入額、Kong→ICS なら最低受取の SNS
+                let ics_paid = match direction {
+                    SwapDirection::IcsToKong => amount_in_u,
+                    SwapDirection::KongToIcs => min_mid,
+                };
+                self.record_realized_pnl(
+                    opportunity,
+                    swap_id,
+                    before,
+                    &window,
+                    [&kong_res, &ics_res],
+                    ics_paid,
+                )
+                .await
+            }
+            _ => None,
+        };
+        if let (Some(risk), Some(pnl)) = (&self.risk, realized_pnl_e8) {
+            risk.record_pnl(&self.config.symbol, pnl).await;
+        }
+
+        self.notify_swap(
+            opportunity,
+            [("kong", &kong_res, kong_hex), ("ics", &ics_res, ics_hex)],
+            realized_pnl_e8,
+        )
+        .await;
+
+        if !errs.is_empty() {
+            return Err(TradeError::Client(errs.join(" | ")));
+        }
+        Ok(())
+    }
+
+    /// swap の結果を通知する。片側でも失敗していればエラーとして送る。
+    /// alerts があれば失敗は ALERT_SWAP_FAILURES 回続いたときだけ送り、次に成功したら復旧を送る
+    async fn notify_swap(
+        &self,
+        opportunity: &Opportunity,
+        legs: [(&str, &Result<SwapReply, SwapError>, Option<String>); 2],
+        realized_pnl_e8: Option<f64>,
+    ) {
+        if self.notifier.is_none() && self.alerts.is_none() {
+            return;
+        }
+        let failed = legs.iter().filter(|(_, res, _)| res.is_err()).count();
+        let (event, severity, title) = match failed {
+            0 => ("swap_executed", Severity::Info, "swap 実行"),
+            1 => ("swap_partial", Severity::Error, "swap 片側のみ約定"),
+            _ => ("swap_failed", Severity::Error, "swap 失敗"),
+        };
+        let dir_text = match opportunity.direction {
+            SwapDirection::IcsToKong => "ics→kong",
+            SwapDirection::KongToIcs => "kong→ics",
+        };
+        let mut notification = Notification::new(event, severity, title)
+            .with_pair(&self.config.symbol)
+            .with_field("方向", dir_text)
+            .with_field("in", format!("{:.4} ICP", opportunity.amount_in / 1e8f64))
+            .with_field(
+                "見込み out",
+                format!("{:.4} ICP", opportunity.final_amount / 1e8f64),
+            )
+            .with_field(
+                "見込み利益",
+                format!("{:+.4} ICP", opportunity.expected_profit / 1e8f64),
+            );
+        if let Some(pnl) = realized_pnl_e8 {
+            notification = notification.with_field("実現損益", format!("{:+.4} ICP", pnl / 1e8f64));
+        }
+        for (leg, res, request_id) in legs {
+            let status = match res {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            };
+            notification = notification.with_field(&format!("{} レッグ", leg), status);
+            if let Some(id) = request_id {
+                notification = notification.with_field(&format!("{} request id", leg), id);
+            }
+        }
+        let key = format!("swap_failed|{}", self.config.symbol);
+        match &self.alerts {
+            Some(alerts) if failed > 0 && alerts.config().swap_failures > 0 => {
+                alerts
+                    .failing(&key, alerts.config().swap_failures, |n| {
+                        notification.with_field("連続失敗", n.to_string())
+                    })
+                    .await;
+                return;
+            }
+            Some(alerts) => {
+                alerts
+                    .healthy(&key, || {
+                        Notification::new(
+                            "swap_failed_recovered",
+                            Severity::Recovered,
+                            "swap が再び両レッグとも成功しました",
+                        )
+                        .with_pair(&self.config.symbol)
+                    })
+                    .await;
+            }
+            None => {}
+        }
+        if let Some(notifier) = &self.notifier {
+            if let Err(e) = notifier.send(&notification).await {
+                warn!("通知失敗: {}", e);
+            }
+        }
+    }
+
+    /// 取引に使う ICP / SNS 残高を取得し、残高メトリクスも更新する
+    pub async fn fetch_balances(&self) -> Option<BalanceSnapshot> {
+        let owner = match self.client.principal() {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("{}: principal 取得失敗: {}", self.config.symbol, e);
+                return None;
+            }
+        };
+        let (icp, sns) = tokio::join!(
+            fetch_balance(&self.client, ICP_LEDGER_RAW, owner),
+            fetch_balance(&self.client, &self.config.token_sns, owner)
+        );
+        match (icp, sns) {
+            (Ok(icp_e8), Ok(sns_e8)) => {
+                let m = metrics();
+                m.wallet_balance_e8
+                    .with_label_values(&[&self.config.identity, "ICP"])
+                    .set(icp_e8 as f64);
+                m.wallet_balance_e8
+                    .with_label_values(&[&self.config.identity, self.sns_label()])
+                    .set(sns_e8 as f64);
+                if let Some(alerts) = &self.alerts {
+                    alerts
+                        .check_balance(&self.config.identity, "ICP", icp_e8)
+                        .await;
+                    alerts
+                        .check_balance(&self.config.identity, self.sns_label(), sns_e8)
+                        .await;
                 }
-                if !errs.is_empty() {
-                    return Err(TradeError::Client(errs.join(" | ")));
+                if let Some(risk) = &self.risk {
+                    risk.record_inventory(&self.config.symbol, self.sns_label(), sns_e8)
+                        .await;
                 }
+                Some(BalanceSnapshot { icp_e8, sns_e8 })
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("{}: 残高取得失敗: {}", self.config.symbol, e);
+                None
             }
         }
+    }
 
-        if let Some(notifier) = &self.notifier {
-            let dir_text = match direction {
-                SwapDirection::IcsToKong => "ics→kong",
-                SwapDirection::KongToIcs => "kong→ics",
-            };
-            let message = format!(
-                "{} が {} で swap 実行。in {:.4} / out {:.4}",
-                self.config.symbol,
-                dir_text,
-                amount_in / 1e8f64,
-                final_amount / 1e8f64
+    /// swap で transfer_from される 4 通り（ICP / SNS → Kong / ICPSwap）の allowance
+    pub async fn fetch_allowances(&self) -> Vec<(String, Option<u128>)> {
+        let owner = match self.client.principal() {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("{}: principal 取得失敗: {}", self.config.symbol, e);
+                return Vec::new();
+            }
+        };
+        let sns = self.sns_label();
+        let routes = [
+            (ICP_LEDGER_RAW, "ICP", &self.config.kong_canister, "kong"),
+            (ICP_LEDGER_RAW, "ICP", &self.config.icpswap_lp, "icpswap"),
+            (
+                &self.config.token_sns,
+                sns,
+                &self.config.kong_canister,
+                "kong",
+            ),
+            (
+                &self.config.token_sns,
+                sns,
+                &self.config.icpswap_lp,
+                "icpswap",
+            ),
+        ];
+        let results =
+            futures::future::join_all(routes.iter().map(|(ledger, _, spender, _)| {
+                fetch_allowance(&self.client, ledger, owner, spender)
+            }))
+            .await;
+        routes
+            .iter()
+            .zip(results)
+            .map(|((_, token, _, spender), res)| {
+                let amount = match res {
+                    Ok(a) => Some(a),
+                    Err(e) => {
+                        warn!(
+                            "{}: allowance 取得失敗 ({}→{}): {}",
+                            self.config.symbol, token, spender, e
+                        );
+                        None
+                    }
+                };
+                (format!("{}→{}", token, spender), amount)
+            })
+            .collect()
+    }
+
+    /// 同じ identity の他の swap と重なったときと、約定量が確定しないレッグがあるときは記録しない
+    async fn record_realized_pnl(
+        &self,
+        opportunity: &Opportunity,
+        swap_id: Option<i64>,
+        before: BalanceSnapshot,
+        window: &SwapWindow<'_>,
+        [kong_res, ics_res]: [&Result<SwapReply, SwapError>; 2],
+        ics_paid_e8: u128,
+    ) -> Option<f64> {
+        // Kong の swap_async は非同期に約定するので、反映を待ってから約定量と残高を取り直す
+        sleep(Duration::from_millis(self.pnl_settle_ms)).await;
+        let fills = self
+            .swap_fills(opportunity.direction, kong_res, ics_res, ics_paid_e8)
+            .await;
+        let after = self.fetch_balances().await?;
+        if !window.exclusive() {
+            warn!(
+                "{}: 同じ identity で他の swap が並行していたため実現損益を記録しません",
+                self.config.symbol
+            );
+            return None;
+        }
+        let Some(fills) = fills else {
+            warn!(
+                "{}: 約定量が確定していないレッグがあるため実現損益を記録しません",
+                self.config.symbol
             );
-            if let Err(e) = notifier.notify(&message).await {
-                warn!("LINE 通知失敗: {}", e);
+            return None;
+        };
+        let record = realized_pnl(&PnlInput {
+            pair: self.config.symbol.clone(),
+            direction: opportunity.direction.as_str().to_string(),
+            swap_id,
+            before,
+            after,
+            sns_price_icp: opportunity.sns_price_icp,
+            expected_profit_e8: opportunity.expected_profit,
+            fills,
+        });
+        info!(
+            "{}: 実現損益 {:+.4} ICP (見込み {:+.4}, ICP {:+} / SNS {:+})",
+            self.config.symbol,
+            record.realized_pnl_e8 / 1e8f64,
+            record.expected_profit_e8 / 1e8f64,
+            record.icp_delta_e8,
+            record.sns_delta_e8
+        );
+        if let Some(journal) = &self.journal {
+            let row = record.clone();
+            if let Err(e) = journal.run(move |j| j.record_pnl(&row)).await {
+                warn!("{}: ジャーナル記録失敗 (pnl): {}", self.config.symbol, e);
             }
         }
-        Ok(())
+        if let Some(stats) = &self.stats {
+            stats.record_pnl(&self.config.symbol, record.realized_pnl_e8, record.fees_e8);
+        }
+        Some(record.realized_pnl_e8)
     }
+
+    /// 各レッグの約定量。Kong は requests、ICS は swap の応答から読む。
+    /// 送ったかどうか分からない失敗や、まだ処理中の request があれば None
+    async fn swap_fills(
+        &self,
+        direction: SwapDirection,
+        kong_res: &Result<SwapReply, SwapError>,
+        ics_res: &Result<SwapReply, SwapError>,
+        ics_paid_e8: u128,
+    ) -> Option<SwapFills> {
+        let kong = match kong_res {
+            Ok(reply) => {
+                let request_id = reply.amount? as u64;
+                match fetch_swap_fill(&self.client, &self.config.kong_canister, request_id).await {
+                    Ok(Some(fill)) => fill
+                        .succeeded
+                        .then_some((fill.pay_amount, fill.receive_amount)),
+                    Ok(None) => {
+                        info!(
+                            "{}: Kong の request {} はまだ処理中です",
+                            self.config.symbol, request_id
+                        );
+                        return None;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "{}: Kong の約定量を取得できません: {}",
+                            self.config.symbol, e
+                        );
+                        return None;
+                    }
+                }
+            }
+            Err(SwapError::Client(_)) => return None,
+            // canister が断った・送っていない swap は何も動かしていない
+            Err(_) => None,
+        };
+        let ics = match ics_res {
+            Ok(reply) => Some((ics_paid_e8, reply.amount?)),
+            Err(SwapError::Client(_)) => return None,
+            Err(_) => None,
+        };
+        // どちらの方向でも 1 本目は ICP → SNS、2 本目は SNS → ICP
+        let (first, second) = match direction {
+            SwapDirection::IcsToKong => (ics, kong),
+            SwapDirection::KongToIcs => (kong, ics),
+        };
+        let (icp_paid_e8, sns_received_e8) = first.unwrap_or_default();
+        let (sns_paid_e8, icp_received_e8) = second.unwrap_or_default();
+        Some(SwapFills {
+            icp_paid_e8,
+            icp_received_e8,
+            sns_paid_e8,
+            sns_received_e8,
+            kong_fill_e8: kong.map(|(_, received)| received),
+            ics_fill_e8: ics.map(|(_, received)| received),
+        })
+    }
+
+    async fn journal_opportunity(&self, record: OpportunityRecord) -> Option<i64> {
+        let journal = self.journal.as_ref()?;
+        match journal.run(move |j| j.record_opportunity(&record)).await {
+            Ok(id) => Some(id),
+            Err(e) => {
+                warn!(
+                    "{}: ジャーナル記録失敗 (opportunity): {}",
+                    self.config.symbol, e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// 1 回の評価で得られた裁定機会（金額はすべて e8）
+#[derive(Debug, Clone, Copy)]
+pub struct Opportunity {
+    pub amount_in: f64,
+    pub mid_amount: f64,
+    pub final_amount: f64,
+    pub expected_profit: f64,
+    pub direction: SwapDirection,
+    /// SNS 1 e8 あたりの ICP e8（中間トークン在庫の評価用）
+    pub sns_price_icp: f64,
 }
 
+/// evaluate に渡すペア固有の判定パラメータ
 #[derive(Debug, Clone, Copy)]
-enum SwapDirection {
+pub struct DecisionParams {
+    pub fee_rate: f64,
+    pub ikiti_e8: u128,
+    pub sns_fee_e8: u128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapDirection {
     IcsToKong,
     KongToIcs,
 }
 
+impl SwapDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwapDirection::IcsToKong => "ics_to_kong",
+            SwapDirection::KongToIcs => "kong_to_ics",
+        }
+    }
+}
+
 // --- 計算ロジック ---
 
+/// 両プールのスナップショットから最適投入量・方向・期待出力を求める。
+/// 実行するかどうか（しきい値判定）は呼び出し側で行う
+pub fn evaluate(
+    kong: &KongPoolSnapshot,
+    ics: &IcsPoolSnapshot,
+    params: &DecisionParams,
+) -> Opportunity {
+    let ikiti = params.ikiti_e8 as f64;
+
+    let result = cal_amount(
+        kong.icp_balance,
+        kong.sns_balance,
+        ics.token0_k,
+        ics.token1_k,
+    );
+    let mut result_abs = result.abs();
+    if result_abs > ikiti {
+        result_abs = ikiti;
+    }
+
+    let fee = params.fee_rate;
+    let (kekka, direction, output_a, output_b) = if result < 0f64 {
+        let output_icpswap = swap_icp_to_ckusdc(result_abs, ics.token1_k, ics.token0_k, fee);
+        let output_kong = kong_quote_const_prod(
+            output_icpswap,
+            kong,
+            KongDirection::SnsToIcp,
+            ICP_TRANSFER_FEE_E8,
+        );
+        let delta = output_kong - result_abs;
+        (delta, SwapDirection::IcsToKong, output_icpswap, output_kong)
+    } else {
+        let output_kong =
+            kong_quote_const_prod(result_abs, kong, KongDirection::IcpToSns, params.sns_fee_e8);
+        let output_icpswap = swap_icp_to_ckusdc(output_kong, ics.token0_k, ics.token1_k, fee);
+        let delta = output_icpswap - result_abs;
+        (delta, SwapDirection::KongToIcs, output_kong, output_icpswap)
+    };
+
+    Opportunity {
+        amount_in: result_abs,
+        mid_amount: output_a,
+        final_amount: output_b,
+        expected_profit: kekka,
+        direction,
+        sns_price_icp: kong_sns_price_icp(kong),
+    }
+}
+
+/// 期待値から transfer fee を差し引き、min_receive_factor を掛けた最終最小受取を算出
+pub fn min_receive(expected_out: f64, out_fee_e8: u128, factor: f64) -> u128 {
+    let after_fee = (expected_out - out_fee_e8 as f64).max(0f64);
+    (after_fee * factor).floor() as u128
+}
+
 pub fn swap_icp_to_ckusdc(amount: f64, token0: f64, token1: f64, fee_rate: f64) -> f64 {
     // Uniswap v2 形式: out = (amount_in*(1-fee) * reserve_out) / (reserve_in + amount_in*(1-fee))
     let amount_in_after_fee = amount * (1f64 - fee_rate);
@@ -341,13 +1318,24 @@ pub fn swap_icp_to_ckusdc(amount: f64, token0: f64, token1: f64, fee_rate: f64)
 }
 
 #[derive(Debug, Clone, Copy)]
-enum KongDirection {
+pub enum KongDirection {
     IcpToSns,
     SnsToIcp,
 }
 
+/// Kong のリザーブ比から SNS 1 e8 あたりの ICP e8 を出す（桁数の違うトークンでもそのまま使える）
+pub fn kong_sns_price_icp(pool: &KongPoolSnapshot) -> f64 {
+    let r_sns = pool.sns_raw.saturating_add(pool.sns_lp_raw);
+    let r_icp = pool.icp_raw.saturating_add(pool.icp_lp_raw);
+    if r_sns == 0 {
+        0f64
+    } else {
+        r_icp as f64 / r_sns as f64
+    }
+}
+
 /// Kong の独自計算: LP fee を含めた定数積を整数で計算し、出力トークンの transfer fee を控除
-fn kong_quote_const_prod(
+pub fn kong_quote_const_prod(
     amount_in: f64,
     pool: &KongPoolSnapshot,
     dir: KongDirection,
@@ -365,20 +1353,16 @@ fn kong_quote_const_prod(
     let mut out = match dir {
         KongDirection::IcpToSns => {
             let r1_new = r_icp.saturating_add(amount_eff);
-            if r1_new == 0 {
-                0
-            } else {
-                let r0_new = k / r1_new;
-                r_sns.saturating_sub(r0_new)
+            match k.checked_div(r1_new) {
+                Some(r0_new) => r_sns.saturating_sub(r0_new),
+                None => 0,
             }
         }
         KongDirection::SnsToIcp => {
             let r0_new = r_sns.saturating_add(amount_eff);
-            if r0_new == 0 {
-                0
-            } else {
-                let r1_new = k / r0_new;
-                r_icp.saturating_sub(r1_new)
+            match k.checked_div(r0_new) {
+                Some(r1_new) => r_icp.saturating_sub(r1_new),
+                None => 0,
             }
         }
     };
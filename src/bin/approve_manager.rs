@@ -6,11 +6,14 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-use candid::{Encode, IDLArgs, Principal};
-use kong_ics::config::AppConfig;
+use kong_ics::alert::AlertManager;
+use kong_ics::config::{AppConfig, ApproveTokenConfig, IdentityProfile, DEFAULT_IDENTITY};
+use kong_ics::health::HealthState;
+use kong_ics::http::{serve, Router};
 use kong_ics::ic_client::agent::IcClient;
-use kong_ics::identity::load_identity;
-use kong_ics::notify::DiscordNotifier;
+use kong_ics::ic_client::ledger::{approve_from, fetch_allowance, Account};
+use kong_ics::identity::{load_identity, IdentityMode, PassphraseSource};
+use kong_ics::notify::{self, Notification, Severity};
 use tokio::time::sleep;
 use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
@@ -20,112 +23,245 @@ async fn main() {
     dotenvy::dotenv().ok();
     init_tracing();
     let cfg = AppConfig::load_default();
+    if let Err(e) = cfg.identity.validate() {
+        error!("{}", e);
+        return;
+    }
 
-    let identity = match load_identity(Path::new(&cfg.identity.pem_path)) {
-        Ok(id) => id,
-        Err(e) => {
-            error!("Identity 読み込みに失敗: {}", e);
-            return;
-        }
+    // identity ごとに、その identity に割り当てたペアのトークンの allowance を張る。
+    // swap は既定 subaccount から引き落とすので、kong_ics と同じく既定以外の subaccount は起動時に止める
+    let passphrase = PassphraseSource::from_config(&cfg.identity);
+    let owner_of = |token: &ApproveTokenConfig| {
+        cfg.pairs
+            .iter()
+            .find(|p| p.token_sns == token.sns)
+            .map(|p| (p.symbol.clone(), p.identity.clone()))
+            .unwrap_or_else(|| (token.name.clone(), DEFAULT_IDENTITY.to_string()))
     };
-
-    let identity_for_client = identity.clone();
-    let client = match IcClient::new(
-        &cfg.network.api_url,
-        identity_for_client,
-        cfg.network.fetch_root_key,
-    )
-    .await
+    let mut profiles: Vec<&IdentityProfile> = Vec::new();
+    for (label, name) in cfg
+        .pairs
+        .iter()
+        .map(|p| (p.symbol.clone(), p.identity.clone()))
+        .chain(cfg.approve.tokens.iter().map(owner_of))
     {
-        Ok(c) => Arc::new(c),
-        Err(e) => {
-            error!("IcClient 初期化失敗: {}", e);
-            return;
+        match cfg.identity.trading_profile_named(&label, &name) {
+            Ok(p) if !profiles.iter().any(|q| q.name == p.name) => profiles.push(p),
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
         }
-    };
-
-    let my_principal = match identity.sender() {
-        Ok(p) => p,
-        Err(e) => {
-            error!("Identity から principal を取得できません: {}", e);
-            return;
+    }
+    if profiles.is_empty() {
+        profiles.extend(cfg.identity.profile(DEFAULT_IDENTITY));
+    }
+    let mut wallets: Vec<Wallet> = Vec::new();
+    for profile in profiles {
+        match connect(&cfg, profile, &passphrase).await {
+            Ok(mut w) => {
+                w.tokens = cfg
+                    .approve
+                    .tokens
+                    .iter()
+                    .filter(|t| owner_of(t).1 == profile.name)
+                    .cloned()
+                    .collect();
+                wallets.push(w);
+            }
+            Err(e) => {
+                error!("{}: {}", profile.name, e);
+                return;
+            }
         }
-    };
+    }
 
-    let notifier = std::env::var(&cfg.discord.env_key)
-        .ok()
-        .map(DiscordNotifier::new);
+    let alerts = AlertManager::new(notify::start(&cfg.notify), cfg.alert.clone());
 
-    loop {
-        for token in &cfg.approve.tokens {
-            // SNS → Kong
-            check_and_approve(
-                &client,
-                &token.sns,
-                &cfg.approve.kong_canister,
-                &my_principal,
-                token.sns_threshold_e8,
-                &token.name,
-                "kong",
-                notifier.as_ref(),
-            )
-            .await;
+    // approve サイクルは interval_secs ごとなので、それより短い stale 判定にはしない
+    let stale_secs = cfg
+        .http
+        .health_stale_secs
+        .max(cfg.approve.interval_secs * 2);
+    let principals = wallets
+        .iter()
+        .map(|w| format!("{}={}", w.name, w.account.owner.to_text()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let health = Arc::new(HealthState::new(
+        principals,
+        wallets
+            .iter()
+            .map(|w| (w.name.clone(), (*w.client).clone()))
+            .collect(),
+        stale_secs,
+    ));
+    health.register("approve");
+    if !cfg.http.approve_listen_addr.is_empty() {
+        let router = health.routes(Router::new());
+        let addr = cfg.http.approve_listen_addr.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(&addr, router).await {
+                error!("{}", e);
+            }
+        });
+    }
 
-            // SNS → icpswap
-            check_and_approve(
-                &client,
-                &token.sns,
-                &token.icpswap,
-                &my_principal,
-                token.sns_threshold_e8,
-                &token.name,
-                "icpswap",
-                notifier.as_ref(),
-            )
-            .await;
+    loop {
+        let mut failures = 0usize;
+        for wallet in &wallets {
+            failures += approve_wallet(&cfg, wallet, &alerts).await;
+        }
 
-            // ICP → icpswap
-            check_and_approve(
-                &client,
-                &cfg.approve.icp_canister,
-                &token.icpswap,
-                &my_principal,
-                cfg.approve.icp_amount_e8,
-                "icp",
-                &token.name,
-                notifier.as_ref(),
-            )
-            .await;
+        if failures == 0 {
+            health.ok("approve");
+        } else {
+            health.fail(
+                "approve",
+                &format!("{} 件の allowance 確認/approve に失敗", failures),
+            );
         }
 
-        // ICP -> Kong も事前承認しておく
-        check_and_approve(
-            &client,
-            &cfg.approve.icp_canister,
+        sleep(Duration::from_secs(cfg.approve.interval_secs)).await;
+    }
+}
+
+/// 署名する identity と、allowance を張る元の口座
+struct Wallet {
+    name: String,
+    client: Arc<IcClient>,
+    account: Account,
+    tokens: Vec<ApproveTokenConfig>,
+}
+
+async fn connect(
+    cfg: &AppConfig,
+    profile: &IdentityProfile,
+    passphrase: &PassphraseSource,
+) -> Result<Wallet, String> {
+    let identity = load_identity(
+        Path::new(&profile.pem_path),
+        passphrase,
+        IdentityMode::Signing,
+    )
+    .map_err(|e| format!("Identity 読み込みに失敗: {}", e))?;
+    let owner = identity
+        .sender()
+        .map_err(|e| format!("Identity から principal を取得できません: {}", e))?;
+    info!(
+        "identity {}: principal {} で approve します",
+        profile.name, owner
+    );
+    let client = IcClient::new(
+        &cfg.network.api_urls,
+        identity,
+        cfg.network.fetch_root_key,
+        cfg.network.verify_query_signatures,
+    )
+    .await
+    .map_err(|e| format!("IcClient 初期化失敗: {}", e))?;
+    Ok(Wallet {
+        name: profile.name.clone(),
+        client: Arc::new(
+            client
+                .with_retry(cfg.network.query_retry, cfg.network.poll_retry)
+                .with_timeouts(cfg.network.timeouts),
+        ),
+        account: Account::new(owner, profile.subaccount.clone()),
+        tokens: Vec::new(),
+    })
+}
+
+/// 1 つの identity 分の allowance を確認・補充し、失敗した件数を返す
+async fn approve_wallet(cfg: &AppConfig, wallet: &Wallet, alerts: &AlertManager) -> usize {
+    let client = &wallet.client;
+    let owner = &wallet.account;
+    let mut failures = 0usize;
+    for token in &wallet.tokens {
+        // SNS → Kong
+        failures += !check_and_approve(
+            client,
+            &token.sns,
             &cfg.approve.kong_canister,
-            &my_principal,
-            cfg.approve.icp_amount_e8,
-            "icp",
+            owner,
+            token.sns_threshold_e8,
+            &token.name,
             "kong",
-            notifier.as_ref(),
+            alerts,
         )
-        .await;
+        .await as usize;
 
-        sleep(Duration::from_secs(cfg.approve.interval_secs)).await;
+        // SNS → icpswap
+        failures += !check_and_approve(
+            client,
+            &token.sns,
+            &token.icpswap,
+            owner,
+            token.sns_threshold_e8,
+            &token.name,
+            "icpswap",
+            alerts,
+        )
+        .await as usize;
+
+        // ICP → icpswap
+        failures += !check_and_approve(
+            client,
+            &cfg.approve.icp_canister,
+            &token.icpswap,
+            owner,
+            cfg.approve.icp_amount_e8,
+            "icp",
+            &token.name,
+            alerts,
+        )
+        .await as usize;
     }
+
+    // ICP -> Kong も事前承認しておく
+    failures += !check_and_approve(
+        client,
+        &cfg.approve.icp_canister,
+        &cfg.approve.kong_canister,
+        owner,
+        cfg.approve.icp_amount_e8,
+        "icp",
+        "kong",
+        alerts,
+    )
+    .await as usize;
+    failures
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn check_and_approve(
     client: &IcClient,
     token_canister: &str,
     spender_canister: &str,
-    owner: &Principal,
+    owner: &Account,
     target_allowance: u128,
     token_label: &str,
     spender_label: &str,
-    notifier: Option<&DiscordNotifier>,
-) {
-    match query_allowance(client, token_canister, owner, spender_canister).await {
+    alerts: &AlertManager,
+) -> bool {
+    // 同じ口座・token・spender の approve が続けて失敗したら警告する
+    let key = format!(
+        "approve_failed|{}|{}|{}",
+        owner.to_text(),
+        token_label,
+        spender_label
+    );
+    let recovered = || {
+        Notification::new(
+            "approve_failed_recovered",
+            Severity::Recovered,
+            "approve が成功しました",
+        )
+        .with_field("token", token_label)
+        .with_field("spender", spender_label)
+    };
+    match fetch_allowance(client, token_canister, owner.clone(), spender_canister).await {
         Ok(current) => {
             info!(
                 "token:{} -> to:{} | allowance:{} target:{}",
@@ -136,139 +272,71 @@ async fn check_and_approve(
                     "token:{} -> to:{} | approve send: {}",
                     token_label, spender_label, target_allowance
                 );
-                match send_approve(client, token_canister, spender_canister, target_allowance).await
+                match approve_from(
+                    client,
+                    token_canister,
+                    owner.subaccount.clone(),
+                    spender_canister,
+                    target_allowance,
+                )
+                .await
                 {
-                    Ok(()) => {
-                        if let Some(n) = notifier {
-                            let _ = n
-                                .notify(&format!(
-                                    "approve sent: token:{} -> to:{} amount:{}",
-                                    token_label, spender_label, target_allowance
-                                ))
-                                .await;
-                        }
+                    Ok(decoded) => {
+                        info!(
+                            "approve response ({} -> {}): decoded={}",
+                            token_canister, spender_canister, decoded
+                        );
+                        let n = Notification::new("approve_sent", Severity::Info, "approve 送信")
+                            .with_dedup_key(format!(
+                                "approve_sent|{}|{}",
+                                token_label, spender_label
+                            ))
+                            .with_field("token", token_label)
+                            .with_field("spender", spender_label)
+                            .with_field("amount", target_allowance.to_string())
+                            .with_field("allowance (前)", current.to_string());
+                        alerts.send(&n).await;
+                        alerts.healthy(&key, recovered).await;
+                        true
                     }
                     Err(e) => {
                         warn!(
                             "token:{} -> to:{} | approve failed: {}",
                             token_label, spender_label, e
                         );
+                        let n =
+                            Notification::new("approve_failed", Severity::Warning, "approve 失敗")
+                                .with_dedup_key(key.clone())
+                                .with_field("token", token_label)
+                                .with_field("spender", spender_label)
+                                .with_field("amount", target_allowance.to_string())
+                                .with_field("error", e.to_string());
+                        let threshold = alerts.config().approve_failures;
+                        if threshold == 0 {
+                            alerts.send(&n).await;
+                        } else {
+                            alerts
+                                .failing(&key, threshold, |count| {
+                                    n.with_field("連続失敗", count.to_string())
+                                })
+                                .await;
+                        }
+                        false
                     }
                 }
+            } else {
+                alerts.healthy(&key, recovered).await;
+                true
             }
         }
-        Err(e) => warn!(
-            "token:{} -> to:{} | allowance err: {}",
-            token_label, spender_label, e
-        ),
-    }
-}
-
-async fn query_allowance(
-    client: &IcClient,
-    token_canister: &str,
-    owner: &Principal,
-    spender_canister: &str,
-) -> Result<u128, String> {
-    #[derive(candid::CandidType)]
-    struct Account {
-        owner: Principal,
-        subaccount: Option<Vec<u8>>,
-    }
-    #[derive(candid::CandidType)]
-    struct AllowanceReq {
-        account: Account,
-        spender: Account,
-    }
-    let args = Encode!(&AllowanceReq {
-        account: Account {
-            owner: owner.clone(),
-            subaccount: None,
-        },
-        spender: Account {
-            owner: Principal::from_text(spender_canister)
-                .map_err(|e| format!("spender principal parse: {}", e))?,
-            subaccount: None,
-        },
-    })
-    .map_err(|e| e.to_string())?;
-
-    let raw = client
-        .query_raw(token_canister, "icrc2_allowance", args)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let decoded = candid::IDLArgs::from_bytes(&raw).map_err(|e| e.to_string())?;
-    let first = decoded
-        .args
-        .first()
-        .ok_or_else(|| "missing allowance result".to_string())?;
-    if let candid::types::value::IDLValue::Record(fields) = first {
-        for field in fields {
-            // 3230440920 が残高フィールド
-            if field.id == candid::types::Label::Id(3_230_440_920u32) {
-                if let candid::types::value::IDLValue::Nat(n) = &field.val {
-                    let v = n.0.to_string().parse::<u128>().map_err(|e| e.to_string())?;
-                    return Ok(v);
-                }
-            }
+        Err(e) => {
+            warn!(
+                "token:{} -> to:{} | allowance err: {}",
+                token_label, spender_label, e
+            );
+            false
         }
     }
-    Err("allowance field not found".to_string())
-}
-
-async fn send_approve(
-    client: &IcClient,
-    token_canister: &str,
-    spender_canister: &str,
-    amount: u128,
-) -> Result<(), String> {
-    #[derive(candid::CandidType)]
-    struct Spender {
-        owner: Principal,
-        subaccount: Option<Vec<u8>>,
-    }
-    #[derive(candid::CandidType)]
-    struct ApproveReq {
-        fee: Option<u128>,
-        memo: Option<Vec<u8>>,
-        from_subaccount: Option<Vec<u8>>,
-        created_at_time: Option<u64>,
-        amount: u128,
-        spender: Spender,
-    }
-    let now_nanos = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|e| e.to_string())?
-        .as_nanos() as u64;
-
-    let args = Encode!(&ApproveReq {
-        fee: None,
-        memo: None,
-        from_subaccount: None,
-        created_at_time: Some(now_nanos),
-        amount,
-        spender: Spender {
-            owner: Principal::from_text(spender_canister)
-                .map_err(|e| format!("spender principal parse: {}", e))?,
-            subaccount: None,
-        },
-    })
-    .map_err(|e| e.to_string())?;
-
-    let raw = client
-        .update_raw(token_canister, "icrc2_approve", args)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let decoded = IDLArgs::from_bytes(&raw)
-        .map(|v| v.to_string())
-        .unwrap_or_else(|e| format!("decode err: {}", e));
-    info!(
-        "approve response ({} -> {}): decoded={} raw_bytes={:?}",
-        token_canister, spender_canister, decoded, raw
-    );
-    Ok(())
 }
 
 fn init_tracing() {
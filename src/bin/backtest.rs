@@ -0,0 +1,115 @@
+// どこで: 記録済みスナップショットを再生する backtest バイナリ
+// 何を: 指定パラメータで機会判定・約定シミュレーションを行い、ペアごとの結果を表示する
+// なぜ: 判定ロジックやしきい値の変更を実資金を使わずに比較するため
+
+use std::error::Error;
+use std::path::Path;
+
+use kong_ics::arb::DecisionParams;
+use kong_ics::backtest::{run, BacktestParams};
+use kong_ics::config::AppConfig;
+use kong_ics::journal::format_ms;
+use kong_ics::recorder::load_records;
+
+const USAGE: &str = "usage: backtest [--dir DIR] [--pair SYMBOL] [--latency-ms N] [--fee-rate F] \
+[--min-receive-factor F] [--profit-threshold-e8 F] [--ikiti-e8 N] [--verbose]";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenvy::dotenv().ok();
+    let cfg = AppConfig::load_default();
+
+    let mut dir = cfg.recorder.dir.clone();
+    let mut pair_filter: Option<String> = None;
+    let mut latency_ms: i64 = 2_000;
+    let mut fee_rate = cfg.trade.fee_rate;
+    let mut min_receive_factor = cfg.trade.min_receive_factor;
+    let mut profit_threshold_e8 = cfg.trade.profit_threshold_e8;
+    let mut ikiti_override: Option<u128> = None;
+    let mut verbose = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "--verbose" {
+            verbose = true;
+            continue;
+        }
+        let Some(value) = args.next() else {
+            eprintln!("{} に値がありません\n{}", flag, USAGE);
+            std::process::exit(2);
+        };
+        match flag.as_str() {
+            "--dir" => dir = value,
+            "--pair" => pair_filter = Some(value),
+            "--latency-ms" => latency_ms = value.parse()?,
+            "--fee-rate" => fee_rate = value.parse()?,
+            "--min-receive-factor" => min_receive_factor = value.parse()?,
+            "--profit-threshold-e8" => profit_threshold_e8 = value.parse()?,
+            "--ikiti-e8" => ikiti_override = Some(value.parse()?),
+            _ => {
+                eprintln!("不明なオプション: {}\n{}", flag, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    println!(
+        "dir={} latency_ms={} fee_rate={} min_receive_factor={} profit_threshold={:.4} ICP",
+        dir,
+        latency_ms,
+        fee_rate,
+        min_receive_factor,
+        profit_threshold_e8 / 1e8f64
+    );
+
+    for pair in &cfg.pairs {
+        if pair_filter.as_deref().is_some_and(|p| p != pair.symbol) {
+            continue;
+        }
+        let records = load_records(Path::new(&dir), &pair.symbol)?;
+        if records.is_empty() {
+            println!("{}: スナップショットなし", pair.symbol);
+            continue;
+        }
+        let params = BacktestParams {
+            decision: DecisionParams {
+                fee_rate,
+                ikiti_e8: ikiti_override.unwrap_or(pair.ikiti_e8),
+                sns_fee_e8: pair.sns_fee_e8,
+            },
+            profit_threshold_e8,
+            min_receive_factor,
+            latency_ms,
+        };
+        let report = run(&records, &params);
+
+        println!(
+            "{}: {} 〜 {} snapshots {} | opportunities {} fills {} partial {} rejects {} | expected {:+.4} ICP realized {:+.4} ICP",
+            pair.symbol,
+            format_ms(records[0].ts_ms),
+            format_ms(records[records.len() - 1].ts_ms),
+            report.snapshots,
+            report.opportunities,
+            report.fills,
+            report.partial_fills,
+            report.rejects,
+            report.expected_profit_e8 / 1e8f64,
+            report.realized_pnl_e8 / 1e8f64
+        );
+        if verbose {
+            for t in &report.trades {
+                println!(
+                    "  {} dir={:?} in {:.4} expected {:+.4} legs {}/{} realized {:+.4} ICP (fill {})",
+                    format_ms(t.ts_ms),
+                    t.opportunity.direction,
+                    t.opportunity.amount_in / 1e8f64,
+                    t.opportunity.expected_profit / 1e8f64,
+                    if t.first_leg_ok { "ok" } else { "ng" },
+                    if t.second_leg_ok { "ok" } else { "ng" },
+                    t.realized_pnl_e8 / 1e8f64,
+                    format_ms(t.fill_ts_ms)
+                );
+            }
+        }
+    }
+    Ok(())
+}
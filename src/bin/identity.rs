@@ -0,0 +1,200 @@
+// どこで: 署名鍵の管理用 CLI
+// 何を: load_identity が読める PEM の生成・書き出し、principal と ICP account id の表示、読み込み確認を行う
+// なぜ: dfx 無しで鍵を用意し、入金先や読み込めるかをサーバ上で確かめられるようにするため
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use candid::Principal;
+use kong_ics::config::{parse_subaccount, AppConfig};
+use kong_ics::ic_client::ledger::Account;
+use kong_ics::identity::{
+    encode_private_key, generate_private_key, load_identity, load_private_key, IdentityMode,
+    KeyKind, PassphraseSource,
+};
+use zeroize::Zeroizing;
+
+const USAGE: &str = "usage: identity <generate|show|verify|export> [--profile NAME] [--pem PATH] [--out PATH] [--kind ed25519|secp256k1] [--encrypt] [--subaccount N]... [--principal TEXT]";
+
+/// 書き出す鍵を暗号化するときのパスフレーズ（読み込み側の IDENTITY_PASSPHRASE とは分ける）
+const NEW_PASSPHRASE_ENV: &str = "IDENTITY_NEW_PASSPHRASE";
+
+struct CliArgs {
+    command: String,
+    profile: Option<String>,
+    pem: Option<String>,
+    out: Option<String>,
+    kind: KeyKind,
+    encrypt: bool,
+    subaccounts: Vec<String>,
+    principal: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenvy::dotenv().ok();
+    let cfg = AppConfig::load_default();
+    cfg.identity.validate()?;
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // --pem が無ければ --profile（既定 default）の鍵と subaccount を使う
+    let profile = match &args.profile {
+        Some(name) => Some(
+            cfg.identity
+                .profile(name)
+                .ok_or_else(|| format!("identity {} が IDENTITY_PROFILES にありません", name))?,
+        ),
+        None if args.pem.is_none() => cfg.identity.profiles.first(),
+        None => None,
+    };
+    let pem = args
+        .pem
+        .clone()
+        .or_else(|| profile.map(|p| p.pem_path.clone()))
+        .unwrap_or_default();
+    let mut subaccounts: Vec<Vec<u8>> = profile
+        .and_then(|p| p.subaccount.clone())
+        .into_iter()
+        .collect();
+    for text in &args.subaccounts {
+        subaccounts.push(
+            parse_subaccount(text).ok_or_else(|| format!("subaccount が不正です: {}", text))?,
+        );
+    }
+    let passphrase = PassphraseSource::from_config(&cfg.identity);
+
+    match args.command.as_str() {
+        "generate" => {
+            let out = args.out.as_deref().ok_or("--out を指定してください")?;
+            let key = generate_private_key(args.kind)?;
+            let new_pass = new_passphrase(args.encrypt, out)?;
+            let pem = encode_private_key(&key, new_pass.as_deref().map(|p| p.as_str()))?;
+            write_private(Path::new(out), pem.as_bytes())?;
+            println!("{} を作成しました ({:?})", out, args.kind);
+            // 書いたファイルを読み直して確認する
+            let reader = match &new_pass {
+                Some(p) => PassphraseSource::with_passphrase(p),
+                None => PassphraseSource::new("", None, false),
+            };
+            let owner = load_identity(Path::new(out), &reader, IdentityMode::Signing)?.sender()?;
+            print_accounts(owner, &subaccounts);
+        }
+        "show" => {
+            let owner = sender(&pem, &passphrase)?;
+            print_accounts(owner, &subaccounts);
+        }
+        "verify" => {
+            let owner = sender(&pem, &passphrase)?;
+            println!("{} を読み込みました: {}", pem, owner.to_text());
+            if let Some(expected) = &args.principal {
+                if owner.to_text() != *expected {
+                    eprintln!("principal が一致しません (期待値 {})", expected);
+                    std::process::exit(1);
+                }
+                println!("principal は期待値と一致します");
+            }
+        }
+        "export" => {
+            let out = args.out.as_deref().ok_or("--out を指定してください")?;
+            if pem.is_empty() {
+                return Err("書き出す鍵がありません (--pem か --profile を指定)".into());
+            }
+            let key = load_private_key(Path::new(&pem), &passphrase)?;
+            let new_pass = new_passphrase(args.encrypt, out)?;
+            let encoded = encode_private_key(&key, new_pass.as_deref().map(|p| p.as_str()))?;
+            write_private(Path::new(out), encoded.as_bytes())?;
+            println!(
+                "{} を {} に書き出しました ({})",
+                pem,
+                out,
+                if args.encrypt { "暗号化" } else { "平文" }
+            );
+        }
+        other => {
+            eprintln!("不明なコマンド: {}\n{}", other, USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn sender(pem: &str, passphrase: &PassphraseSource) -> Result<Principal, Box<dyn Error>> {
+    Ok(load_identity(Path::new(pem), passphrase, IdentityMode::Signing)?.sender()?)
+}
+
+fn new_passphrase(encrypt: bool, out: &str) -> Result<Option<Zeroizing<String>>, Box<dyn Error>> {
+    if !encrypt {
+        return Ok(None);
+    }
+    let source = PassphraseSource::new(NEW_PASSPHRASE_ENV, None, true);
+    Ok(Some(source.new_passphrase(out)?))
+}
+
+fn print_accounts(owner: Principal, subaccounts: &[Vec<u8>]) {
+    let default = Account::from(owner);
+    println!("principal:  {}", owner.to_text());
+    println!("account id: {}", default.account_identifier());
+    for sub in subaccounts {
+        let account = Account::new(owner, Some(sub.clone()));
+        println!("--- subaccount {}", account.to_text());
+        println!("account id: {}", account.account_identifier());
+    }
+}
+
+/// 既存ファイルは上書きせず、所有者だけが読める権限で作る
+fn write_private(path: &Path, body: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("{} を作成できません: {}", path.display(), e))?;
+    file.write_all(body)?;
+    Ok(())
+}
+
+fn parse_args(mut it: impl Iterator<Item = String>) -> Result<CliArgs, String> {
+    let command = it
+        .next()
+        .ok_or_else(|| "コマンドを指定してください".to_string())?;
+    let mut args = CliArgs {
+        command,
+        profile: None,
+        pem: None,
+        out: None,
+        kind: KeyKind::Ed25519,
+        encrypt: false,
+        subaccounts: Vec::new(),
+        principal: None,
+    };
+    while let Some(flag) = it.next() {
+        if flag == "--encrypt" {
+            args.encrypt = true;
+            continue;
+        }
+        let value = it
+            .next()
+            .ok_or_else(|| format!("{} に値がありません", flag))?;
+        match flag.as_str() {
+            "--profile" => args.profile = Some(value),
+            "--pem" => args.pem = Some(value),
+            "--out" => args.out = Some(value),
+            "--kind" => {
+                args.kind =
+                    KeyKind::parse(&value).ok_or_else(|| format!("不明な鍵の種類: {}", value))?
+            }
+            "--subaccount" => args.subaccounts.push(value),
+            "--principal" => args.principal = Some(value),
+            _ => return Err(format!("不明なオプション: {}", flag)),
+        }
+    }
+    Ok(args)
+}
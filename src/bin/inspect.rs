@@ -5,7 +5,7 @@
 use candid::{types::value::IDLValue, Encode, IDLArgs};
 use kong_ics::config::AppConfig;
 use kong_ics::ic_client::agent::IcClient;
-use kong_ics::identity::load_identity;
+use kong_ics::identity::{load_identity, IdentityMode, PassphraseSource};
 use std::error::Error;
 use std::path::Path;
 use tracing_subscriber::EnvFilter;
@@ -16,13 +16,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let cfg = AppConfig::load_default();
     let pair = cfg.pairs.first().expect("少なくとも1ペア必要です (config)");
 
-    let identity = load_identity(Path::new(&cfg.identity.pem_path))?;
+    let identity = load_identity(
+        Path::new(&cfg.identity.pem_path),
+        &PassphraseSource::from_config(&cfg.identity),
+        IdentityMode::ReadOnly,
+    )?;
+    println!("principal: {}", identity.sender()?);
     let client = IcClient::new(
-        &cfg.network.api_url,
+        &cfg.network.api_urls,
         identity.clone(),
         cfg.network.fetch_root_key,
+        cfg.network.verify_query_signatures,
     )
-    .await?;
+    .await?
+    .with_retry(cfg.network.query_retry, cfg.network.poll_retry)
+    .with_timeouts(cfg.network.timeouts);
 
     // ICS metadata
     let meta_args = candid::Encode!()?;
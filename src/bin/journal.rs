@@ -0,0 +1,119 @@
+// どこで: 取引ジャーナル参照用 CLI
+// 何を: SQLite に記録した swap 試行と実現損益をペア・期間で絞り込んで表示する
+// なぜ: 約定履歴をログを漁らずに確認できるようにするため
+
+use std::error::Error;
+use std::path::Path;
+
+use kong_ics::config::AppConfig;
+use kong_ics::journal::{format_ms, Journal, TradeFilter};
+use kong_ics::pnl::format_summary;
+
+const USAGE: &str =
+    "usage: journal <trades|pnl> [--pair SYMBOL] [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--db PATH]";
+
+struct CliArgs {
+    command: String,
+    pair: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    db: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenvy::dotenv().ok();
+    let cfg = AppConfig::load_default();
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let db_path = args.db.clone().unwrap_or(cfg.journal.db_path);
+    let journal = Journal::open(Path::new(&db_path))?;
+    let filter = TradeFilter::from_dates(args.pair, args.from.as_deref(), args.to.as_deref())?;
+
+    match args.command.as_str() {
+        "trades" => print_trades(&journal, &filter)?,
+        "pnl" => {
+            let rows = journal.pnl_summary(&filter)?;
+            println!("{}", format_summary("実現損益 (ペア/日, UTC)", &rows));
+        }
+        other => {
+            eprintln!("不明なコマンド: {}\n{}", other, USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn print_trades(journal: &Journal, filter: &TradeFilter) -> Result<(), Box<dyn Error>> {
+    let swaps = journal.swaps(filter)?;
+    println!(
+        "{:<23} {:<10} {:<12} {:>12} {:>12} {:>12} {:>12} {:>12}  status",
+        "started (UTC)", "pair", "direction", "in", "exp_mid", "min_mid", "exp_final", "min_final"
+    );
+    for s in &swaps {
+        let status = if s.succeeded() {
+            "ok".to_string()
+        } else {
+            let errs: Vec<String> = [("kong", &s.kong_error), ("ics", &s.ics_error)]
+                .iter()
+                .filter_map(|(leg, e)| e.as_ref().map(|e| format!("{}: {}", leg, e)))
+                .collect();
+            format!("err ({})", errs.join(" | "))
+        };
+        println!(
+            "{:<23} {:<10} {:<12} {:>12.4} {:>12.4} {:>12.4} {:>12.4} {:>12.4}  {}",
+            format_ms(s.started_ms),
+            s.pair,
+            s.direction,
+            s.amount_in_e8 as f64 / 1e8f64,
+            s.expected_mid_e8 / 1e8f64,
+            s.min_mid_e8 as f64 / 1e8f64,
+            s.expected_final_e8 / 1e8f64,
+            s.min_final_e8 as f64 / 1e8f64,
+            status
+        );
+    }
+
+    let (evaluated, executed) = journal.opportunity_counts(filter)?;
+    let ok = swaps.iter().filter(|s| s.succeeded()).count();
+    println!(
+        "--- swaps {} (ok {} / err {}) | opportunities evaluated {} / executed {}",
+        swaps.len(),
+        ok,
+        swaps.len() - ok,
+        evaluated,
+        executed
+    );
+    Ok(())
+}
+
+fn parse_args(mut it: impl Iterator<Item = String>) -> Result<CliArgs, String> {
+    let command = it
+        .next()
+        .ok_or_else(|| "コマンドを指定してください".to_string())?;
+    let mut args = CliArgs {
+        command,
+        pair: None,
+        from: None,
+        to: None,
+        db: None,
+    };
+    while let Some(flag) = it.next() {
+        let value = it
+            .next()
+            .ok_or_else(|| format!("{} に値がありません", flag))?;
+        match flag.as_str() {
+            "--pair" => args.pair = Some(value),
+            "--from" => args.from = Some(value),
+            "--to" => args.to = Some(value),
+            "--db" => args.db = Some(value),
+            _ => return Err(format!("不明なオプション: {}", flag)),
+        }
+    }
+    Ok(args)
+}
@@ -0,0 +1,143 @@
+// どこで: kong_ics の取引判定の直後
+// 何を: Kong の価格・ICS の sqrtPriceX96 から求めた価格・直近の中央値を比べ、会場間の乖離・急な価格変化・大きすぎる見込みエッジを検出する
+// なぜ: 片方のプールが操作されたりデコードを誤ったりすると、cal_amount が大きなエッジを出して ikiti_e8 をそのまま投入してしまうため
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+use crate::arb::Opportunity;
+use crate::config::BreakerConfig;
+use crate::ic_client::ics::IcsPoolSnapshot;
+use crate::ic_client::kong::KongPoolSnapshot;
+
+/// 中央値との比較を始めるのに必要なサンプル数
+const MIN_SAMPLES: usize = 5;
+
+/// 取引を止める価格の異常（価格は SNS 1 あたりの ICP）
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum PriceAnomaly {
+    #[error("価格が不正です (kong {kong}, ics {ics})")]
+    Invalid { kong: f64, ics: f64 },
+    #[error("Kong と ICS の価格が {bps:.0} bps 乖離しています（上限 {limit:.0} bps）")]
+    Divergence { bps: f64, limit: f64 },
+    #[error("{venue} の価格 {price:.8} が直近の中央値 {median:.8} から {bps:.0} bps 動きました（上限 {limit:.0} bps）")]
+    Jump {
+        venue: &'static str,
+        price: f64,
+        median: f64,
+        bps: f64,
+        limit: f64,
+    },
+    #[error("見込みエッジ {bps:.0} bps が上限 {limit:.0} bps を超えています")]
+    Edge { bps: f64, limit: f64 },
+}
+
+impl PriceAnomaly {
+    /// trades_refused_total の reason
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PriceAnomaly::Invalid { .. } => "price_invalid",
+            PriceAnomaly::Divergence { .. } => "price_divergence",
+            PriceAnomaly::Jump { .. } => "price_jump",
+            PriceAnomaly::Edge { .. } => "price_edge",
+        }
+    }
+}
+
+/// ペアごとの価格の見張り。直近 median_window_secs 秒の (取得時刻 ms, Kong, ICS) の価格を持つ
+pub struct PriceBreaker {
+    config: BreakerConfig,
+    history: Mutex<VecDeque<(i64, f64, f64)>>,
+}
+
+impl PriceBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        PriceBreaker {
+            config,
+            history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 1 tick 分を確かめる。refreshed（今回スナップショットを取り直した）なら、異常があっても価格を履歴に入れる
+    /// （本当に価格が動いたなら、窓の半分を超えた時点で中央値が追いつく）。
+    /// 取り直せずキャッシュを使った tick は同じ価格を何度も入れて中央値を偏らせないように入れない
+    pub fn check(
+        &self,
+        now_ms: i64,
+        refreshed: bool,
+        kong: &KongPoolSnapshot,
+        ics: &IcsPoolSnapshot,
+        opportunity: &Opportunity,
+    ) -> Result<(), PriceAnomaly> {
+        let (k, i) = (kong.price_icp_per_sns, ics.price_icp_per_sns);
+        if !(k.is_finite() && k > 0f64 && i.is_finite() && i > 0f64) {
+            return Err(PriceAnomaly::Invalid { kong: k, ics: i });
+        }
+        let medians = {
+            let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+            let window_ms = self.config.median_window_secs as i64 * 1_000;
+            while history
+                .front()
+                .is_some_and(|(at, _, _)| *at <= now_ms - window_ms)
+            {
+                history.pop_front();
+            }
+            let medians = (history.len() >= MIN_SAMPLES).then(|| {
+                (
+                    median(history.iter().map(|(_, k, _)| *k)),
+                    median(history.iter().map(|(_, _, i)| *i)),
+                )
+            });
+            if refreshed && window_ms > 0 {
+                history.push_back((now_ms, k, i));
+            }
+            medians
+        };
+
+        let limit = self.config.max_divergence_bps;
+        let bps = (k - i).abs() / ((k + i) / 2f64) * 10_000f64;
+        if limit > 0f64 && bps > limit {
+            return Err(PriceAnomaly::Divergence { bps, limit });
+        }
+        let limit = self.config.max_jump_bps;
+        if let (true, Some((kong_median, ics_median))) = (limit > 0f64, medians) {
+            for (venue, price, median) in [("Kong", k, kong_median), ("ICS", i, ics_median)] {
+                let bps = (price - median).abs() / median * 10_000f64;
+                if bps > limit {
+                    return Err(PriceAnomaly::Jump {
+                        venue,
+                        price,
+                        median,
+                        bps,
+                        limit,
+                    });
+                }
+            }
+        }
+        let limit = self.config.max_edge_bps;
+        if limit > 0f64 && opportunity.amount_in > 0f64 {
+            let bps = opportunity.expected_profit / opportunity.amount_in * 10_000f64;
+            if bps > limit {
+                return Err(PriceAnomaly::Edge { bps, limit });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 中央値（偶数個なら中央 2 つの平均）。空なら 0
+pub fn median(values: impl Iterator<Item = f64>) -> f64 {
+    let mut values: Vec<f64> = values.collect();
+    if values.is_empty() {
+        return 0f64;
+    }
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    if n % 2 == 1 {
+        values[n / 2]
+    } else {
+        (values[n / 2 - 1] + values[n / 2]) / 2f64
+    }
+}
@@ -17,24 +17,417 @@ fn e8(amount: f64) -> u128 {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
-    pub api_url: String,
+    /// API エンドポイント（boundary node）。速くて健全なものを選び、障害時は切り替える
+    pub api_urls: Vec<String>,
+    /// エンドポイントの状態をログに出す間隔 (秒)。0 なら出さない
+    pub endpoint_log_secs: u64,
     pub fetch_root_key: bool,
+    /// query 応答のノード署名を検証する。取引判定に使うプール情報を 1 台の replica の応答だけで信じないため
+    pub verify_query_signatures: bool,
+    /// query の再試行（一時的な通信エラー・タイムアウト・SysTransient のみ）
+    pub query_retry: RetryPolicy,
+    /// update 送信後の request_status ポーリングの再試行。送信そのものは二重実行を避けるため再試行しない
+    pub poll_retry: RetryPolicy,
+    pub timeouts: CallTimeouts,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CallTimeouts {
+    /// query 1 回あたりの上限 (ms)
+    pub query_timeout_ms: u64,
+    /// update の送信から結果取得までの上限 (ms)
+    pub update_timeout_ms: u64,
+    /// update の ingress expiry (秒)。これを過ぎた message はサブネットに受理されない
+    pub ingress_expiry_secs: u64,
+}
+
+impl Default for CallTimeouts {
+    fn default() -> Self {
+        CallTimeouts {
+            query_timeout_ms: 10_000,
+            update_timeout_ms: 60_000,
+            ingress_expiry_secs: 240,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// 初回を含む試行回数（1 なら再試行しない）
+    pub max_attempts: u32,
+    /// 再試行間隔の初期値。試行ごとに倍にし max_delay_ms で頭打ち
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    /// attempt 回目（1 始まり）の失敗後に待つ時間
+    pub fn delay_ms(&self, attempt: u32) -> u64 {
+        let exp = attempt.saturating_sub(1).min(16);
+        self.base_delay_ms
+            .saturating_mul(1u64 << exp)
+            .min(self.max_delay_ms)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 200,
+            max_delay_ms: 2_000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdentityConfig {
+    /// PEM ファイル。暗号化 PKCS#8 PEM や dfx の identity ディレクトリ / identity.pem.encrypted も可
+    pub pem_path: String,
+    /// 暗号化鍵のパスフレーズを読む環境変数名
+    pub passphrase_env_key: String,
+    /// パスフレーズを読む file descriptor（親プロセスから渡すパイプなど）
+    pub passphrase_fd: Option<i32>,
+    /// 上記で得られず端末から起動されたときはプロンプトで尋ねる
+    pub passphrase_prompt: bool,
+    /// 使い分ける identity。先頭は pem_path の "default"
+    pub profiles: Vec<IdentityProfile>,
+    /// IDENTITY_PROFILES / IDENTITY_SUBACCOUNT / PAIR_IDENTITIES を読めなかった理由。鍵を使うバイナリは validate で起動時に止める
+    pub invalid: Option<String>,
+}
+
+/// ペアに割り当てる署名鍵と ICRC subaccount の組
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityProfile {
+    pub name: String,
     pub pem_path: String,
+    /// 32 バイト。None は既定 subaccount
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl IdentityConfig {
+    /// 書き間違えた subaccount を黙って既定 subaccount として扱わないように、読めなかった指定があればエラーにする
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.invalid {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&IdentityProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// ペアに割り当てた identity を返す。
+    /// Kong / ICPSwap は呼び出し元の既定 subaccount から transfer_from するので、取引ペアには既定以外の subaccount を使えない
+    pub fn trading_profile(&self, pair: &PairConfig) -> Result<&IdentityProfile, String> {
+        self.trading_profile_named(&pair.symbol, &pair.identity)
+    }
+
+    /// trading_profile と同じ確認を名前で行う。ペアを持たない approve 対象のトークンなど、label はエラーに出す呼び出し元
+    pub fn trading_profile_named(
+        &self,
+        label: &str,
+        name: &str,
+    ) -> Result<&IdentityProfile, String> {
+        let profile = self.profile(name).ok_or_else(|| {
+            format!(
+                "{}: identity {} が IDENTITY_PROFILES にありません",
+                label, name
+            )
+        })?;
+        if profile
+            .subaccount
+            .as_ref()
+            .is_some_and(|s| s.iter().any(|b| *b != 0))
+        {
+            return Err(format!(
+                "{}: identity {} は既定以外の subaccount を使っています。Kong / ICPSwap は既定 subaccount から引き落とすため取引に使えません",
+                label, name
+            ));
+        }
+        Ok(profile)
+    }
+}
+
+pub const DEFAULT_IDENTITY: &str = "default";
+
+/// subaccount 指定を 32 バイトにする。64 桁までの 16 進数か、10 進数（ビッグエンディアンで末尾に詰める）
+pub fn parse_subaccount(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if text.len() <= 20 && text.bytes().all(|b| b.is_ascii_digit()) {
+        let n = text.parse::<u64>().ok()?;
+        let mut sub = vec![0u8; 32];
+        sub[24..].copy_from_slice(&n.to_be_bytes());
+        return Some(sub);
+    }
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    if hex.len() > 64 || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let mut sub = vec![0u8; 32 - bytes.len()];
+    sub.extend(bytes);
+    Some(sub)
+}
+
+/// "name=path[@subaccount]" をカンマ区切りで並べた指定を読む。読めない項目があればその項目を示してエラー
+pub fn parse_profiles(text: &str) -> Result<Vec<IdentityProfile>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, rest) = entry
+                .split_once('=')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "IDENTITY_PROFILES の {} は 名前=パス[@subaccount] の形ではありません",
+                        entry
+                    )
+                })?;
+            let (pem_path, subaccount) = match rest.rsplit_once('@') {
+                Some((path, sub)) => {
+                    let sub = parse_subaccount(sub).ok_or_else(|| {
+                        format!(
+                            "IDENTITY_PROFILES の {} の subaccount が不正です（64 桁までの 16 進数か 10 進数）",
+                            entry
+                        )
+                    })?;
+                    (path, Some(sub))
+                }
+                None => (rest, None),
+            };
+            Ok(IdentityProfile {
+                name: name.trim().to_string(),
+                pem_path: pem_path.trim().to_string(),
+                subaccount,
+            })
+        })
+        .collect()
+}
+
+/// "BOB_ICP=alice" をカンマ区切りで並べた PAIR_IDENTITIES を (ペア, identity 名) にする。
+/// 形の崩れた項目を黙って落とすとそのペアが default で取引するので、その項目を示してエラー
+pub fn parse_pair_identities(text: &str) -> Result<Vec<(String, String)>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .map(|(pair, name)| (pair.trim(), name.trim()))
+                .filter(|(pair, name)| !pair.is_empty() && !name.is_empty())
+                .map(|(pair, name)| (pair.to_string(), name.to_string()))
+                .ok_or_else(|| {
+                    format!(
+                        "PAIR_IDENTITIES の {} は ペア=identity 名 の形ではありません",
+                        entry
+                    )
+                })
+        })
+        .collect()
+}
+
+/// 通知の送り先 1 つ分
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NotifierConfig {
+    Discord {
+        webhook_url: String,
+    },
+    /// Slack の Incoming Webhook
+    Slack {
+        webhook_url: String,
+    },
+    /// Telegram Bot API。api_base は既定で https://api.telegram.org
+    Telegram {
+        bot_token: String,
+        chat_id: String,
+        api_base: String,
+    },
+    /// {"source", "text"} を受け取る任意の HTTP 受け口
+    Webhook {
+        url: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LineNotifyConfig {
-    pub token: Option<String>,
+pub struct NotifyConfig {
+    /// 設定された順に全てへ送る（空なら通知しない）
+    pub backends: Vec<NotifierConfig>,
+    /// 空でなければ警告・エラーは backends ではなくこちらへ送る
+    pub alert_backends: Vec<NotifierConfig>,
+    /// 同じキーの通知をまとめる窓（秒）。0 なら全て送る
+    pub dedup_window_secs: u64,
+    /// 送信待ちキューの長さ。溢れた通知は捨てる
+    pub queue_size: usize,
+}
+
+/// 各サービスの環境変数から送り先を組み立てる。値が空のものは使わない
+fn notify_backends() -> Vec<NotifierConfig> {
+    let non_empty = |key: &str| env::var(key).ok().filter(|v| !v.trim().is_empty());
+    let mut backends = Vec::new();
+    // Discord は URL を入れる変数名自体を DISCORD_WEBHOOK_ENV_KEY で変えられる
+    let discord_env_key = env::var("DISCORD_WEBHOOK_ENV_KEY")
+        .ok()
+        .unwrap_or_else(|| "DISCORD_WEBHOOK_URL".to_string());
+    if let Some(webhook_url) = non_empty(&discord_env_key) {
+        backends.push(NotifierConfig::Discord { webhook_url });
+    }
+    if let Some(webhook_url) = non_empty("SLACK_WEBHOOK_URL") {
+        backends.push(NotifierConfig::Slack { webhook_url });
+    }
+    if let (Some(bot_token), Some(chat_id)) = (
+        non_empty("TELEGRAM_BOT_TOKEN"),
+        non_empty("TELEGRAM_CHAT_ID"),
+    ) {
+        let api_base = non_empty("TELEGRAM_API_BASE")
+            .unwrap_or_else(|| "https://api.telegram.org".to_string());
+        backends.push(NotifierConfig::Telegram {
+            bot_token,
+            chat_id,
+            api_base,
+        });
+    }
+    if let Some(urls) = non_empty("NOTIFY_WEBHOOK_URLS") {
+        backends.extend(
+            urls.split(',')
+                .map(|u| u.trim())
+                .filter(|u| !u.is_empty())
+                .map(|u| NotifierConfig::Webhook { url: u.to_string() }),
+        );
+    }
+    backends
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DiscordWebhookConfig {
-    /// .env に定義するキー名（例: DISCORD_WEBHOOK_URL）
-    pub env_key: String,
+pub struct JournalConfig {
+    /// SQLite ファイルのパス（空文字なら記録しない）
+    pub db_path: String,
+    /// 実行しなかった評価も opportunities に残すか
+    pub record_evaluations: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecorderConfig {
+    /// tick ごとのプールスナップショットを保存するか
+    pub enabled: bool,
+    /// 保存先ディレクトリ（backtest の既定入力にもなる）
+    pub dir: String,
+}
+
+/// 異常検知のしきい値。回数・秒数が 0 のものは無効
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertConfig {
+    /// ペアの tick が何回続けて失敗したら警告するか
+    pub tick_failures: u32,
+    /// swap が何回続けて片側以上失敗したら警告するか
+    pub swap_failures: u32,
+    /// 同じ token→spender の approve が何回続けて失敗したら警告するか
+    pub approve_failures: u32,
+    /// ペアのスナップショットがこの秒数更新されなければ警告する
+    pub snapshot_stale_secs: u64,
+    /// トークン名ごとの残高下限 (e8)。載っていないトークンは見ない
+    pub min_balances_e8: Vec<(String, u128)>,
+}
+
+impl AlertConfig {
+    pub fn min_balance_e8(&self, token: &str) -> Option<u128> {
+        self.min_balances_e8
+            .iter()
+            .find(|(t, _)| t == token)
+            .map(|(_, min)| *min)
+    }
+}
+
+/// "名前=量" をカンマ区切りで並べた指定を (名前, 量 e8) にする。名前の意味は環境変数ごとに違う
+/// - ALERT_MIN_BALANCES: トークン名（ICP か、ペア名の "_" より前の BOB など）。例 "ICP=10,BOB=5000"
+/// - RISK_MAX_INVENTORY: ペア名で、量はそのペアの SNS トークン。例 "BOB_ICP=50000"
+///
+/// 量はトークン単位で書き、小数 8 桁として e8 に直す。扱うトークン（ICP と TokenDefinition の SNS）はどれも 8 桁なので、
+/// 桁数の違うトークンを足すときはここで桁数を引けるようにすること
+fn parse_amounts(text: &str) -> Vec<(String, u128)> {
+    text.split(',')
+        .filter_map(|entry| {
+            let (token, amount) = entry.split_once('=')?;
+            let amount = amount.trim().parse::<f64>().ok()?;
+            Some((token.trim().to_string(), (amount * 1e8).round() as u128))
+        })
+        .filter(|(token, _)| !token.is_empty())
+        .collect()
+}
+
+/// 価格の異常で取引を止める上限 (bps)。0 のものは無効
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakerConfig {
+    /// Kong と ICS の価格の乖離
+    pub max_divergence_bps: f64,
+    /// 各会場の価格と直近の中央値の差
+    pub max_jump_bps: f64,
+    /// 見込み利益 / 投入額
+    pub max_edge_bps: f64,
+    /// 中央値を取る期間（秒）。スナップショットを取り直せた tick の価格だけを入れる
+    pub median_window_secs: u64,
+}
+
+/// リスク上限。0（空）のものは無効
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskConfig {
+    /// UTC の 1 日の実現損失がこれに達したら全ペアを止める (e8)
+    pub max_daily_loss_e8: f64,
+    /// 直近 1 時間の投入額の合計（全ペア）の上限 (e8)
+    pub max_notional_per_hour_e8: f64,
+    /// 同時に実行中の裁定（全ペア）の上限
+    pub max_in_flight: usize,
+    /// ペアごとの中間トークン残高の上限 (e8)。超えたらそのペアを止める
+    pub max_inventory_e8: Vec<(String, u128)>,
+}
+
+impl RiskConfig {
+    pub fn max_inventory_e8(&self, pair: &str) -> Option<u128> {
+        self.max_inventory_e8
+            .iter()
+            .find(|(p, _)| p == pair)
+            .map(|(_, max)| *max)
+    }
+}
+
+/// 取引の一時停止
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlConfig {
+    /// このファイルがある間は取引しない（空文字なら見ない）
+    pub pause_file: String,
+    /// 停止ファイルを見直す間隔
+    pub poll_ms: u64,
+    /// 管理用 /control を出す listen アドレス（空文字なら起動しない）
+    pub listen_addr: String,
+    /// /control に要求する Bearer トークン（空文字なら認証なし。ループバック以外で listen するなら必須）
+    pub auth_token: String,
+}
+
+/// 定期レポート（通知先が無ければ送らない）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportConfig {
+    /// UTC の正時ごとに直前 1 時間分を送る
+    pub hourly: bool,
+    /// UTC 0 時に前日分を送る
+    pub daily: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// kong_ics の /metrics・/healthz・/readyz を出す listen アドレス（空文字なら起動しない）
+    pub listen_addr: String,
+    /// approve_manager の /healthz・/readyz を出す listen アドレス（空文字なら起動しない）
+    pub approve_listen_addr: String,
+    /// 最終成功からこの秒数を超えたら unhealthy とする（approve_manager は interval の 2 倍と比べて大きい方）
+    pub health_stale_secs: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +464,8 @@ pub struct PairConfig {
     pub symbol: String,
     pub ikiti_e8: u128,
     pub sns_fee_e8: u128,
+    /// 署名に使う IdentityProfile の名前
+    pub identity: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,14 +478,31 @@ pub struct TradeParams {
     pub profit_threshold_e8: f64,
     /// ループ間隔 (ms)
     pub loop_interval_ms: u64,
+    /// swap 後に実現損益を測るまでの待ち時間 (ms)
+    pub pnl_settle_ms: u64,
+    /// 残高メトリクスの更新間隔 (秒)
+    pub balance_refresh_secs: u64,
+    /// swap レッグの ingress expiry (秒)。遅れて受理された古い裁定を約定させないため短くする
+    pub swap_ingress_expiry_secs: u64,
+    /// swap レッグの結果待ちの上限 (ms)
+    pub swap_timeout_ms: u64,
+    /// 署名検証済みの query 応答だけで取引する（検証が無効・失敗した tick では swap しない）
+    pub require_verified_queries: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub network: NetworkConfig,
     pub identity: IdentityConfig,
-    pub line: LineNotifyConfig,
-    pub discord: DiscordWebhookConfig,
+    pub notify: NotifyConfig,
+    pub journal: JournalConfig,
+    pub recorder: RecorderConfig,
+    pub report: ReportConfig,
+    pub alert: AlertConfig,
+    pub control: ControlConfig,
+    pub risk: RiskConfig,
+    pub breaker: BreakerConfig,
+    pub http: HttpConfig,
     pub tokens: Vec<TokenDefinition>,
     pub trade: TradeParams,
     pub approve: ApproveConfig,
@@ -116,9 +528,226 @@ impl AppConfig {
             .ok()
             .and_then(|v| v.parse::<u64>().ok())
             .unwrap_or(200);
-        let discord_env_key = env::var("DISCORD_WEBHOOK_ENV_KEY")
+        let pnl_settle_ms = env::var("TRADE_PNL_SETTLE_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(4_000);
+        let balance_refresh_secs = env::var("TRADE_BALANCE_REFRESH_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(60);
+        let notify_dedup_window_secs = env::var("NOTIFY_DEDUP_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(300);
+        let notify_queue_size = env::var("NOTIFY_QUEUE_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(256);
+        let identity_pem_path = env::var("IDENTITY_PEM_PATH")
+            .ok()
+            .unwrap_or_else(|| "infinity_identity.pem".to_string());
+        let passphrase_env_key = env::var("IDENTITY_PASSPHRASE_ENV_KEY")
+            .ok()
+            .unwrap_or_else(|| "IDENTITY_PASSPHRASE".to_string());
+        let passphrase_fd = env::var("IDENTITY_PASSPHRASE_FD")
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok());
+        let passphrase_prompt = env::var("IDENTITY_PASSPHRASE_PROMPT")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(true);
+        let mut identity_invalid = None;
+        let identity_subaccount = match env::var("IDENTITY_SUBACCOUNT") {
+            Ok(v) if !v.trim().is_empty() => {
+                let sub = parse_subaccount(&v);
+                if sub.is_none() {
+                    identity_invalid = Some(format!(
+                        "IDENTITY_SUBACCOUNT={} が不正です（64 桁までの 16 進数か 10 進数）",
+                        v
+                    ));
+                }
+                sub
+            }
+            _ => None,
+        };
+        let mut identity_profiles = vec![IdentityProfile {
+            name: DEFAULT_IDENTITY.to_string(),
+            pem_path: identity_pem_path.clone(),
+            subaccount: identity_subaccount,
+        }];
+        if let Ok(v) = env::var("IDENTITY_PROFILES") {
+            match parse_profiles(&v) {
+                Ok(profiles) => identity_profiles.extend(profiles),
+                Err(e) => identity_invalid = identity_invalid.or(Some(e)),
+            }
+        }
+        // "BOB_ICP=alice,KONG_ICP=bob" のようにペアごとの identity を指定する
+        let mut pair_identities = Vec::new();
+        if let Ok(v) = env::var("PAIR_IDENTITIES") {
+            match parse_pair_identities(&v) {
+                Ok(entries) => pair_identities = entries,
+                Err(e) => identity_invalid = identity_invalid.or(Some(e)),
+            }
+        }
+        let journal_db_path = env::var("JOURNAL_DB_PATH")
+            .ok()
+            .unwrap_or_else(|| "kong_ics_journal.sqlite".to_string());
+        let journal_record_evaluations = env::var("JOURNAL_RECORD_EVALUATIONS")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(true);
+        let snapshot_record = env::var("SNAPSHOT_RECORD")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(false);
+        let alert_tick_failures = env::var("ALERT_TICK_FAILURES")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(10);
+        let alert_swap_failures = env::var("ALERT_SWAP_FAILURES")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(1);
+        let alert_approve_failures = env::var("ALERT_APPROVE_FAILURES")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(1);
+        let alert_snapshot_stale_secs = env::var("ALERT_SNAPSHOT_STALE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(120);
+        let alert_min_balances = env::var("ALERT_MIN_BALANCES")
+            .ok()
+            .map(|v| parse_amounts(&v))
+            .unwrap_or_default();
+        let report_hourly = env::var("REPORT_HOURLY")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(false);
+        let report_daily = env::var("REPORT_DAILY")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(true);
+        let snapshot_dir = env::var("SNAPSHOT_DIR")
+            .ok()
+            .unwrap_or_else(|| "snapshots".to_string());
+        let breaker_max_divergence_bps = env::var("BREAKER_MAX_DIVERGENCE_BPS")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(1_000.0);
+        let breaker_max_jump_bps = env::var("BREAKER_MAX_JUMP_BPS")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(1_000.0);
+        let breaker_max_edge_bps = env::var("BREAKER_MAX_EDGE_BPS")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(500.0);
+        let breaker_median_window_secs = env::var("BREAKER_MEDIAN_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(60);
+        let risk_max_daily_loss_icp = env::var("RISK_MAX_DAILY_LOSS_ICP")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        let risk_max_notional_per_hour_icp = env::var("RISK_MAX_NOTIONAL_PER_HOUR_ICP")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        let risk_max_in_flight = env::var("RISK_MAX_IN_FLIGHT")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        let risk_max_inventory = env::var("RISK_MAX_INVENTORY")
+            .ok()
+            .map(|v| parse_amounts(&v))
+            .unwrap_or_default();
+        let control_pause_file = env::var("CONTROL_PAUSE_FILE")
+            .ok()
+            .unwrap_or_else(|| "kong_ics.pause".to_string());
+        let control_poll_ms = env::var("CONTROL_POLL_MS")
             .ok()
-            .unwrap_or_else(|| "DISCORD_WEBHOOK_URL".to_string());
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(2_000);
+        let control_listen_addr = env::var("CONTROL_LISTEN_ADDR")
+            .ok()
+            .unwrap_or_else(|| "127.0.0.1:9102".to_string());
+        let control_auth_token = env::var("CONTROL_AUTH_TOKEN").unwrap_or_default();
+        let http_listen_addr = env::var("HTTP_LISTEN_ADDR")
+            .ok()
+            .unwrap_or_else(|| "127.0.0.1:9100".to_string());
+        let approve_http_listen_addr = env::var("APPROVE_HTTP_LISTEN_ADDR")
+            .ok()
+            .unwrap_or_else(|| "127.0.0.1:9101".to_string());
+        let health_stale_secs = env::var("HEALTH_STALE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(60);
+        let retry_base_delay_ms = env::var("IC_RETRY_BASE_DELAY_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(200);
+        let retry_max_delay_ms = env::var("IC_RETRY_MAX_DELAY_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(2_000);
+        let api_urls: Vec<String> = env::var("IC_API_URLS")
+            .ok()
+            .map(|v| {
+                v.split(',')
+                    .map(|u| u.trim().to_string())
+                    .filter(|u| !u.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|urls| !urls.is_empty())
+            .unwrap_or_else(|| {
+                ["https://icp-api.io", "https://icp0.io", "https://ic0.app"]
+                    .iter()
+                    .map(|u| u.to_string())
+                    .collect()
+            });
+        let endpoint_log_secs = env::var("IC_ENDPOINT_LOG_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(300);
+        let query_timeout_ms = env::var("IC_QUERY_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(10_000);
+        let update_timeout_ms = env::var("IC_UPDATE_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(60_000);
+        let ingress_expiry_secs = env::var("IC_INGRESS_EXPIRY_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(240);
+        let swap_ingress_expiry_secs = env::var("TRADE_SWAP_INGRESS_EXPIRY_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(20);
+        let swap_timeout_ms = env::var("TRADE_SWAP_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(30_000);
+        let verify_query_signatures = env::var("IC_VERIFY_QUERY_SIGNATURES")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(true);
+        let require_verified_queries = env::var("TRADE_REQUIRE_VERIFIED_QUERIES")
+            .ok()
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(true);
+        let query_max_attempts = env::var("IC_QUERY_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(3);
+        let poll_max_attempts = env::var("IC_POLL_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(5);
 
         // トークン定義を一元化
         let tokens = vec![
@@ -176,13 +805,13 @@ impl AppConfig {
             |name: &str| -> Option<&TokenDefinition> { tokens.iter().find(|t| t.name == name) };
 
         // approve用しきい値
-        let approve_specs = vec![
+        let approve_specs = [
             ("kong", e8(10_000.0)),
             ("bob", e8(600.1)),
             ("exe", e8(600.1)),
         ];
         // アービトラージ対象ペア（symbol, token_name, ikiti）
-        let pair_specs = vec![
+        let pair_specs = [
             ("BOB_ICP", "bob", e8(20.0)),
             ("KONG_ICP", "kong", e8(20.0)),
             ("EXE_ICP", "exe", e8(20.0)),
@@ -210,21 +839,107 @@ impl AppConfig {
                     symbol: symbol.to_string(),
                     ikiti_e8: *ikiti,
                     sns_fee_e8: t.transfer_fee_e8,
+                    identity: pair_identities
+                        .iter()
+                        .find(|(pair, _)| pair == symbol)
+                        .map(|(_, name)| name.clone())
+                        .unwrap_or_else(|| DEFAULT_IDENTITY.to_string()),
                 })
             })
             .collect();
 
+        if let Some((pair, _)) = pair_identities
+            .iter()
+            .find(|(pair, _)| !pairs.iter().any(|p| p.symbol == *pair))
+        {
+            identity_invalid = identity_invalid.or(Some(format!(
+                "PAIR_IDENTITIES のペア {} は取引対象にありません",
+                pair
+            )));
+        }
+
         AppConfig {
             network: NetworkConfig {
-                api_url: "https://icp-api.io".to_string(),
+                api_urls,
+                endpoint_log_secs,
                 fetch_root_key: false,
+                verify_query_signatures,
+                query_retry: RetryPolicy {
+                    max_attempts: query_max_attempts.max(1),
+                    base_delay_ms: retry_base_delay_ms,
+                    max_delay_ms: retry_max_delay_ms,
+                },
+                poll_retry: RetryPolicy {
+                    max_attempts: poll_max_attempts.max(1),
+                    base_delay_ms: retry_base_delay_ms,
+                    max_delay_ms: retry_max_delay_ms,
+                },
+                timeouts: CallTimeouts {
+                    query_timeout_ms,
+                    update_timeout_ms,
+                    ingress_expiry_secs,
+                },
             },
             identity: IdentityConfig {
-                pem_path: "infinity_identity.pem".to_string(),
+                pem_path: identity_pem_path,
+                passphrase_env_key,
+                passphrase_fd,
+                passphrase_prompt,
+                profiles: identity_profiles,
+                invalid: identity_invalid,
+            },
+            notify: NotifyConfig {
+                backends: notify_backends(),
+                alert_backends: env::var("DISCORD_ALERT_WEBHOOK_URL")
+                    .ok()
+                    .filter(|v| !v.trim().is_empty())
+                    .map(|webhook_url| NotifierConfig::Discord { webhook_url })
+                    .into_iter()
+                    .collect(),
+                dedup_window_secs: notify_dedup_window_secs,
+                queue_size: notify_queue_size,
+            },
+            journal: JournalConfig {
+                db_path: journal_db_path,
+                record_evaluations: journal_record_evaluations,
+            },
+            recorder: RecorderConfig {
+                enabled: snapshot_record,
+                dir: snapshot_dir,
+            },
+            report: ReportConfig {
+                hourly: report_hourly,
+                daily: report_daily,
+            },
+            alert: AlertConfig {
+                tick_failures: alert_tick_failures,
+                swap_failures: alert_swap_failures,
+                approve_failures: alert_approve_failures,
+                snapshot_stale_secs: alert_snapshot_stale_secs,
+                min_balances_e8: alert_min_balances,
+            },
+            control: ControlConfig {
+                pause_file: control_pause_file,
+                poll_ms: control_poll_ms,
+                listen_addr: control_listen_addr,
+                auth_token: control_auth_token,
+            },
+            risk: RiskConfig {
+                max_daily_loss_e8: risk_max_daily_loss_icp * 1e8,
+                max_notional_per_hour_e8: risk_max_notional_per_hour_icp * 1e8,
+                max_in_flight: risk_max_in_flight,
+                max_inventory_e8: risk_max_inventory,
+            },
+            breaker: BreakerConfig {
+                max_divergence_bps: breaker_max_divergence_bps,
+                max_jump_bps: breaker_max_jump_bps,
+                max_edge_bps: breaker_max_edge_bps,
+                median_window_secs: breaker_median_window_secs,
             },
-            line: LineNotifyConfig { token: None },
-            discord: DiscordWebhookConfig {
-                env_key: discord_env_key,
+            http: HttpConfig {
+                listen_addr: http_listen_addr,
+                approve_listen_addr: approve_http_listen_addr,
+                health_stale_secs,
             },
             tokens,
             trade: TradeParams {
@@ -232,6 +947,11 @@ impl AppConfig {
                 min_receive_factor,
                 profit_threshold_e8,
                 loop_interval_ms,
+                pnl_settle_ms,
+                balance_refresh_secs,
+                swap_ingress_expiry_secs,
+                swap_timeout_ms,
+                require_verified_queries,
             },
             approve: ApproveConfig {
                 tokens: approve_tokens,
@@ -0,0 +1,407 @@
+// どこで: kong_ics の取引制御
+// 何を: 全ペア / ペア単位の一時停止を持ち、停止ファイルと管理用 HTTP から切り替えて、変化を通知する
+// なぜ: プロセスを落とさずに取引だけを止め、スナップショットの更新や監視は続けられるようにするため
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::Serialize;
+use thiserror::Error;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::http::{HttpRequest, HttpResponse, Router};
+use crate::journal::now_ms;
+use crate::metrics::metrics;
+use crate::notify::{Notification, Notifier, Severity};
+
+/// 全ペアを止めるときの対象名
+pub const ALL_PAIRS: &str = "*";
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("不明なペアです: {0}")]
+    UnknownPair(String),
+    #[error("{0} は停止ファイルで止めているため、ファイルを消すまで再開できません")]
+    HeldByFile(String),
+    #[error("Authorization: Bearer のトークンが違います")]
+    Unauthorized,
+}
+
+/// 停止を掛けた経路
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlSource {
+    /// 停止ファイル。ファイルから消えるまで他の経路では再開しない
+    File,
+    /// 管理用 HTTP
+    Http,
+    /// リスク上限の超過。管理用 HTTP からも再開できる。Risk からの再開は Risk で止めたものだけ
+    Risk,
+}
+
+impl ControlSource {
+    fn label(&self) -> &'static str {
+        match self {
+            ControlSource::File => "停止ファイル",
+            ControlSource::Http => "管理 API",
+            ControlSource::Risk => "リスク管理",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Pause {
+    pub source: ControlSource,
+    pub reason: String,
+    pub since_ms: i64,
+}
+
+/// 取引の一時停止状態。Trade は swap の直前にだけ見るので、止めてもスナップショットの更新は続く
+pub struct TradingControl {
+    notifier: Option<Arc<dyn Notifier>>,
+    pairs: Vec<String>,
+    paused: Mutex<BTreeMap<String, Pause>>,
+    auth_token: Option<String>,
+}
+
+impl TradingControl {
+    pub fn new(notifier: Option<Arc<dyn Notifier>>, pairs: Vec<String>) -> Self {
+        TradingControl {
+            notifier,
+            pairs,
+            paused: Mutex::new(BTreeMap::new()),
+            auth_token: None,
+        }
+    }
+
+    /// /control に Authorization: Bearer <token> を要求する（空文字なら認証なし）
+    pub fn with_auth_token(mut self, token: &str) -> Self {
+        self.auth_token = (!token.is_empty()).then(|| token.to_string());
+        self
+    }
+
+    /// pair の取引を止めている停止（全ペアの停止を優先）。取引してよければ None
+    pub fn paused(&self, pair: &str) -> Option<Pause> {
+        let paused = self.lock();
+        paused.get(ALL_PAIRS).or_else(|| paused.get(pair)).cloned()
+    }
+
+    /// 現在の停止一覧（対象名 → 停止）
+    pub fn status(&self) -> BTreeMap<String, Pause> {
+        self.lock().clone()
+    }
+
+    /// target（ペア名か ALL_PAIRS）を止める。既に止まっていれば Ok(false)
+    pub fn pause(
+        &self,
+        target: &str,
+        source: ControlSource,
+        reason: &str,
+    ) -> Result<bool, ControlError> {
+        self.check_target(target)?;
+        let pause = Pause {
+            source,
+            reason: reason.to_string(),
+            since_ms: now_ms(),
+        };
+        {
+            let mut paused = self.lock();
+            if let Some(existing) = paused.get_mut(target) {
+                // 管理 API で止めた対象を停止ファイルにも書いたら、以後はファイルの管理にする。
+                // リスク管理で止めた対象を運用者が止め直したら、日付の切り替えでは解かないように運用者の停止にする
+                let takes_over = match source {
+                    ControlSource::File => existing.source != ControlSource::File,
+                    ControlSource::Http => existing.source == ControlSource::Risk,
+                    ControlSource::Risk => false,
+                };
+                if takes_over {
+                    info!(
+                        "停止中の {} を{}の停止に切り替えました ({})",
+                        target_label(target),
+                        source.label(),
+                        reason
+                    );
+                    existing.source = source;
+                    existing.reason = reason.to_string();
+                }
+                return Ok(false);
+            }
+            paused.insert(target.to_string(), pause.clone());
+        }
+        metrics().trading_paused.with_label_values(&[target]).set(1);
+        warn!(
+            "取引を一時停止しました: {} ({}: {})",
+            target_label(target),
+            source.label(),
+            reason
+        );
+        let mut notification = Notification::new(
+            "trading_paused",
+            Severity::Warning,
+            "取引を一時停止しました",
+        );
+        if target != ALL_PAIRS {
+            notification = notification.with_pair(target);
+        }
+        self.announce(
+            notification
+                .with_field("対象", target_label(target))
+                .with_field("操作元", source.label())
+                .with_field("理由", reason)
+                .with_dedup_key(format!("trading_paused|{}|{}", target, pause.since_ms)),
+        );
+        Ok(true)
+    }
+
+    /// target の停止を解除する。止まっていなければ Ok(false)
+    pub fn resume(&self, target: &str, source: ControlSource) -> Result<bool, ControlError> {
+        self.check_target(target)?;
+        let removed = {
+            let mut paused = self.lock();
+            match paused.get(target) {
+                None => return Ok(false),
+                Some(p) if p.source == ControlSource::File && source != ControlSource::File => {
+                    return Err(ControlError::HeldByFile(target_label(target)));
+                }
+                // 日付の切り替えで、運用者が別に掛けた停止まで解かないように
+                Some(p) if source == ControlSource::Risk && p.source != ControlSource::Risk => {
+                    return Ok(false);
+                }
+                Some(_) => paused.remove(target).expect("checked above"),
+            }
+        };
+        metrics().trading_paused.with_label_values(&[target]).set(0);
+        let paused_secs = (now_ms() - removed.since_ms) / 1_000;
+        info!(
+            "取引を再開しました: {} ({}, {} 秒停止)",
+            target_label(target),
+            source.label(),
+            paused_secs
+        );
+        let mut notification =
+            Notification::new("trading_resumed", Severity::Recovered, "取引を再開しました");
+        if target != ALL_PAIRS {
+            notification = notification.with_pair(target);
+        }
+        self.announce(
+            notification
+                .with_field("対象", target_label(target))
+                .with_field("操作元", source.label())
+                .with_field("停止していた時間", format!("{} 秒", paused_secs))
+                .with_dedup_key(format!("trading_resumed|{}|{}", target, now_ms())),
+        );
+        Ok(true)
+    }
+
+    /// 停止ファイルを読み、書かれている対象を止め、消えた対象を再開する
+    pub fn sync_pause_file(&self, path: &Path) {
+        self.apply_pause_file(path, &read_pause_file(path));
+    }
+
+    /// 書き間違いなどで知らないペア名があれば、止めたかった対象が分からないので全ペアを止める
+    fn apply_pause_file(&self, path: &Path, wanted: &BTreeSet<String>) {
+        let unknown: Vec<&str> = wanted
+            .iter()
+            .map(String::as_str)
+            .filter(|target| self.check_target(target).is_err())
+            .collect();
+        let mut wanted = wanted.clone();
+        let mut reason = format!("{} があります", path.display());
+        if !unknown.is_empty() {
+            warn!(
+                "停止ファイル {} に不明なペアがあるため全ペアを止めます: {}",
+                path.display(),
+                unknown.join(", ")
+            );
+            wanted.retain(|target| self.check_target(target).is_ok());
+            wanted.insert(ALL_PAIRS.to_string());
+            reason = format!(
+                "{} に不明なペアがあります: {}",
+                path.display(),
+                unknown.join(", ")
+            );
+        }
+        let held: BTreeSet<String> = self
+            .lock()
+            .iter()
+            .filter(|(_, p)| p.source == ControlSource::File)
+            .map(|(target, _)| target.clone())
+            .collect();
+        for target in wanted.difference(&held) {
+            if let Err(e) = self.pause(target, ControlSource::File, &reason) {
+                warn!("停止ファイル {}: {}", path.display(), e);
+            }
+        }
+        for target in held.difference(&wanted) {
+            if let Err(e) = self.resume(target, ControlSource::File) {
+                warn!("停止ファイル {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// 管理用の GET /control・POST /control/pause・POST /control/resume を router に追加する。
+    /// ?pair= を省くと全ペアが対象。with_auth_token を設定していなければ認証は無いので、ループバック以外では listen しないこと
+    pub fn routes(self: &Arc<Self>, router: Router) -> Router {
+        let status = self.clone();
+        let pause = self.clone();
+        let resume = self.clone();
+        router
+            .route("GET", "/control", move |req: &HttpRequest| {
+                status.respond(status.authorize(req).map(|_| false))
+            })
+            .route("POST", "/control/pause", move |req: &HttpRequest| {
+                let reason = req
+                    .query
+                    .get("reason")
+                    .map(String::as_str)
+                    .unwrap_or("手動");
+                pause.respond(
+                    pause.authorize(req).and_then(|_| {
+                        pause.pause(request_target(req), ControlSource::Http, reason)
+                    }),
+                )
+            })
+            .route("POST", "/control/resume", move |req: &HttpRequest| {
+                resume.respond(
+                    resume
+                        .authorize(req)
+                        .and_then(|_| resume.resume(request_target(req), ControlSource::Http)),
+                )
+            })
+    }
+
+    fn authorize(&self, req: &HttpRequest) -> Result<(), ControlError> {
+        let Some(token) = &self.auth_token else {
+            return Ok(());
+        };
+        let given = req
+            .headers
+            .get("authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or("");
+        if constant_time_eq(given.as_bytes(), token.as_bytes()) {
+            Ok(())
+        } else {
+            Err(ControlError::Unauthorized)
+        }
+    }
+
+    fn respond(&self, res: Result<bool, ControlError>) -> HttpResponse {
+        if let Err(ControlError::Unauthorized) = res {
+            // 認証前なので停止状態は返さない
+            let body = serde_json::json!({ "error": ControlError::Unauthorized.to_string() });
+            return HttpResponse::json(401, format!("{}\n", body));
+        }
+        let (status, body) = match res {
+            Ok(changed) => (
+                200,
+                serde_json::json!({ "changed": changed, "paused": self.status() }),
+            ),
+            Err(e) => {
+                let status = match e {
+                    ControlError::UnknownPair(_) => 404,
+                    ControlError::HeldByFile(_) => 409,
+                    ControlError::Unauthorized => 401,
+                };
+                (
+                    status,
+                    serde_json::json!({ "error": e.to_string(), "paused": self.status() }),
+                )
+            }
+        };
+        HttpResponse::json(status, format!("{}\n", body))
+    }
+
+    fn check_target(&self, target: &str) -> Result<(), ControlError> {
+        if target == ALL_PAIRS || self.pairs.iter().any(|p| p == target) {
+            Ok(())
+        } else {
+            Err(ControlError::UnknownPair(target.to_string()))
+        }
+    }
+
+    /// 通知の送信は待たない（HTTP ハンドラからも呼ぶため）
+    fn announce(&self, notification: Notification) {
+        if let Some(notifier) = self.notifier.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = notifier.send(&notification).await {
+                    warn!("通知失敗: {}", e);
+                }
+            });
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Pause>> {
+        self.paused.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// トークンの比較で一致した長さが応答時間から分からないようにする
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn target_label(target: &str) -> String {
+    if target == ALL_PAIRS {
+        "全ペア".to_string()
+    } else {
+        target.to_string()
+    }
+}
+
+fn request_target(req: &HttpRequest) -> &str {
+    req.query
+        .get("pair")
+        .map(String::as_str)
+        .filter(|p| !p.is_empty())
+        .unwrap_or(ALL_PAIRS)
+}
+
+/// 停止ファイルの中身を止める対象にする。
+/// 1 行に 1 ペア、`*` か `all` は全ペア、`#` 以降はコメント。対象が 1 つも無ければ全ペア
+pub fn parse_pause_file(text: &str) -> BTreeSet<String> {
+    let mut targets: BTreeSet<String> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if line.eq_ignore_ascii_case("all") {
+                ALL_PAIRS.to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if targets.is_empty() {
+        targets.insert(ALL_PAIRS.to_string());
+    }
+    targets
+}
+
+/// ファイルが無ければ空。あるのに読めなければ安全側に倒して全ペア
+fn read_pause_file(path: &Path) -> BTreeSet<String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse_pause_file(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+        Err(e) => {
+            warn!("停止ファイルを読めません ({}): {}", path.display(), e);
+            BTreeSet::from([ALL_PAIRS.to_string()])
+        }
+    }
+}
+
+/// interval ごとに停止ファイルを見直し、中身が変わったときだけ反映する。
+/// 起動時の状態は取引ループを回す前に sync_pause_file で反映しておく
+pub async fn watch_pause_file(control: Arc<TradingControl>, path: PathBuf, interval: Duration) {
+    let mut last = read_pause_file(&path);
+    loop {
+        sleep(interval).await;
+        let wanted = read_pause_file(&path);
+        if wanted != last {
+            control.apply_pause_file(&path, &wanted);
+            last = wanted;
+        }
+    }
+}
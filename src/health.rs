@@ -0,0 +1,152 @@
+// どこで: 常駐バイナリ (kong_ics / approve_manager) の死活・準備状態
+// 何を: ペアや approve サイクルごとの最終成功時刻と identity ごとの IC 到達状況を記録し、/healthz と /readyz で返す
+// なぜ: agent 呼び出しが固まってもプロセスは生きたままなので、外部の監視から再起動できるようにするため
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::http::{HttpRequest, HttpResponse, Router};
+use crate::ic_client::agent::IcClient;
+use crate::ic_client::endpoint::EndpointHealth;
+use crate::journal::now_ms;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ComponentHealth {
+    /// ループが 1 周終わった時刻（成否を問わない）。止まっていれば wedge とみなす
+    pub last_beat_ms: Option<i64>,
+    /// 最後に正常終了した時刻
+    pub last_ok_ms: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+/// identity ごとの IcClient の状態
+#[derive(Serialize)]
+struct AgentHealth<'a> {
+    identity: &'a str,
+    last_ok_ms: Option<i64>,
+    endpoints: Vec<EndpointHealth>,
+}
+
+#[derive(Serialize)]
+struct HealthReport<'a> {
+    status: &'static str,
+    principal: &'a str,
+    started_ms: i64,
+    /// 全 identity のうち最も古い IC 成功時刻（一度も成功していない identity があれば null）
+    agent_last_ok_ms: Option<i64>,
+    agents: Vec<AgentHealth<'a>>,
+    components: &'a BTreeMap<String, ComponentHealth>,
+}
+
+pub struct HealthState {
+    principal: String,
+    /// (identity 名, IcClient)。identity ごとに鍵と接続が別なので、どれか 1 つでも IC に届かなければ準備未完了
+    clients: Vec<(String, IcClient)>,
+    started_ms: i64,
+    /// この時間を超えて beat / ok / IC 成功が無ければ異常とみなす
+    stale_after_ms: i64,
+    components: Mutex<BTreeMap<String, ComponentHealth>>,
+}
+
+impl HealthState {
+    pub fn new(principal: String, clients: Vec<(String, IcClient)>, stale_after_secs: u64) -> Self {
+        HealthState {
+            principal,
+            clients,
+            started_ms: now_ms(),
+            stale_after_ms: stale_after_secs as i64 * 1_000,
+            components: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 監視対象を登録する。登録しておくと一度も動かなかった場合も異常として検出できる
+    pub fn register(&self, name: &str) {
+        self.lock().entry(name.to_string()).or_default();
+    }
+
+    pub fn ok(&self, name: &str) {
+        let now = now_ms();
+        let mut components = self.lock();
+        let c = components.entry(name.to_string()).or_default();
+        c.last_beat_ms = Some(now);
+        c.last_ok_ms = Some(now);
+        c.last_error = None;
+    }
+
+    pub fn fail(&self, name: &str, error: &str) {
+        let mut components = self.lock();
+        let c = components.entry(name.to_string()).or_default();
+        c.last_beat_ms = Some(now_ms());
+        c.last_error = Some(error.to_string());
+    }
+
+    /// 生存: 全コンポーネントのループが stale_after 以内に回っている
+    pub fn is_live(&self) -> bool {
+        let now = now_ms();
+        self.lock()
+            .values()
+            .all(|c| self.fresh(c.last_beat_ms, now))
+    }
+
+    /// 準備完了: 全コンポーネントが stale_after 以内に成功し、全 identity の IcClient が IC に到達できている
+    pub fn is_ready(&self) -> bool {
+        let now = now_ms();
+        let agent_ok = self.clients.iter().all(|(_, c)| {
+            c.last_ok_ms()
+                .is_some_and(|ms| now - ms <= self.stale_after_ms)
+        });
+        agent_ok
+            && self.lock().values().all(|c| {
+                c.last_ok_ms
+                    .is_some_and(|ms| now - ms <= self.stale_after_ms)
+            })
+    }
+
+    /// /healthz と /readyz を router に追加する
+    pub fn routes(self: &Arc<Self>, router: Router) -> Router {
+        let live = self.clone();
+        let ready = self.clone();
+        router
+            .route("GET", "/healthz", move |_req: &HttpRequest| {
+                live.respond(live.is_live())
+            })
+            .route("GET", "/readyz", move |_req: &HttpRequest| {
+                ready.respond(ready.is_ready())
+            })
+    }
+
+    fn respond(&self, healthy: bool) -> HttpResponse {
+        let components = self.lock().clone();
+        let agents: Vec<AgentHealth> = self
+            .clients
+            .iter()
+            .map(|(name, c)| AgentHealth {
+                identity: name,
+                last_ok_ms: c.last_ok_ms(),
+                endpoints: c.endpoint_health(),
+            })
+            .collect();
+        let agent_last_ok_ms = agents.iter().map(|a| a.last_ok_ms).min().flatten();
+        let report = HealthReport {
+            status: if healthy { "ok" } else { "unhealthy" },
+            principal: &self.principal,
+            started_ms: self.started_ms,
+            agent_last_ok_ms,
+            agents,
+            components: &components,
+        };
+        let body = serde_json::to_string(&report).unwrap_or_else(|e| e.to_string());
+        HttpResponse::json(if healthy { 200 } else { 503 }, body)
+    }
+
+    /// 一度も beat していない場合は起動時刻から数える（起動直後を異常扱いしない）
+    fn fresh(&self, last_ms: Option<i64>, now: i64) -> bool {
+        now - last_ms.unwrap_or(self.started_ms) <= self.stale_after_ms
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, ComponentHealth>> {
+        self.components.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,206 @@
+// どこで: 運用向けの小さな HTTP サーバ
+// 何を: メソッド + パスごとに登録したハンドラで短いテキスト応答を返す
+// なぜ: /metrics などを出すだけなので、Web フレームワークを持ち込まず tokio だけで済ませるため
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tracing::{info, warn};
+
+/// リクエストヘッダの上限。これを超えるものは運用用途では想定しない
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+/// ヘッダを読み終えるまでの既定の待ち時間。送ってこない接続がタスクを握り続けないようにする
+const READ_TIMEOUT_MS: u64 = 5_000;
+
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("listen に失敗しました ({0}): {1}")]
+    Bind(String, String),
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// `?` 以降をそのまま分解したもの（URL デコードはしない）
+    pub query: HashMap<String, String>,
+    /// ヘッダ名は小文字にそろえる
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        HttpResponse {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        HttpResponse {
+            status,
+            content_type: "application/json",
+            body: body.into(),
+        }
+    }
+}
+
+pub type Handler = Arc<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Router {
+    routes: HashMap<(String, String), Handler>,
+    read_timeout: Duration,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            routes: HashMap::new(),
+            read_timeout: Duration::from_millis(READ_TIMEOUT_MS),
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// ヘッダを読み終えるまでの待ち時間を変える
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    pub fn route<F>(mut self, method: &str, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.routes
+            .insert((method.to_string(), path.to_string()), Arc::new(handler));
+        self
+    }
+
+    fn handle(&self, req: &HttpRequest) -> HttpResponse {
+        match self.routes.get(&(req.method.clone(), req.path.clone())) {
+            Some(handler) => handler(req),
+            None => HttpResponse::text(404, "not found\n"),
+        }
+    }
+}
+
+/// addr で listen し、接続ごとにタスクを立てて応答する（戻らない）
+pub async fn serve(addr: &str, router: Router) -> Result<(), HttpError> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| HttpError::Bind(addr.to_string(), e.to_string()))?;
+    info!("HTTP サーバを起動しました: {}", addr);
+    let router = Arc::new(router);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let router = router.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_conn(stream, &router).await {
+                        warn!("HTTP 応答失敗: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("HTTP accept 失敗: {}", e),
+        }
+    }
+}
+
+async fn handle_conn(mut stream: TcpStream, router: &Router) -> std::io::Result<()> {
+    let response = match timeout(router.read_timeout, read_head(&mut stream)).await {
+        Ok(Ok(Some(buf))) => match parse_request(&buf) {
+            Some(req) => router.handle(&req),
+            None => HttpResponse::text(400, "bad request\n"),
+        },
+        Ok(Ok(None)) => HttpResponse::text(431, "request header too large\n"),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => HttpResponse::text(408, "request timeout\n"),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// 空行までを読む。MAX_REQUEST_BYTES を超えても空行が来なければ None
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Ok(Some(buf))
+}
+
+fn parse_request(buf: &[u8]) -> Option<HttpRequest> {
+    let text = std::str::from_utf8(buf).ok()?;
+    let mut lines = text.lines();
+    let mut parts = lines.next()?.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let (path, query_str) = target.split_once('?').unwrap_or((target, ""));
+    let query = query_str
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            (k.to_string(), v.to_string())
+        })
+        .collect();
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    Some(HttpRequest {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
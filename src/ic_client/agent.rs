@@ -1,82 +1,444 @@
 // どこで: IC への低レベル呼び出しをまとめる Agent ラッパ
-// 何を: Agent 初期化、query/update のエラーハンドリング一元化
+// 何を: query/update のエラー分類・再試行・タイムアウト・メトリクスの一元化（実際の呼び出しは transport）
 // なぜ: agent-rs フォーク差分を吸収し、上位を安定させるため
 
+use ic_agent::agent::{RejectCode, RejectResponse};
 use ic_agent::export::Principal;
-use ic_agent::{Agent, AgentError, Identity};
+use ic_agent::{AgentError, Identity, RequestId};
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::time::{sleep, timeout};
+use tracing::{info, warn};
+
+use super::endpoint::EndpointHealth;
+use super::transport::{AgentTransport, CanisterTransport};
+use crate::config::{CallTimeouts, RetryPolicy};
+use crate::journal::now_ms;
+use crate::metrics::metrics;
+
+/// poll_update の締め切りが過ぎていても最低限結果を確認する時間
+const MIN_POLL_MS: u64 = 5_000;
 
 #[derive(Debug, Error)]
 pub enum IcClientError {
     #[error("Agent 初期化に失敗しました: {0}")]
     Init(String),
-    #[error("query 失敗: {0}")]
-    Query(String),
-    #[error("update 失敗: {0}")]
-    Update(String),
+    #[error("canister id が不正です ({0}): {1}")]
+    InvalidCanister(String, String),
+    /// HTTP ステータスが取れたときは status に入る
+    #[error("通信エラー ({method}): {message}")]
+    Transport {
+        method: String,
+        status: Option<u16>,
+        message: String,
+    },
+    #[error("canister reject ({method}): {code:?} {message}")]
+    Reject {
+        method: String,
+        code: RejectCode,
+        message: String,
+    },
+    /// update の場合は送信済みの可能性がある（ingress expiry までは受理されうる）
+    #[error("応答待ちタイムアウト ({0})")]
+    Timeout(String),
+    #[error("証明書/署名の検証に失敗しました ({method}): {message}")]
+    Certificate { method: String, message: String },
+    #[error("応答のデコードに失敗しました ({method}): {message}")]
+    Decode { method: String, message: String },
+    #[error("IC 呼び出し失敗 ({method}): {message}")]
+    Other { method: String, message: String },
+}
+
+impl IcClientError {
+    /// ic-agent のエラーを再試行・エンドポイント切り替えの判断に使う分類へ寄せる
+    pub fn from_agent(method: &str, err: AgentError) -> Self {
+        let method = method.to_string();
+        match err {
+            AgentError::ReplicaError(RejectResponse {
+                reject_code,
+                reject_message,
+                ..
+            }) => IcClientError::Reject {
+                method,
+                code: reject_code,
+                message: reject_message,
+            },
+            AgentError::HttpError(payload) => IcClientError::Transport {
+                method,
+                status: Some(payload.status),
+                message: AgentError::HttpError(payload).to_string(),
+            },
+            AgentError::TransportError(e) => IcClientError::Transport {
+                method,
+                status: None,
+                message: e.to_string(),
+            },
+            AgentError::TimeoutWaitingForResponse() => IcClientError::Timeout(method),
+            e @ (AgentError::CertificateVerificationFailed()
+            | AgentError::QuerySignatureVerificationFailed
+            | AgentError::CertificateNotAuthorized()
+            | AgentError::CertificateOutdated(_)
+            | AgentError::MissingSignature
+            | AgentError::MalformedSignature
+            | AgentError::MalformedPublicKey
+            | AgentError::TooManySignatures { .. }
+            | AgentError::DerKeyLengthMismatch { .. }
+            | AgentError::DerPrefixMismatch { .. }) => IcClientError::Certificate {
+                method,
+                message: e.to_string(),
+            },
+            e @ (AgentError::InvalidCborData(_)
+            | AgentError::CandidError(_)
+            | AgentError::Leb128ReadError(_)
+            | AgentError::Utf8ReadError(_)
+            | AgentError::LookupPathAbsent(_)
+            | AgentError::LookupPathUnknown(_)
+            | AgentError::LookupPathError(_)
+            | AgentError::InvalidRequestStatus(..)
+            | AgentError::ResponseSizeExceededLimit()) => IcClientError::Decode {
+                method,
+                message: e.to_string(),
+            },
+            e => IcClientError::Other {
+                method,
+                message: e.to_string(),
+            },
+        }
+    }
+
+    /// 応答の candid デコード失敗を上位からこの分類で返すためのヘルパ
+    pub fn decode(method: &str, message: impl ToString) -> Self {
+        IcClientError::Decode {
+            method: method.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// エンドポイント（boundary node）側の問題とみなす失敗か。別のエンドポイントに切り替える対象
+    pub fn is_endpoint_failure(&self) -> bool {
+        matches!(
+            self,
+            IcClientError::Transport { .. }
+                | IcClientError::Timeout(_)
+                | IcClientError::Certificate { .. }
+        )
+    }
+
+    /// 応答の署名・証明書の検証に失敗したか（応答内容を信用できない）
+    pub fn is_verification_failure(&self) -> bool {
+        matches!(self, IcClientError::Certificate { .. })
+    }
+
+    /// 同じ呼び出しを繰り返せば通る見込みがあるか
+    pub fn is_transient(&self) -> bool {
+        match self {
+            IcClientError::Transport { status, .. } => status.is_none_or(|s| s >= 500 || s == 429),
+            IcClientError::Timeout(_) => true,
+            IcClientError::Reject { code, .. } => *code == RejectCode::SysTransient,
+            _ => false,
+        }
+    }
+
+    /// メトリクス・ログ用の分類
+    pub fn class(&self) -> &'static str {
+        match self {
+            IcClientError::Init(_) => "init",
+            IcClientError::InvalidCanister(..) => "invalid_canister",
+            IcClientError::Transport { .. } => "transport",
+            IcClientError::Reject { .. } => "reject",
+            IcClientError::Timeout(_) => "timeout",
+            IcClientError::Certificate { .. } => "certificate",
+            IcClientError::Decode { .. } => "decode",
+            IcClientError::Other { .. } => "other",
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct IcClient {
-    pub agent: Arc<Agent>,
+    /// 実際の呼び出し先。本番は AgentTransport、オフラインでは mock
+    transport: Arc<dyn CanisterTransport>,
+    /// 最後に query/update が成功した時刻 (ms)。0 は未成功
+    last_ok_ms: Arc<AtomicI64>,
+    query_retry: RetryPolicy,
+    poll_retry: RetryPolicy,
+    timeouts: CallTimeouts,
 }
 
 impl IcClient {
     pub async fn new(
-        url: &str,
+        urls: &[String],
         identity: Arc<dyn Identity + Send + Sync>,
         fetch_root_key: bool,
+        verify_query_signatures: bool,
     ) -> Result<Self, IcClientError> {
-        let agent = Agent::builder()
-            .with_url(url)
-            .with_arc_identity(identity)
-            .build()
-            .map_err(|e| IcClientError::Init(e.to_string()))?;
-        if fetch_root_key {
-            agent
-                .fetch_root_key()
-                .await
-                .map_err(|e| IcClientError::Init(format!("root key: {}", e)))?;
+        let transport =
+            AgentTransport::connect(urls, identity, fetch_root_key, verify_query_signatures)
+                .await?;
+        Ok(IcClient::from_transport(Arc::new(transport)))
+    }
+
+    /// 任意の transport で作る（mock やシミュレータ向け）
+    pub fn from_transport(transport: Arc<dyn CanisterTransport>) -> Self {
+        IcClient {
+            transport,
+            last_ok_ms: Arc::new(AtomicI64::new(0)),
+            query_retry: RetryPolicy::default(),
+            poll_retry: RetryPolicy::default(),
+            timeouts: CallTimeouts::default(),
         }
-        Ok(IcClient {
-            agent: Arc::new(agent),
-        })
     }
 
+    /// 再試行方針を差し替える（未指定なら RetryPolicy::default）
+    pub fn with_retry(mut self, query: RetryPolicy, poll: RetryPolicy) -> Self {
+        self.query_retry = query;
+        self.poll_retry = poll;
+        self
+    }
+
+    /// タイムアウトと ingress expiry を差し替える。
+    /// IcClient は transport を共有したまま clone できるので、swap 用だけ短くするといった使い方ができる
+    pub fn with_timeouts(mut self, timeouts: CallTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn timeouts(&self) -> CallTimeouts {
+        self.timeouts
+    }
+
+    /// 署名に使う principal
+    pub fn principal(&self) -> Result<Principal, String> {
+        self.transport.principal()
+    }
+
+    /// query 応答が署名検証済みか（検証に失敗した応答は Certificate エラーになる）
+    pub fn verifies_queries(&self) -> bool {
+        self.transport.verifies_queries()
+    }
+
+    pub fn endpoint_health(&self) -> Vec<EndpointHealth> {
+        self.transport.endpoint_health()
+    }
+
+    /// 各エンドポイントの状態を 1 行ずつログに出す
+    pub fn log_endpoint_health(&self) {
+        for h in self.endpoint_health() {
+            info!(
+                "endpoint {} healthy={} latency={} calls={} errors={} last_error={}",
+                h.url,
+                h.healthy,
+                h.ewma_latency_ms
+                    .map(|ms| format!("{:.0}ms", ms))
+                    .unwrap_or_else(|| "-".to_string()),
+                h.calls,
+                h.errors,
+                h.last_error.as_deref().unwrap_or("-")
+            );
+        }
+    }
+
+    /// 疎通確認用: 最後に IC 呼び出しが成功した時刻
+    pub fn last_ok_ms(&self) -> Option<i64> {
+        match self.last_ok_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(ms),
+        }
+    }
+
+    /// query は副作用が無いので一時的な失敗なら query_retry に従ってやり直す。
+    /// 直前に失敗したエンドポイントは transport 側で後回しになるので、再試行は別エンドポイントへ向かう
     pub async fn query_raw(
         &self,
         canister: &str,
         method: &str,
         args: Vec<u8>,
     ) -> Result<Vec<u8>, IcClientError> {
-        let canister_id =
-            Principal::from_text(canister).map_err(|e| IcClientError::Query(e.to_string()))?;
-        self.agent
-            .query(&canister_id, method)
-            .with_arg(args)
-            .call()
-            .await
-            .map_err(|e| IcClientError::Query(render_agent_error(e)))
+        let call_timeout = Duration::from_millis(self.timeouts.query_timeout_ms);
+        let mut attempt = 1;
+        loop {
+            let started = Instant::now();
+            let res = self
+                .transport
+                .query(canister, method, args.clone(), call_timeout)
+                .await;
+            self.observe_call(
+                canister,
+                method,
+                "query",
+                started.elapsed(),
+                res.as_ref().map(|_| ()),
+            );
+            match res {
+                Err(e) if e.is_transient() && attempt < self.query_retry.max_attempts => {
+                    warn!(
+                        "query 再試行 {}/{} ({}): {}",
+                        attempt, self.query_retry.max_attempts, canister, e
+                    );
+                    sleep(Duration::from_millis(self.query_retry.delay_ms(attempt))).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
     }
 
+    /// update を送信して結果まで待つ（submit_update + poll_update）。
+    /// 送信は 1 回だけ行い、request_status の待機だけを poll_retry に従って再開するので二重実行にはならない
     pub async fn update_raw(
         &self,
         canister: &str,
         method: &str,
         args: Vec<u8>,
     ) -> Result<Vec<u8>, IcClientError> {
-        let canister_id =
-            Principal::from_text(canister).map_err(|e| IcClientError::Update(e.to_string()))?;
-        self.agent
-            .update(&canister_id, method)
-            .with_arg(args)
-            .call_and_wait()
-            .await
-            .map_err(|e| IcClientError::Update(render_agent_error(e)))
+        let submitted = self.submit_update(canister, method, args).await?;
+        self.poll_update(&submitted).await
+    }
+
+    /// update を送信し、結果を待たずに request id を返す。
+    /// message には ingress_expiry_secs の期限を付け、送信は update_timeout_ms で打ち切る。
+    /// 失敗しても投げ直さない（届いていれば二重実行になる）
+    pub async fn submit_update(
+        &self,
+        canister: &str,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<SubmittedUpdate, IcClientError> {
+        let started = Instant::now();
+        let submitted_ms = now_ms();
+        let res = self
+            .transport
+            .submit(
+                canister,
+                method,
+                args,
+                Duration::from_secs(self.timeouts.ingress_expiry_secs),
+                Duration::from_millis(self.timeouts.update_timeout_ms),
+            )
+            .await;
+        match res {
+            Ok(request_id) => Ok(SubmittedUpdate {
+                canister: canister.to_string(),
+                method: method.to_string(),
+                request_id,
+                submitted_ms,
+            }),
+            Err(e) => {
+                self.observe_call(canister, method, "update", started.elapsed(), Err(&e));
+                Err(e)
+            }
+        }
+    }
+
+    /// 送信済み update の結果を待つ。締め切りは送信時刻から update_timeout_ms
+    /// （再起動後の回収などで既に過ぎていても MIN_POLL_MS だけは確認する）
+    pub async fn poll_update(&self, update: &SubmittedUpdate) -> Result<Vec<u8>, IcClientError> {
+        let elapsed_ms = (now_ms() - update.submitted_ms).max(0) as u64;
+        let remaining_ms = self
+            .timeouts
+            .update_timeout_ms
+            .saturating_sub(elapsed_ms)
+            .max(MIN_POLL_MS);
+        let res = match timeout(
+            Duration::from_millis(remaining_ms),
+            self.wait_update(update),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => Err(IcClientError::Timeout(update.method.clone())),
+        };
+        let elapsed = Duration::from_millis((now_ms() - update.submitted_ms).max(0) as u64);
+        self.observe_call(
+            &update.canister,
+            &update.method,
+            "update",
+            elapsed,
+            res.as_ref().map(|_| ()),
+        );
+        res
+    }
+
+    async fn wait_update(&self, update: &SubmittedUpdate) -> Result<Vec<u8>, IcClientError> {
+        let mut attempt = 1;
+        loop {
+            let res = self
+                .transport
+                .wait(&update.canister, &update.method, update.request_id)
+                .await;
+            match res {
+                // reject は確定結果、Timeout は agent 側の待機上限（5 分）を使い切った後なので、
+                // 再試行するのはポーリング中の一時的な通信エラーだけ
+                Err(e @ IcClientError::Transport { .. })
+                    if e.is_transient() && attempt < self.poll_retry.max_attempts =>
+                {
+                    warn!(
+                        "update 結果の取得を再試行 {}/{} ({} {}): {}",
+                        attempt, self.poll_retry.max_attempts, update.canister, update.method, e
+                    );
+                    sleep(Duration::from_millis(self.poll_retry.delay_ms(attempt))).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn observe_call(
+        &self,
+        canister: &str,
+        method: &str,
+        kind: &str,
+        elapsed: Duration,
+        res: Result<(), &IcClientError>,
+    ) {
+        let outcome = match res {
+            Ok(_) => "ok",
+            Err(e) => e.class(),
+        };
+        metrics()
+            .ic_call_seconds
+            .with_label_values(&[canister, method, kind, outcome])
+            .observe(elapsed.as_secs_f64());
+        // canister の reject も IC までは届いているので疎通としては成功扱い
+        if matches!(res, Ok(_) | Err(IcClientError::Reject { .. })) {
+            self.last_ok_ms.store(now_ms(), Ordering::Relaxed);
+        }
     }
 }
 
-fn render_agent_error(err: AgentError) -> String {
-    err.to_string()
+/// 送信済み update。request id を保存しておけば再起動後も poll_update で結果を確認できる
+/// （IC が request status を保持している間 = ingress expiry 後しばらくまで）
+#[derive(Debug, Clone)]
+pub struct SubmittedUpdate {
+    pub canister: String,
+    pub method: String,
+    pub request_id: RequestId,
+    pub submitted_ms: i64,
+}
+
+impl SubmittedUpdate {
+    /// ジャーナルに保存した hex 表記の request id から復元する
+    pub fn restore(
+        canister: &str,
+        method: &str,
+        request_id_hex: &str,
+        submitted_ms: i64,
+    ) -> Result<Self, IcClientError> {
+        let request_id = request_id_hex.parse::<RequestId>().map_err(|e| {
+            IcClientError::decode(method, format!("request id {}: {}", request_id_hex, e))
+        })?;
+        Ok(SubmittedUpdate {
+            canister: canister.to_string(),
+            method: method.to_string(),
+            request_id,
+            submitted_ms,
+        })
+    }
+
+    pub fn request_id_hex(&self) -> String {
+        String::from(self.request_id)
+    }
 }
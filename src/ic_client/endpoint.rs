@@ -0,0 +1,172 @@
+// どこで: IcClient が使う API エンドポイント（boundary node）の集合
+// 何を: エンドポイントごとのレイテンシ・エラー率を集計し、速くて健全なものを選ぶ
+// なぜ: 単一 URL だと boundary node の障害や遅延がそのまま取引停止につながるため
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ic_agent::Agent;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::journal::now_ms;
+
+/// 連続でこの回数失敗したら一時的に選択対象から外す
+const FAILURE_THRESHOLD: u32 = 3;
+/// 外している時間の既定値。過ぎたら再び試す
+const COOLDOWN_MS: i64 = 30_000;
+/// レイテンシの指数移動平均の重み（新しい値側）
+const EWMA_ALPHA: f64 = 0.2;
+
+pub struct Endpoint {
+    pub url: String,
+    pub agent: Agent,
+    stats: Mutex<EndpointStats>,
+}
+
+#[derive(Debug, Default)]
+struct EndpointStats {
+    ewma_latency_ms: Option<f64>,
+    calls: u64,
+    errors: u64,
+    consecutive_failures: u32,
+    down_until_ms: i64,
+    last_error: Option<String>,
+}
+
+/// /readyz やログ向けのエンドポイント状態
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointHealth {
+    pub url: String,
+    pub healthy: bool,
+    pub ewma_latency_ms: Option<f64>,
+    pub calls: u64,
+    pub errors: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl Endpoint {
+    pub fn new(url: String, agent: Agent) -> Self {
+        Endpoint {
+            url,
+            agent,
+            stats: Mutex::new(EndpointStats::default()),
+        }
+    }
+
+    fn stats(&self) -> std::sync::MutexGuard<'_, EndpointStats> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    cooldown_ms: i64,
+}
+
+impl EndpointPool {
+    /// endpoints は 1 つ以上であること
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        assert!(!endpoints.is_empty(), "エンドポイントが 1 つもありません");
+        EndpointPool {
+            endpoints,
+            cooldown_ms: COOLDOWN_MS,
+        }
+    }
+
+    /// 連続失敗で外す時間を変える
+    pub fn with_cooldown_ms(mut self, cooldown_ms: i64) -> Self {
+        self.cooldown_ms = cooldown_ms;
+        self
+    }
+
+    pub fn get(&self, idx: usize) -> &Endpoint {
+        &self.endpoints[idx]
+    }
+
+    /// 除外中でないものから平均レイテンシが最小のものを選ぶ（未計測は 0 扱いで一度は試す）。
+    /// 直前に失敗したものは後回しにするので、IcClient の再試行は自然に別エンドポイントへ切り替わる
+    pub fn pick(&self) -> usize {
+        let now = now_ms();
+        // 除外中でないもの優先、次に直前の成否、平均レイテンシ。全滅時は復帰の早いものを選ぶ
+        let key = |i: usize| {
+            let s = self.endpoints[i].stats();
+            if s.down_until_ms > now {
+                (true, false, s.down_until_ms as f64)
+            } else {
+                (
+                    false,
+                    s.consecutive_failures > 0,
+                    s.ewma_latency_ms.unwrap_or(0.0),
+                )
+            }
+        };
+        (0..self.endpoints.len())
+            .map(|i| (i, key(i)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// latency は query・update 送信など「エンドポイントの速さ」を表すものだけ渡す
+    pub fn record_ok(&self, idx: usize, latency: Option<Duration>) {
+        let ep = &self.endpoints[idx];
+        let mut s = ep.stats();
+        s.calls += 1;
+        if let Some(latency) = latency {
+            let ms = latency.as_secs_f64() * 1_000.0;
+            s.ewma_latency_ms = Some(match s.ewma_latency_ms {
+                Some(prev) => prev * (1.0 - EWMA_ALPHA) + ms * EWMA_ALPHA,
+                None => ms,
+            });
+        }
+        if s.consecutive_failures >= FAILURE_THRESHOLD {
+            info!("API エンドポイントが復帰しました: {}", ep.url);
+        }
+        s.consecutive_failures = 0;
+        s.down_until_ms = 0;
+    }
+
+    /// 通信エラー・タイムアウトなどエンドポイント起因の失敗を記録する
+    pub fn record_failure(&self, idx: usize, error: &str) {
+        let ep = &self.endpoints[idx];
+        let mut s = ep.stats();
+        s.calls += 1;
+        s.errors += 1;
+        s.consecutive_failures += 1;
+        s.last_error = Some(error.to_string());
+        if s.consecutive_failures >= FAILURE_THRESHOLD {
+            let first = s.consecutive_failures == FAILURE_THRESHOLD;
+            s.down_until_ms = now_ms() + self.cooldown_ms;
+            if first {
+                warn!(
+                    "API エンドポイントを {} 秒間除外します ({} 回連続失敗): {} {}",
+                    self.cooldown_ms / 1_000,
+                    s.consecutive_failures,
+                    ep.url,
+                    error
+                );
+            }
+        }
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        let now = now_ms();
+        self.endpoints
+            .iter()
+            .map(|ep| {
+                let s = ep.stats();
+                EndpointHealth {
+                    url: ep.url.clone(),
+                    healthy: s.down_until_ms <= now,
+                    ewma_latency_ms: s.ewma_latency_ms,
+                    calls: s.calls,
+                    errors: s.errors,
+                    consecutive_failures: s.consecutive_failures,
+                    last_error: s.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+}
@@ -1,29 +1,82 @@
 // どこで: ICPSwap の metadata を取得するクライアント
-// 何を: metadata メソッドを叩き、プールの k 値を計算する
+// 何を: metadata メソッドを叩き、プールの k 値を計算する。ICPSwap の .did に合わせた型でデコードする
 // なぜ: アービトラージ計算の入力となる流動性指標が必要なため
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use candid::types::Label;
-use candid::{types::value::IDLField, types::value::IDLValue, Encode, IDLArgs, Nat};
+use candid::{types::value::IDLField, types::value::IDLValue, Decode, Encode, IDLArgs, Int, Nat};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::warn;
 
-use super::agent::IcClient;
+use super::agent::{IcClient, IcClientError};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IcsPoolSnapshot {
     pub token0_k: f64,
     pub token1_k: f64,
+    /// sqrtPriceX96 から求めた token1 / token0（SNS 1 あたりの ICP）。これを持たない古い記録では 0
+    #[serde(default)]
+    pub price_icp_per_sns: f64,
 }
 
 #[derive(Debug, Error)]
 pub enum IcsError {
     #[error("IC クライアントエラー: {0}")]
-    Client(String),
+    Client(IcClientError),
     #[error("candid デコード失敗: {0}")]
     Decode(String),
     #[error("metadata から必要なフィールドを取得できませんでした")]
     MissingFields,
+    #[error("metadata がエラーを返しました: {0:?}")]
+    Rejected(PoolError),
+}
+
+#[derive(candid::CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct Token {
+    pub address: String,
+    pub standard: String,
+}
+
+/// ICPSwap の .did の PoolMetadata
+#[derive(candid::CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct PoolMetadata {
+    pub fee: Nat,
+    pub key: String,
+    pub liquidity: Nat,
+    #[serde(rename = "maxLiquidityPerTick")]
+    pub max_liquidity_per_tick: Nat,
+    #[serde(rename = "nextPositionId")]
+    pub next_position_id: Nat,
+    #[serde(rename = "sqrtPriceX96")]
+    pub sqrt_price_x96: Nat,
+    pub tick: Int,
+    pub token0: Token,
+    pub token1: Token,
+}
+
+/// ICPSwap の Error
+#[derive(candid::CandidType, Deserialize, Serialize, Debug, Clone)]
+pub enum PoolError {
+    CommonError,
+    InternalError(String),
+    UnsupportedToken(String),
+    InsufficientFunds,
 }
 
+/// ICPSwap の Result 系 variant（ok / err は小文字）
+#[derive(candid::CandidType, Deserialize, Debug, Clone)]
+pub enum IcsResult<T> {
+    #[serde(rename = "ok")]
+    Ok(T),
+    #[serde(rename = "err")]
+    Err(PoolError),
+}
+
+/// 型付きデコードに失敗して動的デコードに切り替えたことを一度だけ警告する
+static FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
 pub async fn fetch_pool_snapshot(
     client: &IcClient,
     canister: &str,
@@ -32,11 +85,24 @@ pub async fn fetch_pool_snapshot(
     let raw = client
         .query_raw(canister, "metadata", args)
         .await
-        .map_err(|e| IcsError::Client(e.to_string()))?;
+        .map_err(IcsError::Client)?;
 
-    parse_metadata(&raw)
+    match Decode!(&raw, IcsResult<PoolMetadata>) {
+        Ok(IcsResult::Ok(meta)) => snapshot_from_parts(&meta.sqrt_price_x96, &meta.liquidity),
+        Ok(IcsResult::Err(e)) => Err(IcsError::Rejected(e)),
+        Err(e) => {
+            if !FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "ICPSwap metadata の型付きデコードに失敗したため動的デコードで読みます (.did の変更を確認してください): {}",
+                    e
+                );
+            }
+            parse_metadata(&raw)
+        }
+    }
 }
 
+/// 型付きデコードできなかったときの予備。フィールドを名前かハッシュ id で探す
 fn parse_metadata(raw: &[u8]) -> Result<IcsPoolSnapshot, IcsError> {
     let args = IDLArgs::from_bytes(raw).map_err(|e| IcsError::Decode(e.to_string()))?;
     let first = args.args.first().ok_or(IcsError::MissingFields)?;
@@ -67,15 +133,23 @@ fn parse_metadata(raw: &[u8]) -> Result<IcsPoolSnapshot, IcsError> {
     let l_val = extract_nat_named_or_id(record_fields, "liquidity", 1_304_432_370u32)
         .ok_or(IcsError::MissingFields)?;
 
-    let sqrt_price = nat_to_f64(&sqrt_price_val)?;
-    let l = nat_to_f64(&l_val)?;
+    snapshot_from_parts(&sqrt_price_val, &l_val)
+}
+
+fn snapshot_from_parts(sqrt_price_x96: &Nat, liquidity: &Nat) -> Result<IcsPoolSnapshot, IcsError> {
+    let sqrt_price = nat_to_f64(sqrt_price_x96)?;
+    let l = nat_to_f64(liquidity)?;
 
     // price = (sqrt_price^2)/(2^192)
     let price = (sqrt_price * sqrt_price) / (2f64.powi(192));
     let token0_k = l / price.sqrt();
     let token1_k = l * price.sqrt();
 
-    Ok(IcsPoolSnapshot { token0_k, token1_k })
+    Ok(IcsPoolSnapshot {
+        token0_k,
+        token1_k,
+        price_icp_per_sns: price,
+    })
 }
 
 fn extract_nat_named_or_id(entries: &[IDLField], name: &str, id: u32) -> Option<Nat> {
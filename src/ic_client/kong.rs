@@ -1,14 +1,18 @@
 // どこで: Kong canister へのクエリを扱うクライアント
-// 何を: pools メソッドから残高を取得し (ICP, SNS) を返す
+// 何を: pools メソッドから残高を取得し (ICP, SNS) を返し、requests から swap_async の約定量を読む。Kong の .did に合わせた型でデコードする
 // なぜ: アービトラージ計算の基準価格として利用するため
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use candid::types::Label;
-use candid::{types::value::IDLField, types::value::IDLValue, Encode, IDLArgs, Nat};
+use candid::{types::value::IDLField, types::value::IDLValue, Decode, Encode, IDLArgs, Nat};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::warn;
 
-use super::agent::IcClient;
+use super::agent::{IcClient, IcClientError};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KongPoolSnapshot {
     pub icp_balance: f64,
     pub sns_balance: f64,
@@ -25,13 +29,73 @@ pub struct KongPoolSnapshot {
 #[derive(Debug, Error)]
 pub enum KongError {
     #[error("IC クライアントエラー: {0}")]
-    Client(String),
+    Client(IcClientError),
     #[error("candid デコード失敗: {0}")]
     Decode(String),
     #[error("pools から必要なフィールドを取得できませんでした")]
     MissingFields,
+    #[error("pools がエラーを返しました: {0}")]
+    Rejected(String),
+}
+
+/// Kong の .did の PoolReply のうち bot が使うフィールド（他のフィールドはデコード時に読み飛ばす）
+#[derive(candid::CandidType, Deserialize, Debug, Clone)]
+pub struct PoolReply {
+    pub symbol: String,
+    pub symbol_0: String,
+    pub address_0: String,
+    pub balance_0: Nat,
+    pub lp_fee_0: Nat,
+    pub symbol_1: String,
+    pub address_1: String,
+    pub balance_1: Nat,
+    pub lp_fee_1: Nat,
+    pub price: f64,
+    pub lp_fee_bps: u8,
+}
+
+/// pools : (opt text) -> (variant { Ok : vec PoolReply; Err : text }) query
+pub type PoolsResult = Result<Vec<PoolReply>, String>;
+
+/// swap_async : (SwapArgs) -> (variant { Ok : nat64; Err : text })
+pub type SwapAsyncResult = Result<u64, String>;
+
+/// requests の Swap 応答のうち bot が使うフィールド
+#[derive(candid::CandidType, Deserialize, Debug, Clone)]
+pub struct SwapFillReply {
+    pub request_id: u64,
+    pub status: String,
+    pub pay_amount: Nat,
+    pub receive_amount: Nat,
 }
 
+/// requests の reply。swap_async の request は処理中なら Pending、終われば Swap
+#[derive(candid::CandidType, Deserialize, Debug, Clone)]
+pub enum RequestReply {
+    Pending,
+    Swap(SwapFillReply),
+}
+
+#[derive(candid::CandidType, Deserialize, Debug, Clone)]
+pub struct RequestsReply {
+    pub request_id: u64,
+    pub reply: RequestReply,
+}
+
+/// requests : (opt nat64) -> (variant { Ok : vec RequestsReply; Err : text }) query
+pub type RequestsResult = Result<Vec<RequestsReply>, String>;
+
+/// swap_async の約定結果。失敗した request は支払いを返金済みなので量は 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KongSwapFill {
+    pub succeeded: bool,
+    pub pay_amount: u128,
+    pub receive_amount: u128,
+}
+
+/// 型付きデコードに失敗して動的デコードに切り替えたことを一度だけ警告する
+static FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
 pub async fn fetch_pool_snapshot(
     client: &IcClient,
     kong_canister: &str,
@@ -43,11 +107,43 @@ pub async fn fetch_pool_snapshot(
     let raw = client
         .query_raw(kong_canister, "pools", args)
         .await
-        .map_err(|e| KongError::Client(e.to_string()))?;
+        .map_err(KongError::Client)?;
 
-    parse_pools(&raw, ticker)
+    match Decode!(&raw, PoolsResult) {
+        Ok(Ok(pools)) => snapshot_from_pools(&pools, ticker),
+        Ok(Err(msg)) => Err(KongError::Rejected(msg)),
+        Err(e) => {
+            if !FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "Kong pools の型付きデコードに失敗したため動的デコードで読みます (.did の変更を確認してください): {}",
+                    e
+                );
+            }
+            parse_pools(&raw, ticker)
+        }
+    }
+}
+
+fn snapshot_from_pools(pools: &[PoolReply], ticker: &str) -> Result<KongPoolSnapshot, KongError> {
+    let pool = pools
+        .iter()
+        .find(|p| p.symbol == ticker)
+        .ok_or_else(|| KongError::Decode(format!("pool {} が見つかりません", ticker)))?;
+    Ok(KongPoolSnapshot {
+        icp_balance: nat_to_f64(&pool.balance_1)?,
+        sns_balance: nat_to_f64(&pool.balance_0)?,
+        icp_lp_fee: nat_to_f64(&pool.lp_fee_1)?,
+        sns_lp_fee: nat_to_f64(&pool.lp_fee_0)?,
+        icp_raw: nat_to_u128(&pool.balance_1)?,
+        sns_raw: nat_to_u128(&pool.balance_0)?,
+        icp_lp_raw: nat_to_u128(&pool.lp_fee_1)?,
+        sns_lp_raw: nat_to_u128(&pool.lp_fee_0)?,
+        price_icp_per_sns: pool.price,
+        lp_fee_bps: pool.lp_fee_bps as u32,
+    })
 }
 
+/// 型付きデコードできなかったときの予備。フィールドをハッシュ id で探す
 fn parse_pools(raw: &[u8], ticker: &str) -> Result<KongPoolSnapshot, KongError> {
     let args = IDLArgs::from_bytes(raw).map_err(|e| KongError::Decode(e.to_string()))?;
     let first = args.args.first().ok_or(KongError::MissingFields)?;
@@ -88,10 +184,8 @@ fn parse_pools(raw: &[u8], ticker: &str) -> Result<KongPoolSnapshot, KongError>
 
     let sns = extract_nat(entry_record, 1_476_685_581u32).ok_or(KongError::MissingFields)?;
     let icp = extract_nat(entry_record, 1_476_685_582u32).ok_or(KongError::MissingFields)?;
-    let sns_lp_fee =
-        extract_nat(entry_record, 1_283_592_060u32).ok_or(KongError::MissingFields)?;
-    let icp_lp_fee =
-        extract_nat(entry_record, 1_283_592_061u32).ok_or(KongError::MissingFields)?;
+    let sns_lp_fee = extract_nat(entry_record, 1_283_592_060u32).ok_or(KongError::MissingFields)?;
+    let icp_lp_fee = extract_nat(entry_record, 1_283_592_061u32).ok_or(KongError::MissingFields)?;
     let price_icp_per_sns =
         extract_float(entry_record, 3_364_572_809u32).ok_or(KongError::MissingFields)?;
     let lp_fee_bps_nat =
@@ -176,3 +270,38 @@ fn extract_float(entries: &[IDLField], id: u32) -> Option<f64> {
     }
     None
 }
+
+/// swap_async の request id の約定量を requests から読む。まだ終わっていなければ None
+pub async fn fetch_swap_fill(
+    client: &IcClient,
+    kong_canister: &str,
+    request_id: u64,
+) -> Result<Option<KongSwapFill>, KongError> {
+    let args = Encode!(&Some(request_id)).map_err(|e| KongError::Decode(e.to_string()))?;
+    let raw = client
+        .query_raw(kong_canister, "requests", args)
+        .await
+        .map_err(KongError::Client)?;
+    let replies = match Decode!(&raw, RequestsResult) {
+        Ok(Ok(replies)) => replies,
+        Ok(Err(msg)) => return Err(KongError::Rejected(msg)),
+        Err(e) => return Err(KongError::Decode(e.to_string())),
+    };
+    let reply = replies
+        .into_iter()
+        .find(|r| r.request_id == request_id)
+        .ok_or_else(|| KongError::Decode(format!("request {} が見つかりません", request_id)))?;
+    match reply.reply {
+        RequestReply::Swap(swap) if swap.status == "Success" => Ok(Some(KongSwapFill {
+            succeeded: true,
+            pay_amount: nat_to_u128(&swap.pay_amount)?,
+            receive_amount: nat_to_u128(&swap.receive_amount)?,
+        })),
+        RequestReply::Swap(swap) if swap.status == "Failed" => Ok(Some(KongSwapFill {
+            succeeded: false,
+            pay_amount: 0,
+            receive_amount: 0,
+        })),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,233 @@
+// どこで: ICRC-1 台帳へのクエリ
+// 何を: icrc1_balance_of で残高、icrc2_allowance / icrc2_approve で allowance を扱う
+// なぜ: swap 前後の残高差から実際の損益を計算し、swap に必要な allowance を張っておくため
+
+use candid::{CandidType, Decode, Encode, IDLArgs, Nat, Principal};
+use sha2::{Digest, Sha224};
+use thiserror::Error;
+
+use super::agent::IcClient;
+
+#[derive(Debug, Error)]
+pub enum LedgerError {
+    #[error("IC クライアントエラー: {0}")]
+    Client(String),
+    #[error("candid 変換失敗: {0}")]
+    Candid(String),
+    #[error("principal が不正です ({0}): {1}")]
+    Principal(String, String),
+}
+
+#[derive(CandidType, Debug, Clone)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl Account {
+    pub fn new(owner: Principal, subaccount: Option<Vec<u8>>) -> Self {
+        Account { owner, subaccount }
+    }
+}
+
+impl Account {
+    /// ICP 台帳の account identifier（16 進 64 桁）。
+    /// sha224("\x0Aaccount-id" + principal + subaccount) の前に CRC32 を付ける
+    pub fn account_identifier(&self) -> String {
+        let mut hasher = Sha224::new();
+        hasher.update(b"\x0Aaccount-id");
+        hasher.update(self.owner.as_slice());
+        hasher.update(self.subaccount_bytes());
+        let hash = hasher.finalize();
+        let mut id = crc32(&hash).to_be_bytes().to_vec();
+        id.extend_from_slice(&hash);
+        hex(&id)
+    }
+
+    /// ICRC-1 のテキスト表記。既定 subaccount なら principal のみ、
+    /// それ以外は "<principal>-<checksum>.<先頭の 0 を除いた subaccount>"
+    pub fn to_text(&self) -> String {
+        let sub = self.subaccount_bytes();
+        if sub.iter().all(|b| *b == 0) {
+            return self.owner.to_text();
+        }
+        let mut data = self.owner.as_slice().to_vec();
+        data.extend_from_slice(&sub);
+        let checksum = base32(&crc32(&data).to_be_bytes());
+        let sub_hex = hex(&sub);
+        format!(
+            "{}-{}.{}",
+            self.owner.to_text(),
+            checksum,
+            sub_hex.trim_start_matches('0')
+        )
+    }
+
+    fn subaccount_bytes(&self) -> [u8; 32] {
+        let mut sub = [0u8; 32];
+        if let Some(s) = &self.subaccount {
+            let n = s.len().min(32);
+            sub[..n].copy_from_slice(&s[..n]);
+        }
+        sub
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// RFC 4648 base32（小文字・パディング無し）
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for b in bytes {
+        buffer = (buffer << 8) | *b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+/// CRC-32 (IEEE)
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Account {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+pub async fn fetch_balance(
+    client: &IcClient,
+    ledger: &str,
+    owner: impl Into<Account>,
+) -> Result<u128, LedgerError> {
+    let account: Account = owner.into();
+    let args = Encode!(&account).map_err(|e| LedgerError::Candid(e.to_string()))?;
+    let raw = client
+        .query_raw(ledger, "icrc1_balance_of", args)
+        .await
+        .map_err(|e| LedgerError::Client(e.to_string()))?;
+    let balance = Decode!(&raw, Nat).map_err(|e| LedgerError::Candid(e.to_string()))?;
+    nat_to_u128(&balance)
+}
+
+/// owner（subaccount 込み）から spender canister への allowance
+pub async fn fetch_allowance(
+    client: &IcClient,
+    ledger: &str,
+    owner: impl Into<Account>,
+    spender_canister: &str,
+) -> Result<u128, LedgerError> {
+    #[derive(CandidType)]
+    struct AllowanceArgs {
+        account: Account,
+        spender: Account,
+    }
+    #[derive(CandidType, candid::Deserialize)]
+    struct Allowance {
+        allowance: Nat,
+        #[allow(dead_code)]
+        expires_at: Option<u64>,
+    }
+    let args = Encode!(&AllowanceArgs {
+        account: owner.into(),
+        spender: Account {
+            owner: parse_principal(spender_canister)?,
+            subaccount: None,
+        },
+    })
+    .map_err(|e| LedgerError::Candid(e.to_string()))?;
+    let raw = client
+        .query_raw(ledger, "icrc2_allowance", args)
+        .await
+        .map_err(|e| LedgerError::Client(e.to_string()))?;
+    let allowance = Decode!(&raw, Allowance).map_err(|e| LedgerError::Candid(e.to_string()))?;
+    nat_to_u128(&allowance.allowance)
+}
+
+/// spender canister への allowance を amount に張り直す。応答は candid のテキスト表記で返す
+pub async fn approve(
+    client: &IcClient,
+    ledger: &str,
+    spender_canister: &str,
+    amount: u128,
+) -> Result<String, LedgerError> {
+    approve_from(client, ledger, None, spender_canister, amount).await
+}
+
+/// approve を呼び出し元の subaccount から張る
+pub async fn approve_from(
+    client: &IcClient,
+    ledger: &str,
+    from_subaccount: Option<Vec<u8>>,
+    spender_canister: &str,
+    amount: u128,
+) -> Result<String, LedgerError> {
+    #[derive(CandidType)]
+    struct ApproveArgs {
+        fee: Option<u128>,
+        memo: Option<Vec<u8>>,
+        from_subaccount: Option<Vec<u8>>,
+        created_at_time: Option<u64>,
+        amount: u128,
+        spender: Account,
+    }
+    let now_nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| LedgerError::Candid(e.to_string()))?
+        .as_nanos() as u64;
+    let args = Encode!(&ApproveArgs {
+        fee: None,
+        memo: None,
+        from_subaccount,
+        created_at_time: Some(now_nanos),
+        amount,
+        spender: Account {
+            owner: parse_principal(spender_canister)?,
+            subaccount: None,
+        },
+    })
+    .map_err(|e| LedgerError::Candid(e.to_string()))?;
+    let raw = client
+        .update_raw(ledger, "icrc2_approve", args)
+        .await
+        .map_err(|e| LedgerError::Client(e.to_string()))?;
+    Ok(IDLArgs::from_bytes(&raw)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|e| format!("decode err: {}", e)))
+}
+
+fn parse_principal(text: &str) -> Result<Principal, LedgerError> {
+    Principal::from_text(text).map_err(|e| LedgerError::Principal(text.to_string(), e.to_string()))
+}
+
+fn nat_to_u128(n: &Nat) -> Result<u128, LedgerError> {
+    n.0.to_string()
+        .parse::<u128>()
+        .map_err(|e| LedgerError::Candid(e.to_string()))
+}
@@ -0,0 +1,182 @@
+// どこで: CanisterTransport のメモリ内実装
+// 何を: (canister, method) ごとに用意した candid 応答を返し、呼び出しを記録する
+// なぜ: ネットワーク無しで Trade::tick から swap までを決定的に動かして確かめるため
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ic_agent::export::Principal;
+use ic_agent::RequestId;
+
+use super::agent::IcClientError;
+use super::transport::{BoxFuture, CanisterTransport};
+
+/// 記録された呼び出し 1 回分
+#[derive(Debug, Clone)]
+pub struct MockCall {
+    pub canister: String,
+    pub method: String,
+    /// "query" か "update"
+    pub kind: &'static str,
+    pub args: Vec<u8>,
+}
+
+type Key = (String, String);
+
+#[derive(Default)]
+struct MockState {
+    /// 一度だけ返す応答。空になったら replies を使う
+    queued: HashMap<Key, VecDeque<Result<Vec<u8>, IcClientError>>>,
+    /// 何度でも返す応答
+    replies: HashMap<Key, Vec<u8>>,
+    /// submit 済みで wait 待ちの結果（RequestId は Hash を持たないので hex で引く）
+    pending: HashMap<String, Result<Vec<u8>, IcClientError>>,
+    calls: Vec<MockCall>,
+    next_request: u64,
+}
+
+/// 応答が用意されていない呼び出しは canister reject 扱いで失敗する
+pub struct MockTransport {
+    state: Mutex<MockState>,
+    principal: Principal,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        MockTransport::new()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport {
+            state: Mutex::new(MockState::default()),
+            principal: Principal::anonymous(),
+        }
+    }
+
+    pub fn with_principal(mut self, principal: Principal) -> Self {
+        self.principal = principal;
+        self
+    }
+
+    /// (canister, method) に常に返す candid 応答を設定する（上書き）
+    pub fn reply(&self, canister: &str, method: &str, raw: Vec<u8>) {
+        self.state()
+            .replies
+            .insert((canister.to_string(), method.to_string()), raw);
+    }
+
+    /// 次の 1 回だけ返す結果を積む。エラーも積めるので再試行の確認にも使える
+    pub fn push_reply(&self, canister: &str, method: &str, res: Result<Vec<u8>, IcClientError>) {
+        self.state()
+            .queued
+            .entry((canister.to_string(), method.to_string()))
+            .or_default()
+            .push_back(res);
+    }
+
+    /// これまでの呼び出し（古い順）
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state().calls.clone()
+    }
+
+    /// 指定 method の呼び出し回数
+    pub fn call_count(&self, method: &str) -> usize {
+        self.state()
+            .calls
+            .iter()
+            .filter(|c| c.method == method)
+            .count()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn next(
+        &self,
+        canister: &str,
+        method: &str,
+        kind: &'static str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, IcClientError> {
+        let mut state = self.state();
+        state.calls.push(MockCall {
+            canister: canister.to_string(),
+            method: method.to_string(),
+            kind,
+            args,
+        });
+        let key = (canister.to_string(), method.to_string());
+        if let Some(res) = state.queued.get_mut(&key).and_then(|q| q.pop_front()) {
+            return res;
+        }
+        match state.replies.get(&key) {
+            Some(raw) => Ok(raw.clone()),
+            None => Err(IcClientError::Reject {
+                method: method.to_string(),
+                code: ic_agent::agent::RejectCode::DestinationInvalid,
+                message: format!("mock: {} {} の応答が未設定です", canister, method),
+            }),
+        }
+    }
+}
+
+impl CanisterTransport for MockTransport {
+    fn query<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        _call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move { self.next(canister, method, "query", args) })
+    }
+
+    /// 結果は submit 時点で確定させ、wait で返す
+    fn submit<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        _ingress_expiry: Duration,
+        _call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<RequestId, IcClientError>> {
+        Box::pin(async move {
+            let res = self.next(canister, method, "update", args);
+            let mut state = self.state();
+            state.next_request += 1;
+            let mut id = [0u8; 32];
+            id[24..].copy_from_slice(&state.next_request.to_be_bytes());
+            let request_id = RequestId::new(&id);
+            state.pending.insert(String::from(request_id), res);
+            Ok(request_id)
+        })
+    }
+
+    fn wait<'a>(
+        &'a self,
+        _canister: &'a str,
+        method: &'a str,
+        request_id: RequestId,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move {
+            self.state()
+                .pending
+                .remove(&String::from(request_id))
+                .unwrap_or_else(|| {
+                    Err(IcClientError::Reject {
+                        method: method.to_string(),
+                        code: ic_agent::agent::RejectCode::DestinationInvalid,
+                        message: format!("mock: 不明な request id {}", String::from(request_id)),
+                    })
+                })
+        })
+    }
+
+    fn principal(&self) -> Result<Principal, String> {
+        Ok(self.principal)
+    }
+}
@@ -1,8 +1,12 @@
 // どこで: IC 呼び出しのクライアントをまとめるモジュール
-// 何を: agent 初期化、ICS/Kong への query/update、swap 呼び出し
+// 何を: 呼び出し層 (transport と mock)、エンドポイント選択、ICS/Kong への query/update、swap 呼び出し、台帳残高
 // なぜ: 外部依存をここに閉じ込め、上位ロジックを簡潔にするため
 
 pub mod agent;
+pub mod endpoint;
 pub mod ics;
 pub mod kong;
+pub mod ledger;
+pub mod mock;
 pub mod swap;
+pub mod transport;
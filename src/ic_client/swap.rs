@@ -1,23 +1,91 @@
 // どこで: スワップ系 update 呼び出し
-// 何を: Kong の swap_async と ICPSwap の swap を叩く
+// 何を: Kong の swap_async と ICPSwap の swap を叩き、応答を .did に合わせた型でデコードする
 // なぜ: 取引実行を Rust から完結させるため
 
-use candid::{Encode, IDLArgs, IDLValue};
+use candid::types::Label;
+use candid::{Decode, Encode, IDLArgs, IDLValue, Nat};
 use serde::Serialize;
+use std::fmt;
 use thiserror::Error;
 
-use super::agent::IcClient;
+use super::agent::{IcClient, IcClientError, SubmittedUpdate};
+use super::ics::IcsResult;
+use super::kong::SwapAsyncResult;
 
 #[derive(Debug, Error)]
 pub enum SwapError {
     #[error("IC クライアントエラー: {0}")]
-    Client(String),
+    Client(#[from] IcClientError),
     #[error("candid エンコード失敗: {0}")]
     Encode(String),
     #[error("swap エラー: {0}")]
     Swap(String),
 }
 
+impl SwapError {
+    /// メトリクス用のエラー分類
+    pub fn class(&self) -> &'static str {
+        match self {
+            SwapError::Client(e) => e.class(),
+            SwapError::Encode(_) => "encode",
+            SwapError::Swap(_) => "rejected",
+        }
+    }
+}
+
+/// swap 応答。amount は ok 側が nat のときだけ入る
+/// （ICPSwap は受取量、Kong の swap_async は request id）
+#[derive(Debug, Clone)]
+pub struct SwapReply {
+    pub decoded: String,
+    pub amount: Option<u128>,
+}
+
+impl fmt::Display for SwapReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.decoded)
+    }
+}
+
+impl SwapReply {
+    fn from_raw(raw: &[u8]) -> Self {
+        match IDLArgs::from_bytes(raw) {
+            Ok(args) => SwapReply {
+                amount: ok_nat(&args),
+                decoded: args.to_string(),
+            },
+            Err(e) => SwapReply {
+                decoded: format!("decode err: {}", e),
+                amount: None,
+            },
+        }
+    }
+}
+
+/// variant { ok = nat } / variant { Ok = nat64 } から数値を取り出す
+fn ok_nat(args: &IDLArgs) -> Option<u128> {
+    let IDLValue::Variant(var) = args.args.first()? else {
+        return None;
+    };
+    let field = var.0.as_ref();
+    let is_ok = match &field.id {
+        Label::Named(name) => name == "ok" || name == "Ok",
+        Label::Id(id) => *id == 24_860u32 || *id == 17_724u32,
+        Label::Unnamed(_) => false,
+    };
+    if !is_ok {
+        return None;
+    }
+    match &field.val {
+        IDLValue::Nat(n) => n.0.to_string().parse::<u128>().ok(),
+        IDLValue::Nat64(v) => Some(*v as u128),
+        _ => None,
+    }
+}
+
+/// Kong の swap_async の method 名（送信済み update の応答デコードの切り替えにも使う）
+pub const KONG_SWAP_METHOD: &str = "swap_async";
+
 pub async fn swap_kong(
     client: &IcClient,
     kong_canister: &str,
@@ -25,7 +93,28 @@ pub async fn swap_kong(
     receive_token: &str,
     pay_amount: u128,
     min_receive_amount: u128,
-) -> Result<String, SwapError> {
+) -> Result<SwapReply, SwapError> {
+    let submitted = submit_swap_kong(
+        client,
+        kong_canister,
+        pay_token,
+        receive_token,
+        pay_amount,
+        min_receive_amount,
+    )
+    .await?;
+    finish_swap(client, &submitted).await
+}
+
+/// swap_async を送信だけして request id を返す（結果は finish_swap で受け取る）
+pub async fn submit_swap_kong(
+    client: &IcClient,
+    kong_canister: &str,
+    pay_token: &str,
+    receive_token: &str,
+    pay_amount: u128,
+    min_receive_amount: u128,
+) -> Result<SubmittedUpdate, SwapError> {
     #[derive(candid::CandidType)]
     struct SwapParams {
         receive_token: String,
@@ -43,31 +132,88 @@ pub async fn swap_kong(
 
     let args = Encode!(&params).map_err(|e| SwapError::Encode(e.to_string()))?;
 
-    let raw = client
-        .update_raw(kong_canister, "swap_async", args)
-        .await
-        .map_err(|e| SwapError::Client(e.to_string()))?;
+    Ok(client
+        .submit_update(kong_canister, KONG_SWAP_METHOD, args)
+        .await?)
+}
+
+/// 送信済みの swap の結果を待ってデコードする。再起動後に journal から復元したものにも使う
+pub async fn finish_swap(
+    client: &IcClient,
+    submitted: &SubmittedUpdate,
+) -> Result<SwapReply, SwapError> {
+    let raw = client.poll_update(submitted).await?;
+    if submitted.method == KONG_SWAP_METHOD {
+        decode_kong_reply(&raw)
+    } else {
+        decode_ics_reply(&raw)
+    }
+}
+
+/// swap 応答のテキスト表記（ジャーナルとログ用）
+fn idl_text(raw: &[u8]) -> String {
+    IDLArgs::from_bytes(raw)
+        .map(|args| args.to_string())
+        .unwrap_or_else(|e| format!("decode err: {}", e))
+}
+
+/// Kong は Err variant で失敗を返すので、それを SwapError::Swap にする
+fn decode_kong_reply(raw: &[u8]) -> Result<SwapReply, SwapError> {
+    match Decode!(raw, SwapAsyncResult) {
+        Ok(Ok(request_id)) => Ok(SwapReply {
+            decoded: idl_text(raw),
+            amount: Some(request_id as u128),
+        }),
+        Ok(Err(msg)) => Err(SwapError::Swap(format!(
+            "{} | decoded={}",
+            msg,
+            idl_text(raw)
+        ))),
+        Err(_) => decode_kong_reply_dynamic(raw),
+    }
+}
+
+/// ICPSwap の swap / depositFromAndSwap は ok = 受取量、err = Error
+fn decode_ics_reply(raw: &[u8]) -> Result<SwapReply, SwapError> {
+    match Decode!(raw, IcsResult<Nat>) {
+        Ok(IcsResult::Ok(amount)) => Ok(SwapReply {
+            decoded: idl_text(raw),
+            amount: amount.0.to_string().parse::<u128>().ok(),
+        }),
+        Ok(IcsResult::Err(e)) => Err(SwapError::Swap(format!(
+            "{:?} | decoded={}",
+            e,
+            idl_text(raw)
+        ))),
+        Err(_) => Ok(SwapReply::from_raw(raw)),
+    }
+}
 
-    let decoded_args = IDLArgs::from_bytes(&raw).map_err(|e| SwapError::Client(e.to_string()))?;
+/// 型付きデコードできなかったときの予備
+fn decode_kong_reply_dynamic(raw: &[u8]) -> Result<SwapReply, SwapError> {
+    let decoded_args =
+        IDLArgs::from_bytes(raw).map_err(|e| IcClientError::decode(KONG_SWAP_METHOD, e))?;
     let mut is_err = false;
     let mut err_msg = String::new();
-    if let Some(first) = decoded_args.args.get(0) {
-        if let IDLValue::Variant(var) = first {
-            let label = &var.0.id;
-            // Err が Named/Id のどちらでも検出する
-            if label == &candid::types::Label::Named("err".to_string())
-                || label == &candid::types::Label::Id(3_456_837u32)
-            {
-                is_err = true;
-                err_msg = format!("{}", var.1);
-            }
+    if let Some(IDLValue::Variant(var)) = decoded_args.args.first() {
+        let label = &var.0.id;
+        // Err が Named/Id のどちらでも検出する
+        if label == &Label::Named("err".to_string()) || label == &Label::Id(3_456_837u32) {
+            is_err = true;
+            err_msg = format!("{}", var.1);
         }
     }
     let decoded = decoded_args.to_string();
     if is_err {
-        Err(SwapError::Swap(format!("{} | decoded={}", err_msg, decoded)))
+        Err(SwapError::Swap(format!(
+            "{} | decoded={}",
+            err_msg, decoded
+        )))
     } else {
-        Ok(decoded)
+        Ok(SwapReply {
+            amount: ok_nat(&decoded_args),
+            decoded,
+        })
     }
 }
 
@@ -77,7 +223,7 @@ pub async fn swap_icps(
     amount_in: u128,
     min_amount_out: u128,
     zero_for_one: bool,
-) -> Result<String, SwapError> {
+) -> Result<SwapReply, SwapError> {
     #[derive(candid::CandidType, Serialize)]
     struct SwapParams {
         #[serde(rename = "amountIn")]
@@ -96,15 +242,9 @@ pub async fn swap_icps(
 
     let args = Encode!(&params).map_err(|e| SwapError::Encode(e.to_string()))?;
 
-    let raw = client
-        .update_raw(lp_canister, "swap", args)
-        .await
-        .map_err(|e| SwapError::Client(e.to_string()))?;
+    let raw = client.update_raw(lp_canister, "swap", args).await?;
 
-    let decoded = IDLArgs::from_bytes(&raw)
-        .map(|v| v.to_string())
-        .unwrap_or_else(|e| format!("decode err: {}", e));
-    Ok(decoded)
+    decode_ics_reply(&raw)
 }
 
 pub async fn swap_icps_deposit(
@@ -115,7 +255,30 @@ pub async fn swap_icps_deposit(
     zero_for_one: bool,
     token_in_fee: u128,
     token_out_fee: u128,
-) -> Result<String, SwapError> {
+) -> Result<SwapReply, SwapError> {
+    let submitted = submit_swap_icps_deposit(
+        client,
+        lp_canister,
+        amount_in,
+        min_amount_out,
+        zero_for_one,
+        token_in_fee,
+        token_out_fee,
+    )
+    .await?;
+    finish_swap(client, &submitted).await
+}
+
+/// depositFromAndSwap を送信だけして request id を返す（結果は finish_swap で受け取る）
+pub async fn submit_swap_icps_deposit(
+    client: &IcClient,
+    lp_canister: &str,
+    amount_in: u128,
+    min_amount_out: u128,
+    zero_for_one: bool,
+    token_in_fee: u128,
+    token_out_fee: u128,
+) -> Result<SubmittedUpdate, SwapError> {
     #[derive(candid::CandidType, Serialize)]
     struct SwapParams {
         #[serde(rename = "tokenInFee")]
@@ -140,13 +303,7 @@ pub async fn swap_icps_deposit(
 
     let args = Encode!(&params).map_err(|e| SwapError::Encode(e.to_string()))?;
 
-    let raw = client
-        .update_raw(lp_canister, "depositFromAndSwap", args)
-        .await
-        .map_err(|e| SwapError::Client(e.to_string()))?;
-
-    let decoded = IDLArgs::from_bytes(&raw)
-        .map(|v| v.to_string())
-        .unwrap_or_else(|e| format!("decode err: {}", e));
-    Ok(decoded)
+    Ok(client
+        .submit_update(lp_canister, "depositFromAndSwap", args)
+        .await?)
 }
@@ -0,0 +1,206 @@
+// どこで: IcClient の下で実際に canister を呼ぶ層
+// 何を: CanisterTransport trait と、ic-agent + 複数エンドポイントによる本番実装
+// なぜ: 再試行・タイムアウト・メトリクスは IcClient に残したまま、オフラインでは mock に差し替えられるようにするため
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ic_agent::export::Principal;
+use ic_agent::{Agent, Identity, RequestId};
+use tokio::time::timeout;
+
+use super::agent::IcClientError;
+use super::endpoint::{Endpoint, EndpointHealth, EndpointPool};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// canister 呼び出しの 1 回分。再試行やメトリクスは呼び出し側 (IcClient) が持つ
+pub trait CanisterTransport: Send + Sync {
+    /// query を 1 回投げる。call_timeout を過ぎたら Timeout
+    fn query<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>>;
+
+    /// update を送信して request id を返す（結果は待たない）
+    fn submit<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        ingress_expiry: Duration,
+        call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<RequestId, IcClientError>>;
+
+    /// 送信済み update の結果が出るまで待つ。通信エラーで返った場合は IcClient が再度呼ぶ
+    fn wait<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        request_id: RequestId,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>>;
+
+    /// 署名に使う principal
+    fn principal(&self) -> Result<Principal, String>;
+
+    /// query 応答のノード署名を検証しているか
+    fn verifies_queries(&self) -> bool {
+        false
+    }
+
+    /// エンドポイントを持つ実装だけが返す
+    fn endpoint_health(&self) -> Vec<EndpointHealth> {
+        Vec::new()
+    }
+}
+
+/// ic-agent による実装。同じ identity で URL ごとに agent を作り、呼び出しごとに速くて健全なものを選ぶ
+pub struct AgentTransport {
+    endpoints: EndpointPool,
+    verify_query_signatures: bool,
+}
+
+impl AgentTransport {
+    pub async fn connect(
+        urls: &[String],
+        identity: Arc<dyn Identity + Send + Sync>,
+        fetch_root_key: bool,
+        verify_query_signatures: bool,
+    ) -> Result<Self, IcClientError> {
+        if urls.is_empty() {
+            return Err(IcClientError::Init(
+                "API エンドポイントが指定されていません".to_string(),
+            ));
+        }
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            let agent = Agent::builder()
+                .with_url(url.as_str())
+                .with_arc_identity(identity.clone())
+                .with_verify_query_signatures(verify_query_signatures)
+                .build()
+                .map_err(|e| IcClientError::Init(format!("{}: {}", url, e)))?;
+            if fetch_root_key {
+                agent
+                    .fetch_root_key()
+                    .await
+                    .map_err(|e| IcClientError::Init(format!("root key ({}): {}", url, e)))?;
+            }
+            endpoints.push(Endpoint::new(url.clone(), agent));
+        }
+        Ok(AgentTransport {
+            endpoints: EndpointPool::new(endpoints),
+            verify_query_signatures,
+        })
+    }
+
+    fn record<T>(&self, idx: usize, res: &Result<T, IcClientError>, latency: Option<Duration>) {
+        match res {
+            Err(e) if e.is_endpoint_failure() => self.endpoints.record_failure(idx, &e.to_string()),
+            _ => self.endpoints.record_ok(idx, latency),
+        }
+    }
+}
+
+impl CanisterTransport for AgentTransport {
+    fn query<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move {
+            let canister_id = parse_canister(canister)?;
+            let idx = self.endpoints.pick();
+            let started = Instant::now();
+            let call = self
+                .endpoints
+                .get(idx)
+                .agent
+                .query(&canister_id, method)
+                .with_arg(args)
+                .call();
+            let res = match timeout(call_timeout, call).await {
+                Ok(res) => res.map_err(|e| IcClientError::from_agent(method, e)),
+                Err(_) => Err(IcClientError::Timeout(method.to_string())),
+            };
+            self.record(idx, &res, Some(started.elapsed()));
+            res
+        })
+    }
+
+    fn submit<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        ingress_expiry: Duration,
+        call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<RequestId, IcClientError>> {
+        Box::pin(async move {
+            let canister_id = parse_canister(canister)?;
+            let idx = self.endpoints.pick();
+            let started = Instant::now();
+            let call = self
+                .endpoints
+                .get(idx)
+                .agent
+                .update(&canister_id, method)
+                .with_arg(args)
+                .expire_after(ingress_expiry)
+                .call();
+            let res = match timeout(call_timeout, call).await {
+                Ok(res) => res.map_err(|e| IcClientError::from_agent(method, e)),
+                Err(_) => Err(IcClientError::Timeout(method.to_string())),
+            };
+            self.record(idx, &res, Some(started.elapsed()));
+            res
+        })
+    }
+
+    /// ポーリングは request id さえあればどのエンドポイントからでもできる
+    fn wait<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        request_id: RequestId,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move {
+            let canister_id = parse_canister(canister)?;
+            let idx = self.endpoints.pick();
+            let res = self
+                .endpoints
+                .get(idx)
+                .agent
+                .wait(request_id, canister_id)
+                .await
+                .map_err(|e| IcClientError::from_agent(method, e));
+            // 待ち時間は canister 側の処理時間なのでレイテンシには含めない
+            self.record(idx, &res, None);
+            res
+        })
+    }
+
+    fn principal(&self) -> Result<Principal, String> {
+        self.endpoints.get(0).agent.get_principal()
+    }
+
+    fn verifies_queries(&self) -> bool {
+        self.verify_query_signatures
+    }
+
+    fn endpoint_health(&self) -> Vec<EndpointHealth> {
+        self.endpoints.health()
+    }
+}
+
+fn parse_canister(canister: &str) -> Result<Principal, IcClientError> {
+    Principal::from_text(canister)
+        .map_err(|e| IcClientError::InvalidCanister(canister.to_string(), e.to_string()))
+}
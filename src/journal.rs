@@ -0,0 +1,542 @@
+// どこで: 取引ジャーナル (ローカル SQLite)
+// 何を: 評価した機会と swap 試行 (入力・期待値・最低受取・応答・エラー・時刻・request id) を保存し、ペア/期間で検索する
+// なぜ: info ログと Discord 通知だけでは約定履歴を後から追跡・集計できないため
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("SQLite エラー: {0}")]
+    Sqlite(String),
+    #[error("日付の解析に失敗しました: {0}")]
+    Date(String),
+    #[error("ジャーナルの処理スレッドが異常終了しました: {0}")]
+    Task(String),
+}
+
+impl From<rusqlite::Error> for JournalError {
+    fn from(e: rusqlite::Error) -> Self {
+        JournalError::Sqlite(e.to_string())
+    }
+}
+
+/// tick ごとに評価した裁定機会
+#[derive(Debug, Clone)]
+pub struct OpportunityRecord {
+    pub ts_ms: i64,
+    pub pair: String,
+    pub direction: String,
+    pub amount_in_e8: f64,
+    pub expected_mid_e8: f64,
+    pub expected_final_e8: f64,
+    pub expected_profit_e8: f64,
+    pub executed: bool,
+}
+
+/// 2 レッグ分の swap 試行 (Kong / ICS)
+#[derive(Debug, Clone)]
+pub struct SwapRecord {
+    pub id: i64,
+    pub opportunity_id: Option<i64>,
+    pub pair: String,
+    pub direction: String,
+    pub amount_in_e8: u128,
+    pub expected_mid_e8: f64,
+    pub expected_final_e8: f64,
+    pub min_mid_e8: u128,
+    pub min_final_e8: u128,
+    pub kong_reply: Option<String>,
+    pub kong_error: Option<String>,
+    pub ics_reply: Option<String>,
+    pub ics_error: Option<String>,
+    pub started_ms: i64,
+    pub finished_ms: i64,
+}
+
+impl SwapRecord {
+    pub fn succeeded(&self) -> bool {
+        self.kong_error.is_none() && self.ics_error.is_none()
+    }
+}
+
+/// swap レッグの update 送信状況
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestStatus {
+    /// 送信済みで結果未確認（途中でプロセスが落ちた場合もここに残る）
+    Pending,
+    Replied,
+    Failed,
+    /// 結果待ちがタイムアウトした。ingress expiry までは約定しうる
+    Unknown,
+}
+
+impl RequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestStatus::Pending => "pending",
+            RequestStatus::Replied => "replied",
+            RequestStatus::Failed => "failed",
+            RequestStatus::Unknown => "unknown",
+        }
+    }
+}
+
+/// 送信した swap レッグ 1 本分。request id から再起動後も結果を確認できる
+#[derive(Debug, Clone)]
+pub struct SwapRequestRecord {
+    pub id: i64,
+    pub swap_id: Option<i64>,
+    pub pair: String,
+    /// kong / ics
+    pub leg: String,
+    pub canister: String,
+    pub method: String,
+    /// hex 表記
+    pub request_id: String,
+    pub submitted_ms: i64,
+    pub status: String,
+    pub reply: Option<String>,
+    pub error: Option<String>,
+    pub finished_ms: Option<i64>,
+}
+
+/// 1 回の swap 試行の実現損益 (ICP e8 換算)
+#[derive(Debug, Clone)]
+pub struct PnlRecord {
+    pub swap_id: Option<i64>,
+    pub ts_ms: i64,
+    pub pair: String,
+    pub direction: String,
+    pub icp_delta_e8: i64,
+    pub sns_delta_e8: i64,
+    /// SNS 1 e8 あたりの ICP e8 (Kong のリザーブ比)
+    pub sns_price_icp: f64,
+    pub realized_pnl_e8: f64,
+    pub expected_profit_e8: f64,
+    pub fees_e8: f64,
+    pub slippage_e8: f64,
+    pub kong_fill_e8: Option<u128>,
+    pub ics_fill_e8: Option<u128>,
+}
+
+/// ペア・日 (UTC) 単位の損益集計
+#[derive(Debug, Clone)]
+pub struct PnlSummaryRow {
+    pub pair: String,
+    pub day: String,
+    pub trades: u64,
+    pub realized_pnl_e8: f64,
+    pub expected_profit_e8: f64,
+    pub fees_e8: f64,
+    pub slippage_e8: f64,
+}
+
+/// 検索条件（いずれも省略可）
+#[derive(Debug, Clone, Default)]
+pub struct TradeFilter {
+    pub pair: Option<String>,
+    pub from_ms: Option<i64>,
+    pub to_ms: Option<i64>,
+}
+
+impl TradeFilter {
+    /// [from_ms, to_ms) の範囲
+    pub fn between(from_ms: i64, to_ms: i64) -> Self {
+        TradeFilter {
+            pair: None,
+            from_ms: Some(from_ms),
+            to_ms: Some(to_ms - 1),
+        }
+    }
+
+    /// YYYY-MM-DD (UTC) の範囲指定から作る。to は当日の終わりまで含める
+    pub fn from_dates(
+        pair: Option<String>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Self, JournalError> {
+        let from_ms = from.map(day_start_ms).transpose()?;
+        let to_ms = to
+            .map(|d| day_start_ms(d).map(|ms| ms + 86_400_000 - 1))
+            .transpose()?;
+        Ok(TradeFilter {
+            pair,
+            from_ms,
+            to_ms,
+        })
+    }
+}
+
+pub struct Journal {
+    conn: Mutex<Connection>,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Self, JournalError> {
+        let conn = Connection::open(path)?;
+        // tick ごとの書き込みが多いので WAL にしておく
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        // 以前の DB の pnl には kong_fill_e8 が無い
+        if conn
+            .prepare("SELECT kong_fill_e8 FROM pnl LIMIT 0")
+            .is_err()
+        {
+            conn.execute_batch("ALTER TABLE pnl ADD COLUMN kong_fill_e8 INTEGER")?;
+        }
+        Ok(Journal {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// 非同期の処理からは f を blocking スレッドで動かす（SQLite の待ちで tokio のワーカーを止めないように）
+    pub async fn run<T, F>(self: &Arc<Self>, f: F) -> Result<T, JournalError>
+    where
+        F: FnOnce(&Journal) -> Result<T, JournalError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let journal = self.clone();
+        tokio::task::spawn_blocking(move || f(&journal))
+            .await
+            .map_err(|e| JournalError::Task(e.to_string()))?
+    }
+
+    pub fn record_opportunity(&self, rec: &OpportunityRecord) -> Result<i64, JournalError> {
+        let conn = self.lock();
+        conn.execute(
+            "INSERT INTO opportunities
+                (ts_ms, pair, direction, amount_in_e8, expected_mid_e8, expected_final_e8,
+                 expected_profit_e8, executed)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                rec.ts_ms,
+                rec.pair,
+                rec.direction,
+                rec.amount_in_e8,
+                rec.expected_mid_e8,
+                rec.expected_final_e8,
+                rec.expected_profit_e8,
+                rec.executed,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn record_swap(&self, rec: &SwapRecord) -> Result<i64, JournalError> {
+        let conn = self.lock();
+        conn.execute(
+            "INSERT INTO swaps
+                (opportunity_id, pair, direction, amount_in_e8, expected_mid_e8, expected_final_e8,
+                 min_mid_e8, min_final_e8, kong_reply, kong_error, ics_reply, ics_error,
+                 started_ms, finished_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                rec.opportunity_id,
+                rec.pair,
+                rec.direction,
+                rec.amount_in_e8 as i64,
+                rec.expected_mid_e8,
+                rec.expected_final_e8,
+                rec.min_mid_e8 as i64,
+                rec.min_final_e8 as i64,
+                rec.kong_reply,
+                rec.kong_error,
+                rec.ics_reply,
+                rec.ics_error,
+                rec.started_ms,
+                rec.finished_ms,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// 送信直後に request id を残す。戻り値は finish_request / link_requests に渡す id
+    pub fn record_request(&self, rec: &SwapRequestRecord) -> Result<i64, JournalError> {
+        let conn = self.lock();
+        conn.execute(
+            "INSERT INTO swap_requests
+                (swap_id, pair, leg, canister, method, request_id, submitted_ms, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                rec.swap_id,
+                rec.pair,
+                rec.leg,
+                rec.canister,
+                rec.method,
+                rec.request_id,
+                rec.submitted_ms,
+                rec.status,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn finish_request(
+        &self,
+        id: i64,
+        status: RequestStatus,
+        reply: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<(), JournalError> {
+        let conn = self.lock();
+        conn.execute(
+            "UPDATE swap_requests SET status = ?2, reply = ?3, error = ?4, finished_ms = ?5
+             WHERE id = ?1",
+            params![id, status.as_str(), reply, error, now_ms()],
+        )?;
+        Ok(())
+    }
+
+    /// swaps に記録した後、その試行のレッグを紐付ける
+    pub fn link_requests(&self, swap_id: i64, request_ids: &[i64]) -> Result<(), JournalError> {
+        let conn = self.lock();
+        for id in request_ids {
+            conn.execute(
+                "UPDATE swap_requests SET swap_id = ?1 WHERE id = ?2",
+                params![swap_id, id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 結果が確定していない (pending / unknown) レッグ。since_ms より前の送信は対象外
+    pub fn unresolved_requests(
+        &self,
+        pair: &str,
+        since_ms: i64,
+    ) -> Result<Vec<SwapRequestRecord>, JournalError> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, swap_id, pair, leg, canister, method, request_id, submitted_ms, status,
+                    reply, error, finished_ms
+             FROM swap_requests
+             WHERE pair = ?1 AND submitted_ms >= ?2 AND status IN ('pending', 'unknown')
+             ORDER BY submitted_ms",
+        )?;
+        let rows = stmt.query_map(params![pair, since_ms], |row| {
+            Ok(SwapRequestRecord {
+                id: row.get(0)?,
+                swap_id: row.get(1)?,
+                pair: row.get(2)?,
+                leg: row.get(3)?,
+                canister: row.get(4)?,
+                method: row.get(5)?,
+                request_id: row.get(6)?,
+                submitted_ms: row.get(7)?,
+                status: row.get(8)?,
+                reply: row.get(9)?,
+                error: row.get(10)?,
+                finished_ms: row.get(11)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(JournalError::from)
+    }
+
+    pub fn record_pnl(&self, rec: &PnlRecord) -> Result<i64, JournalError> {
+        let conn = self.lock();
+        conn.execute(
+            "INSERT INTO pnl
+                (swap_id, ts_ms, pair, direction, icp_delta_e8, sns_delta_e8, sns_price_icp,
+                 realized_pnl_e8, expected_profit_e8, fees_e8, slippage_e8, ics_fill_e8,
+                 kong_fill_e8)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                rec.swap_id,
+                rec.ts_ms,
+                rec.pair,
+                rec.direction,
+                rec.icp_delta_e8,
+                rec.sns_delta_e8,
+                rec.sns_price_icp,
+                rec.realized_pnl_e8,
+                rec.expected_profit_e8,
+                rec.fees_e8,
+                rec.slippage_e8,
+                rec.ics_fill_e8.map(|v| v as i64),
+                rec.kong_fill_e8.map(|v| v as i64),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// 実現損益をペア・日 (UTC) ごとに集計する
+    pub fn pnl_summary(&self, filter: &TradeFilter) -> Result<Vec<PnlSummaryRow>, JournalError> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT pair, date(ts_ms / 1000, 'unixepoch') AS day, COUNT(*),
+                    SUM(realized_pnl_e8), SUM(expected_profit_e8), SUM(fees_e8), SUM(slippage_e8)
+             FROM pnl
+             WHERE (?1 IS NULL OR pair = ?1)
+               AND (?2 IS NULL OR ts_ms >= ?2)
+               AND (?3 IS NULL OR ts_ms <= ?3)
+             GROUP BY pair, day
+             ORDER BY day, pair",
+        )?;
+        let rows = stmt.query_map(params![filter.pair, filter.from_ms, filter.to_ms], |row| {
+            Ok(PnlSummaryRow {
+                pair: row.get(0)?,
+                day: row.get(1)?,
+                trades: row.get::<_, i64>(2)? as u64,
+                realized_pnl_e8: row.get(3)?,
+                expected_profit_e8: row.get(4)?,
+                fees_e8: row.get(5)?,
+                slippage_e8: row.get(6)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(JournalError::from)
+    }
+
+    pub fn swaps(&self, filter: &TradeFilter) -> Result<Vec<SwapRecord>, JournalError> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, opportunity_id, pair, direction, amount_in_e8, expected_mid_e8,
+                    expected_final_e8, min_mid_e8, min_final_e8, kong_reply, kong_error,
+                    ics_reply, ics_error, started_ms, finished_ms
+             FROM swaps
+             WHERE (?1 IS NULL OR pair = ?1)
+               AND (?2 IS NULL OR started_ms >= ?2)
+               AND (?3 IS NULL OR started_ms <= ?3)
+             ORDER BY started_ms",
+        )?;
+        let rows = stmt.query_map(params![filter.pair, filter.from_ms, filter.to_ms], |row| {
+            Ok(SwapRecord {
+                id: row.get(0)?,
+                opportunity_id: row.get(1)?,
+                pair: row.get(2)?,
+                direction: row.get(3)?,
+                amount_in_e8: row.get::<_, i64>(4)? as u128,
+                expected_mid_e8: row.get(5)?,
+                expected_final_e8: row.get(6)?,
+                min_mid_e8: row.get::<_, i64>(7)? as u128,
+                min_final_e8: row.get::<_, i64>(8)? as u128,
+                kong_reply: row.get(9)?,
+                kong_error: row.get(10)?,
+                ics_reply: row.get(11)?,
+                ics_error: row.get(12)?,
+                started_ms: row.get(13)?,
+                finished_ms: row.get(14)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(JournalError::from)
+    }
+
+    /// 期間内に評価した機会の件数 (評価数, 実行数)
+    pub fn opportunity_counts(&self, filter: &TradeFilter) -> Result<(u64, u64), JournalError> {
+        let conn = self.lock();
+        let counts = conn
+            .query_row(
+                "SELECT COUNT(*), COALESCE(SUM(executed), 0)
+                 FROM opportunities
+                 WHERE (?1 IS NULL OR pair = ?1)
+                   AND (?2 IS NULL OR ts_ms >= ?2)
+                   AND (?3 IS NULL OR ts_ms <= ?3)",
+                params![filter.pair, filter.from_ms, filter.to_ms],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?
+            .unwrap_or((0, 0));
+        Ok((counts.0 as u64, counts.1 as u64))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // 書き込み中に panic しても DB 自体は壊れないので poison は無視する
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS opportunities (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ts_ms INTEGER NOT NULL,
+    pair TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    amount_in_e8 REAL NOT NULL,
+    expected_mid_e8 REAL NOT NULL,
+    expected_final_e8 REAL NOT NULL,
+    expected_profit_e8 REAL NOT NULL,
+    executed INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_opportunities_pair_ts ON opportunities (pair, ts_ms);
+CREATE TABLE IF NOT EXISTS swaps (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    opportunity_id INTEGER REFERENCES opportunities (id),
+    pair TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    amount_in_e8 INTEGER NOT NULL,
+    expected_mid_e8 REAL NOT NULL,
+    expected_final_e8 REAL NOT NULL,
+    min_mid_e8 INTEGER NOT NULL,
+    min_final_e8 INTEGER NOT NULL,
+    kong_reply TEXT,
+    kong_error TEXT,
+    ics_reply TEXT,
+    ics_error TEXT,
+    started_ms INTEGER NOT NULL,
+    finished_ms INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_swaps_pair_started ON swaps (pair, started_ms);
+CREATE TABLE IF NOT EXISTS swap_requests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    swap_id INTEGER REFERENCES swaps (id),
+    pair TEXT NOT NULL,
+    leg TEXT NOT NULL,
+    canister TEXT NOT NULL,
+    method TEXT NOT NULL,
+    request_id TEXT NOT NULL,
+    submitted_ms INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    reply TEXT,
+    error TEXT,
+    finished_ms INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_swap_requests_pair_status ON swap_requests (pair, status);
+CREATE TABLE IF NOT EXISTS pnl (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    swap_id INTEGER REFERENCES swaps (id),
+    ts_ms INTEGER NOT NULL,
+    pair TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    icp_delta_e8 INTEGER NOT NULL,
+    sns_delta_e8 INTEGER NOT NULL,
+    sns_price_icp REAL NOT NULL,
+    realized_pnl_e8 REAL NOT NULL,
+    expected_profit_e8 REAL NOT NULL,
+    fees_e8 REAL NOT NULL,
+    slippage_e8 REAL NOT NULL,
+    ics_fill_e8 INTEGER,
+    kong_fill_e8 INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_pnl_pair_ts ON pnl (pair, ts_ms);
+";
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// ミリ秒のタイムスタンプを UTC の表示用文字列にする
+pub fn format_ms(ms: i64) -> String {
+    DateTime::<Utc>::from_timestamp_millis(ms)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| ms.to_string())
+}
+
+fn day_start_ms(day: &str) -> Result<i64, JournalError> {
+    let date = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        .map_err(|e| JournalError::Date(format!("{} ({})", day, e)))?;
+    let start = date
+        .and_hms_opt(0, 0, 0)
+        .ok_or_else(|| JournalError::Date(day.to_string()))?;
+    Ok(start.and_utc().timestamp_millis())
+}
@@ -1,6 +1,19 @@
 // ライブラリターゲット: bin/main から共通モジュールを参照するために公開
+pub mod alert;
 pub mod arb;
+pub mod backtest;
+pub mod breaker;
 pub mod config;
+pub mod control;
+pub mod health;
+pub mod http;
 pub mod ic_client;
 pub mod identity;
+pub mod journal;
+pub mod metrics;
 pub mod notify;
+pub mod pnl;
+pub mod recorder;
+pub mod report;
+pub mod risk;
+pub mod sim;
@@ -1,66 +1,336 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
+use kong_ics::alert::{watch_snapshots, AlertManager};
 use kong_ics::arb::Trade;
-use kong_ics::config::AppConfig;
+use kong_ics::config::{AppConfig, IdentityProfile, DEFAULT_IDENTITY};
+use kong_ics::control::{watch_pause_file, TradingControl};
+use kong_ics::health::HealthState;
+use kong_ics::http::{serve, Router};
 use kong_ics::ic_client::agent::IcClient;
-use kong_ics::identity::load_identity;
-use kong_ics::notify::DiscordNotifier;
+use kong_ics::identity::{load_identity, IdentityMode, PassphraseSource};
+use kong_ics::journal::Journal;
+use kong_ics::metrics::metrics_handler;
+use kong_ics::notify;
+use kong_ics::pnl::{run_daily_summary, IdentitySwaps};
+use kong_ics::recorder::SnapshotRecorder;
+use kong_ics::report::{run_reports, Period, TradeStats};
+use kong_ics::risk::{run_day_rollover, RiskManager};
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
     init_tracing();
     let cfg = AppConfig::load_default();
+    if let Err(e) = cfg.identity.validate() {
+        error!("{}", e);
+        return;
+    }
+    if !cfg.control.listen_addr.is_empty()
+        && !is_loopback(&cfg.control.listen_addr)
+        && cfg.control.auth_token.is_empty()
+    {
+        error!(
+            "CONTROL_LISTEN_ADDR={} はループバックではありません。外部から取引を止められないよう CONTROL_AUTH_TOKEN を設定してください",
+            cfg.control.listen_addr
+        );
+        return;
+    }
 
-    let identity = match load_identity(Path::new(&cfg.identity.pem_path)) {
-        Ok(id) => id,
-        Err(e) => {
-            error!("Identity 読み込みに失敗しました: {}", e);
-            return;
+    // ペアに割り当てた identity ごとに IcClient を用意する（同じ identity のペアは共有）
+    let passphrase = PassphraseSource::from_config(&cfg.identity);
+    let mut profiles: Vec<&IdentityProfile> = Vec::new();
+    for pair in &cfg.pairs {
+        match cfg.identity.trading_profile(pair) {
+            Ok(p) if !profiles.iter().any(|q| q.name == p.name) => profiles.push(p),
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
         }
-    };
+    }
+    if profiles.is_empty() {
+        profiles.extend(cfg.identity.profile(DEFAULT_IDENTITY));
+    }
+    let mut clients: Vec<(String, Arc<IcClient>)> = Vec::new();
+    for profile in profiles {
+        match connect(&cfg, profile, &passphrase).await {
+            Ok(c) => clients.push((profile.name.clone(), c)),
+            Err(e) => {
+                error!("{}: {}", profile.name, e);
+                return;
+            }
+        }
+    }
+    let principal = clients
+        .iter()
+        .map(|(name, c)| {
+            let p = c
+                .principal()
+                .map(|p| p.to_text())
+                .unwrap_or_else(|e| format!("unknown ({})", e));
+            if clients.len() == 1 {
+                p
+            } else {
+                format!("{}={}", name, p)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let health = Arc::new(HealthState::new(
+        principal,
+        clients
+            .iter()
+            .map(|(name, c)| (name.clone(), (**c).clone()))
+            .collect(),
+        cfg.http.health_stale_secs,
+    ));
 
-    let client = match IcClient::new(
-        &cfg.network.api_url,
-        identity.clone(),
-        cfg.network.fetch_root_key,
-    )
-    .await
-    {
-        Ok(c) => Arc::new(c),
-        Err(e) => {
-            error!("IcClient 初期化失敗: {}", e);
-            return;
+    let notifier = notify::start(&cfg.notify);
+
+    let journal = if cfg.journal.db_path.is_empty() {
+        None
+    } else {
+        match Journal::open(Path::new(&cfg.journal.db_path)) {
+            Ok(j) => Some(Arc::new(j)),
+            Err(e) => {
+                error!(
+                    "ジャーナル DB を開けません ({}): {}",
+                    cfg.journal.db_path, e
+                );
+                return;
+            }
         }
     };
 
-    let notifier = std::env::var(&cfg.discord.env_key)
-        .ok()
-        .map(DiscordNotifier::new);
+    let recorder = if cfg.recorder.enabled {
+        match SnapshotRecorder::new(Path::new(&cfg.recorder.dir)) {
+            Ok(r) => Some(Arc::new(r)),
+            Err(e) => {
+                error!(
+                    "スナップショット保存先を用意できません ({}): {}",
+                    cfg.recorder.dir, e
+                );
+                return;
+            }
+        }
+    } else {
+        None
+    };
 
     let mut tasks = Vec::new();
+    if cfg.trade.require_verified_queries && !cfg.network.verify_query_signatures {
+        warn!(
+            "IC_VERIFY_QUERY_SIGNATURES=false かつ TRADE_REQUIRE_VERIFIED_QUERIES=true のため取引は行いません"
+        );
+    }
+
+    let periods: Vec<Period> = [
+        (cfg.report.hourly, Period::Hourly),
+        (cfg.report.daily, Period::Daily),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, p)| enabled.then_some(p))
+    .collect();
+    let stats = (notifier.is_some() && !periods.is_empty()).then(|| Arc::new(TradeStats::new()));
+
+    let alerts = Arc::new(AlertManager::new(notifier.clone(), cfg.alert.clone()));
+
+    // 取引ループを回す前に停止ファイルを反映しておく
+    let control = Arc::new(
+        TradingControl::new(
+            notifier.clone(),
+            cfg.pairs.iter().map(|p| p.symbol.clone()).collect(),
+        )
+        .with_auth_token(&cfg.control.auth_token),
+    );
+    if !cfg.control.pause_file.is_empty() {
+        let path = PathBuf::from(&cfg.control.pause_file);
+        control.sync_pause_file(&path);
+        tasks.push(tokio::spawn(watch_pause_file(
+            control.clone(),
+            path,
+            Duration::from_millis(cfg.control.poll_ms.max(100)),
+        )));
+    }
+    let risk = Arc::new(RiskManager::new(
+        cfg.risk.clone(),
+        control.clone(),
+        alerts.clone(),
+    ));
+    if cfg.risk.max_daily_loss_e8 > 0f64 {
+        tasks.push(tokio::spawn(run_day_rollover(risk.clone())));
+    }
+
+    let mut trades = Vec::new();
+    // 同じ identity のペアは口座を共有するので、実現損益の重なりも identity ごとに見る
+    let mut identity_swaps: HashMap<String, Arc<IdentitySwaps>> = HashMap::new();
     for pair in cfg.pairs {
-        let trade = Trade::new(
+        let Some((_, pair_client)) = clients.iter().find(|(name, _)| *name == pair.identity) else {
+            error!(
+                "{}: identity {} の IcClient がありません",
+                pair.symbol, pair.identity
+            );
+            return;
+        };
+        let mut trade = Trade::new(
             pair.clone(),
-            client.clone(),
+            pair_client.clone(),
             notifier.clone(),
             cfg.trade.fee_rate,
             cfg.trade.min_receive_factor,
             cfg.trade.profit_threshold_e8,
             cfg.trade.loop_interval_ms,
-        );
-        let handle = tokio::spawn(run_loop(trade));
+        )
+        .with_swap_timeouts(
+            cfg.trade.swap_ingress_expiry_secs,
+            cfg.trade.swap_timeout_ms,
+        )
+        .with_verified_queries(cfg.trade.require_verified_queries);
+        if let Some(journal) = &journal {
+            trade = trade
+                .with_journal(journal.clone(), cfg.journal.record_evaluations)
+                .with_pnl_settle_ms(cfg.trade.pnl_settle_ms);
+        }
+        if let Some(recorder) = &recorder {
+            trade = trade.with_recorder(recorder.clone());
+        }
+        if let Some(stats) = &stats {
+            trade = trade.with_stats(stats.clone());
+        }
+        trade = trade
+            .with_health(health.clone())
+            .with_alerts(alerts.clone())
+            .with_control(control.clone())
+            .with_risk(risk.clone())
+            .with_price_breaker(cfg.breaker.clone())
+            .with_identity_swaps(
+                identity_swaps
+                    .entry(pair.identity.clone())
+                    .or_default()
+                    .clone(),
+            );
+        let trade = Arc::new(trade);
+        trade.recover_unresolved_swaps().await;
+        let handle = tokio::spawn(run_loop(trade.clone()));
         tasks.push(handle);
+        trades.push(trade);
+    }
+
+    if !cfg.http.listen_addr.is_empty() {
+        let router = health.routes(Router::new().route("GET", "/metrics", metrics_handler));
+        let addr = cfg.http.listen_addr.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = serve(&addr, router).await {
+                error!("{}", e);
+            }
+        }));
+    }
+    if !cfg.control.listen_addr.is_empty() {
+        let addr = cfg.control.listen_addr.clone();
+        let router = control.routes(Router::new());
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = serve(&addr, router).await {
+                error!("{}", e);
+            }
+        }));
+    }
+    // 残高の下限・在庫の上限を見る場合は /metrics が無くても残高を取り直す
+    if !cfg.http.listen_addr.is_empty()
+        || !cfg.alert.min_balances_e8.is_empty()
+        || !cfg.risk.max_inventory_e8.is_empty()
+    {
+        tasks.push(tokio::spawn(refresh_balances(
+            trades.clone(),
+            cfg.trade.balance_refresh_secs,
+        )));
+    }
+    tasks.push(tokio::spawn(watch_snapshots(
+        alerts.clone(),
+        trades.clone(),
+    )));
+
+    if cfg.network.endpoint_log_secs > 0 {
+        for (_, c) in &clients {
+            tasks.push(tokio::spawn(log_endpoints(
+                (**c).clone(),
+                cfg.network.endpoint_log_secs,
+            )));
+        }
+    }
+
+    // 日次レポートを送るときは、ジャーナルの損益もそちらに載るので日次サマリは別に送らない
+    if let (Some(journal), Some(notifier)) = (&journal, &notifier) {
+        if !periods.contains(&Period::Daily) {
+            tasks.push(tokio::spawn(run_daily_summary(
+                journal.clone(),
+                notifier.clone(),
+            )));
+        }
+    }
+
+    if let (Some(stats), Some(notifier)) = (&stats, &notifier) {
+        for period in periods {
+            tasks.push(tokio::spawn(run_reports(
+                stats.clone(),
+                trades.clone(),
+                notifier.clone(),
+                journal.clone(),
+                period,
+            )));
+        }
     }
 
     futures::future::join_all(tasks).await;
 }
 
-async fn run_loop(trade: Trade) {
+/// IP で書いたループバックのアドレスだけ true。ホスト名は解決先が変わりうるのでループバックとみなさない
+fn is_loopback(addr: &str) -> bool {
+    addr.parse::<SocketAddr>()
+        .is_ok_and(|addr| addr.ip().is_loopback())
+}
+
+/// identity を読み込み、その鍵で署名する IcClient を作る
+async fn connect(
+    cfg: &AppConfig,
+    profile: &IdentityProfile,
+    passphrase: &PassphraseSource,
+) -> Result<Arc<IcClient>, String> {
+    let identity = load_identity(
+        Path::new(&profile.pem_path),
+        passphrase,
+        IdentityMode::Signing,
+    )
+    .map_err(|e| format!("Identity 読み込みに失敗しました: {}", e))?;
+    let principal = identity
+        .sender()
+        .map_err(|e| format!("Identity から principal を取得できません: {}", e))?;
+    info!(
+        "identity {}: principal {} で署名します",
+        profile.name, principal
+    );
+    let client = IcClient::new(
+        &cfg.network.api_urls,
+        identity,
+        cfg.network.fetch_root_key,
+        cfg.network.verify_query_signatures,
+    )
+    .await
+    .map_err(|e| format!("IcClient 初期化失敗: {}", e))?;
+    Ok(Arc::new(
+        client
+            .with_retry(cfg.network.query_retry, cfg.network.poll_retry)
+            .with_timeouts(cfg.network.timeouts),
+    ))
+}
+
+async fn run_loop(trade: Arc<Trade>) {
     loop {
         if let Err(e) = trade.tick().await {
             error!("{}: tick エラー {:?}", trade.symbol(), e);
@@ -69,6 +339,24 @@ async fn run_loop(trade: Trade) {
     }
 }
 
+/// 残高メトリクスを定期更新する（swap の有無に関係なく残高を見えるようにする）
+async fn refresh_balances(trades: Vec<Arc<Trade>>, interval_secs: u64) {
+    loop {
+        for trade in &trades {
+            trade.fetch_balances().await;
+        }
+        sleep(Duration::from_secs(interval_secs.max(1))).await;
+    }
+}
+
+/// API エンドポイントごとのレイテンシ・エラー数を定期的にログへ出す
+async fn log_endpoints(client: IcClient, interval_secs: u64) {
+    loop {
+        sleep(Duration::from_secs(interval_secs)).await;
+        client.log_endpoint_health();
+    }
+}
+
 fn init_tracing() {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::fmt().with_env_filter(filter).init();
@@ -0,0 +1,144 @@
+// どこで: Prometheus メトリクスの定義
+// 何を: tick 数・IC 呼び出しレイテンシ・スナップショット取得失敗・エッジ・swap 結果・見送り・残高・停止状態を集計する
+// なぜ: ログ以外に稼働状況を継続的に観測する手段が無かったため
+
+use std::sync::OnceLock;
+
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::http::{HttpRequest, HttpResponse};
+
+pub struct Metrics {
+    registry: Registry,
+    /// pair
+    pub ticks: IntCounterVec,
+    /// canister, method, kind (query/update), outcome (ok/err)
+    pub ic_call_seconds: HistogramVec,
+    /// pair, venue (kong/ics)
+    pub snapshot_fetch_failures: IntCounterVec,
+    /// pair, direction
+    pub edge_bps: HistogramVec,
+    /// pair, direction
+    pub swaps_executed: IntCounterVec,
+    /// pair, leg (kong/ics), class
+    pub swap_failures: IntCounterVec,
+    /// pair, reason
+    pub trades_refused: IntCounterVec,
+    /// token
+    pub wallet_balance_e8: GaugeVec,
+    /// pair（全ペアの停止は "*"）
+    pub trading_paused: IntGaugeVec,
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("kong_ics".to_string()), None)
+            .expect("固定の prefix なので失敗しない");
+
+        let ticks = IntCounterVec::new(Opts::new("ticks_total", "tick の実行回数"), &["pair"])
+            .expect("metric 定義");
+        let ic_call_seconds = HistogramVec::new(
+            HistogramOpts::new("ic_call_seconds", "IC への query/update のレイテンシ")
+                .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0]),
+            &["canister", "method", "kind", "outcome"],
+        )
+        .expect("metric 定義");
+        let snapshot_fetch_failures = IntCounterVec::new(
+            Opts::new(
+                "snapshot_fetch_failures_total",
+                "プールスナップショット取得の失敗回数",
+            ),
+            &["pair", "venue"],
+        )
+        .expect("metric 定義");
+        let edge_bps = HistogramVec::new(
+            HistogramOpts::new("edge_bps", "評価した機会の期待利益 / 投入額 (bps)").buckets(vec![
+                -100.0, -50.0, -20.0, -10.0, 0.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0,
+            ]),
+            &["pair", "direction"],
+        )
+        .expect("metric 定義");
+        let swaps_executed = IntCounterVec::new(
+            Opts::new("swaps_executed_total", "両レッグが成功した swap の回数"),
+            &["pair", "direction"],
+        )
+        .expect("metric 定義");
+        let swap_failures = IntCounterVec::new(
+            Opts::new("swap_failures_total", "失敗した swap レッグの回数"),
+            &["pair", "leg", "class"],
+        )
+        .expect("metric 定義");
+        let trades_refused = IntCounterVec::new(
+            Opts::new(
+                "trades_refused_total",
+                "しきい値を超えたが安全上の理由で見送った回数",
+            ),
+            &["pair", "reason"],
+        )
+        .expect("metric 定義");
+        let wallet_balance_e8 = GaugeVec::new(
+            Opts::new("wallet_balance_e8", "最後に取得したウォレット残高 (e8)"),
+            &["account", "token"],
+        )
+        .expect("metric 定義");
+        let trading_paused = IntGaugeVec::new(
+            Opts::new("trading_paused", "取引を一時停止していれば 1"),
+            &["pair"],
+        )
+        .expect("metric 定義");
+
+        for c in [
+            Box::new(ticks.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(ic_call_seconds.clone()),
+            Box::new(snapshot_fetch_failures.clone()),
+            Box::new(edge_bps.clone()),
+            Box::new(swaps_executed.clone()),
+            Box::new(swap_failures.clone()),
+            Box::new(trades_refused.clone()),
+            Box::new(wallet_balance_e8.clone()),
+            Box::new(trading_paused.clone()),
+        ] {
+            registry.register(c).expect("metric 名が重複しない");
+        }
+
+        Metrics {
+            registry,
+            ticks,
+            ic_call_seconds,
+            snapshot_fetch_failures,
+            edge_bps,
+            swaps_executed,
+            swap_failures,
+            trades_refused,
+            wallet_balance_e8,
+            trading_paused,
+        }
+    }
+
+    /// Prometheus の text 形式で出力する
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        let encoder = TextEncoder::new();
+        if let Err(e) = encoder.encode(&self.registry.gather(), &mut buf) {
+            return format!("# encode error: {}\n", e);
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+/// Router に登録する /metrics ハンドラ
+pub fn metrics_handler(_req: &HttpRequest) -> HttpResponse {
+    HttpResponse {
+        status: 200,
+        content_type: "text/plain; version=0.0.4",
+        body: metrics().render(),
+    }
+}
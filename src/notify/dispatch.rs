@@ -0,0 +1,182 @@
+// どこで: 通知を出す側（取引ループ・approve）と実際の送り先の間
+// 何を: 通知をキューに積んでバックグラウンドで送り、同じキーの通知は窓の間まとめて後でダイジェストにする
+// なぜ: 送信待ちやレート制限で取引が止まらず、失敗が続いてもチャンネルを同じ通知で埋めないようにするため
+
+use std::collections::HashMap;
+use std::future::ready;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use tracing::warn;
+
+use super::{Notification, Notifier, NotifyError};
+
+/// 窓が閉じたかを確認する間隔の上限
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Item {
+    Text(String),
+    Structured(Notification),
+}
+
+impl Item {
+    fn key(&self) -> String {
+        match self {
+            Item::Text(text) => format!("text|{}", text),
+            Item::Structured(n) => n.dedup_key(),
+        }
+    }
+
+    async fn deliver(&self, inner: &dyn Notifier) {
+        let res = match self {
+            Item::Text(text) => inner.notify(text).await,
+            Item::Structured(n) => inner.send(n).await,
+        };
+        if let Err(e) = res {
+            warn!("通知失敗: {}", e);
+        }
+    }
+
+    /// 窓の間に抑えた件数を添えた要約
+    fn digest(self, suppressed: usize, window: Duration) -> Item {
+        let secs = window.as_secs();
+        match self {
+            Item::Text(text) => Item::Text(format!(
+                "{}\n(直近 {} 秒で同じ通知が他に {} 件)",
+                text, secs, suppressed
+            )),
+            Item::Structured(mut n) => {
+                n.title = format!("{} (他に {} 件)", n.title, suppressed);
+                Item::Structured(
+                    n.with_field("まとめた件数", suppressed.to_string())
+                        .with_field("期間", format!("{} 秒", secs)),
+                )
+            }
+        }
+    }
+}
+
+/// キーごとの抑制窓。最初の 1 件は送り、窓の間の残りは件数と最後の 1 件だけ持つ
+struct Window {
+    opened: Instant,
+    suppressed: usize,
+    last: Option<Item>,
+}
+
+/// 通知をキューに積むだけで返す Notifier。送信は start で起動したタスクが行う
+pub struct NotifyDispatcher {
+    tx: mpsc::Sender<Item>,
+}
+
+impl NotifyDispatcher {
+    /// 送信タスクを起動する（tokio ランタイム内で呼ぶ）。dedup_window が 0 なら重複をまとめない
+    pub fn start(inner: Arc<dyn Notifier>, dedup_window: Duration, queue_size: usize) -> Self {
+        let (tx, rx) = mpsc::channel(queue_size.max(1));
+        tokio::spawn(run(inner, dedup_window, rx));
+        NotifyDispatcher { tx }
+    }
+
+    fn enqueue(&self, item: Item) -> Result<(), NotifyError> {
+        self.tx.try_send(item).map_err(|e| match e {
+            TrySendError::Full(_) => NotifyError::QueueFull,
+            TrySendError::Closed(_) => {
+                NotifyError::Http("通知の送信タスクが停止しています".to_string())
+            }
+        })
+    }
+}
+
+impl Notifier for NotifyDispatcher {
+    fn name(&self) -> &str {
+        "dispatcher"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(ready(self.enqueue(Item::Text(message.to_string()))))
+    }
+
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(ready(self.enqueue(Item::Structured(notification.clone()))))
+    }
+}
+
+async fn run(inner: Arc<dyn Notifier>, window: Duration, mut rx: mpsc::Receiver<Item>) {
+    let mut windows: HashMap<String, Window> = HashMap::new();
+    let mut flush = interval(FLUSH_INTERVAL.min(window).max(Duration::from_millis(10)));
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            item = rx.recv() => match item {
+                Some(item) => handle(&*inner, window, &mut windows, item).await,
+                None => break,
+            },
+            _ = flush.tick() => flush_expired(&*inner, window, &mut windows, false).await,
+        }
+    }
+    // 送る側が全て閉じたら、抑えていた分を送り切って終わる
+    flush_expired(&*inner, window, &mut windows, true).await;
+}
+
+async fn handle(
+    inner: &dyn Notifier,
+    window: Duration,
+    windows: &mut HashMap<String, Window>,
+    item: Item,
+) {
+    if window.is_zero() {
+        item.deliver(inner).await;
+        return;
+    }
+    let key = item.key();
+    let now = Instant::now();
+    if let Some(w) = windows.get_mut(&key) {
+        if now.duration_since(w.opened) < window {
+            w.suppressed += 1;
+            w.last = Some(item);
+            return;
+        }
+    }
+    if let Some(old) = windows.remove(&key) {
+        send_digest(inner, window, old).await;
+    }
+    item.deliver(inner).await;
+    windows.insert(
+        key,
+        Window {
+            opened: now,
+            suppressed: 0,
+            last: None,
+        },
+    );
+}
+
+async fn flush_expired(
+    inner: &dyn Notifier,
+    window: Duration,
+    windows: &mut HashMap<String, Window>,
+    all: bool,
+) {
+    let now = Instant::now();
+    let expired: Vec<String> = windows
+        .iter()
+        .filter(|(_, w)| all || now.duration_since(w.opened) >= window)
+        .map(|(k, _)| k.clone())
+        .collect();
+    for key in expired {
+        if let Some(w) = windows.remove(&key) {
+            send_digest(inner, window, w).await;
+        }
+    }
+}
+
+async fn send_digest(inner: &dyn Notifier, window: Duration, w: Window) {
+    if let Some(last) = w.last {
+        last.digest(w.suppressed, window).deliver(inner).await;
+    }
+}
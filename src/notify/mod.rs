@@ -0,0 +1,628 @@
+// どこで: 取引・approve の通知を外部チャットへ送るクライアント群
+// 何を: Notifier trait と Discord / Slack / Telegram / 汎用 webhook の実装、複数宛先へのファンアウト、
+//       重要度つきの構造化通知と警告・エラーの別送り先への振り分け。
+//       重複の抑制と送信キューは dispatch にある
+// なぜ: 送り先を設定だけで切り替え・併用でき、鍵や URL をコードに埋め込まずに済むようにするため
+
+pub mod dispatch;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::future::{join_all, BoxFuture};
+use reqwest::Client;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::config::{NotifierConfig, NotifyConfig};
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("HTTP エラー: {0}")]
+    Http(String),
+    #[error("一部の通知先に送れませんでした: {0}")]
+    Partial(String),
+    #[error("レート制限中です ({0:?} 後に再送可)")]
+    RateLimited(Duration),
+    #[error("通知キューが一杯のため破棄しました")]
+    QueueFull,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    /// 警告していた状態が解消した
+    Recovered,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Recovered => "recovered",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// 種類・重要度・ペアと項目を持つ通知。Discord は embed、それ以外は text() の文字列で送る
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// 機械的に振り分けるための種類（例: swap_executed）
+    pub event: String,
+    pub severity: Severity,
+    pub title: String,
+    pub pair: Option<String>,
+    pub fields: Vec<(String, String)>,
+    /// 重複とみなす単位。未指定なら event とペア
+    pub dedup_key: Option<String>,
+}
+
+impl Notification {
+    pub fn new(event: &str, severity: Severity, title: impl Into<String>) -> Self {
+        Notification {
+            event: event.to_string(),
+            severity,
+            title: title.into(),
+            pair: None,
+            fields: Vec::new(),
+            dedup_key: None,
+        }
+    }
+
+    pub fn with_dedup_key(mut self, key: impl Into<String>) -> Self {
+        self.dedup_key = Some(key.into());
+        self
+    }
+
+    pub fn dedup_key(&self) -> String {
+        match &self.dedup_key {
+            Some(key) => key.clone(),
+            None => format!("{}|{}", self.event, self.pair.as_deref().unwrap_or("")),
+        }
+    }
+
+    pub fn with_pair(mut self, pair: &str) -> Self {
+        self.pair = Some(pair.to_string());
+        self
+    }
+
+    pub fn with_field(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.fields.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// 1 行目に重要度・ペア・タイトル、続けて「項目: 値」を並べる
+    pub fn text(&self) -> String {
+        let mut out = format!("[{}] {}", self.severity.as_str(), self.heading());
+        for (name, value) in &self.fields {
+            out.push_str(&format!("\n{}: {}", name, value));
+        }
+        out
+    }
+
+    fn heading(&self) -> String {
+        match &self.pair {
+            Some(pair) => format!("{} {}", pair, self.title),
+            None => self.title.clone(),
+        }
+    }
+}
+
+/// 通知の送り先。trait object で持てるよう future は Box で返す
+pub trait Notifier: Send + Sync {
+    /// ログに出す送り先の名前
+    fn name(&self) -> &str;
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>>;
+
+    /// 構造化通知を送る。既定では text() の文字列を notify する
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move { self.notify(&notification.text()).await })
+    }
+}
+
+/// 設定された送り先をまとめる。alert_backends があれば警告・エラーと復旧はそちらへ送る
+pub fn from_config(cfg: &NotifyConfig) -> Option<Arc<dyn Notifier>> {
+    let client = Client::new();
+    let routine = build_backends(&client, &cfg.backends);
+    match build_backends(&client, &cfg.alert_backends) {
+        Some(alerts) => Some(Arc::new(SeverityRouter { routine, alerts })),
+        None => routine,
+    }
+}
+
+/// from_config の送り先を、重複をまとめるバックグラウンド送信越しに使う（tokio ランタイム内で呼ぶ）
+pub fn start(cfg: &NotifyConfig) -> Option<Arc<dyn Notifier>> {
+    let inner = from_config(cfg)?;
+    Some(Arc::new(dispatch::NotifyDispatcher::start(
+        inner,
+        Duration::from_secs(cfg.dedup_window_secs),
+        cfg.queue_size,
+    )))
+}
+
+/// 1 つも無ければ None、1 つならそのまま、複数ならファンアウトにする
+fn build_backends(client: &Client, configs: &[NotifierConfig]) -> Option<Arc<dyn Notifier>> {
+    let mut backends: Vec<Arc<dyn Notifier>> = configs
+        .iter()
+        .map(|b| -> Arc<dyn Notifier> {
+            match b {
+                NotifierConfig::Discord { webhook_url } => Arc::new(DiscordNotifier {
+                    client: client.clone(),
+                    webhook_url: webhook_url.clone(),
+                }),
+                NotifierConfig::Slack { webhook_url } => Arc::new(SlackNotifier {
+                    client: client.clone(),
+                    webhook_url: webhook_url.clone(),
+                }),
+                NotifierConfig::Telegram {
+                    bot_token,
+                    chat_id,
+                    api_base,
+                } => Arc::new(TelegramNotifier {
+                    client: client.clone(),
+                    bot_token: bot_token.clone(),
+                    chat_id: chat_id.clone(),
+                    api_base: api_base.clone(),
+                }),
+                NotifierConfig::Webhook { url } => Arc::new(WebhookNotifier {
+                    client: client.clone(),
+                    url: url.clone(),
+                }),
+            }
+        })
+        .collect();
+    match backends.len() {
+        0 => None,
+        1 => backends.pop(),
+        _ => Some(Arc::new(FanoutNotifier::new(backends))),
+    }
+}
+
+/// 429 を受けたときに Retry-After に従って再送する回数
+const RATE_LIMIT_RETRIES: usize = 3;
+/// Retry-After が長すぎるときはこれ以上待たずに諦める
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// JSON を POST し、2xx 以外はエラーにする。429 は Retry-After だけ待って送り直す。
+/// webhook URL や bot token はログに出さない
+async fn post_json<T: Serialize>(client: &Client, url: &str, body: &T) -> Result<(), NotifyError> {
+    let mut attempt = 0;
+    loop {
+        match post_once(client, url, body).await {
+            Err(NotifyError::RateLimited(wait))
+                if attempt < RATE_LIMIT_RETRIES && wait <= RATE_LIMIT_MAX_WAIT =>
+            {
+                attempt += 1;
+                warn!("通知がレート制限されました。{:?} 後に再送します", wait);
+                sleep(wait).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+async fn post_once<T: Serialize>(client: &Client, url: &str, body: &T) -> Result<(), NotifyError> {
+    let res = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| NotifyError::Http(e.without_url().to_string()))?;
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let header = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        // Discord は本文の retry_after（秒、小数あり）の方が細かい。Telegram は parameters の下に入れる
+        let body = res.json::<serde_json::Value>().await.ok();
+        let from_body = body
+            .as_ref()
+            .and_then(|b| {
+                b.get("retry_after")
+                    .or_else(|| b.get("parameters").and_then(|p| p.get("retry_after")))
+            })
+            .and_then(|v| v.as_f64())
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+        let wait = from_body.or(header).unwrap_or(Duration::from_secs(1));
+        return Err(NotifyError::RateLimited(wait));
+    }
+    Err(NotifyError::Http(format!("status {}", status)))
+}
+
+/// Retry-After の秒数（小数も許す）。HTTP 日付形式は扱わない
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+#[derive(Clone)]
+pub struct DiscordNotifier {
+    client: Client,
+    webhook_url: String,
+}
+
+#[derive(Serialize)]
+struct DiscordPayload<'a> {
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbedPayload {
+    embeds: Vec<DiscordEmbed>,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbed {
+    title: String,
+    color: u32,
+    fields: Vec<DiscordField>,
+    footer: DiscordFooter,
+    timestamp: String,
+}
+
+#[derive(Serialize)]
+struct DiscordField {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+#[derive(Serialize)]
+struct DiscordFooter {
+    text: String,
+}
+
+// Discord の embed の上限（タイトル 256 文字、項目 25 個、項目名 256 文字、値 1024 文字）
+const EMBED_TITLE_MAX: usize = 256;
+const EMBED_FIELDS_MAX: usize = 25;
+const EMBED_FIELD_NAME_MAX: usize = 256;
+const EMBED_FIELD_VALUE_MAX: usize = 1024;
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut out: String = text.chars().take(max_chars - 1).collect();
+    out.push('…');
+    out
+}
+
+fn severity_color(severity: Severity) -> u32 {
+    match severity {
+        Severity::Info => 0x2ecc71,
+        Severity::Recovered => 0x3498db,
+        Severity::Warning => 0xf1c40f,
+        Severity::Error => 0xe74c3c,
+    }
+}
+
+impl DiscordEmbedPayload {
+    fn from_notification(n: &Notification) -> Self {
+        let fields = n
+            .fields
+            .iter()
+            .take(EMBED_FIELDS_MAX)
+            .map(|(name, value)| DiscordField {
+                name: truncate(name, EMBED_FIELD_NAME_MAX),
+                // 空の値は Discord が受け付けない
+                value: truncate(
+                    if value.is_empty() { "-" } else { value },
+                    EMBED_FIELD_VALUE_MAX,
+                ),
+                inline: value.chars().count() <= 40,
+            })
+            .collect();
+        DiscordEmbedPayload {
+            embeds: vec![DiscordEmbed {
+                title: truncate(&n.heading(), EMBED_TITLE_MAX),
+                color: severity_color(n.severity),
+                fields,
+                footer: DiscordFooter {
+                    text: format!("{} / {}", n.event, n.severity.as_str()),
+                },
+                timestamp: Utc::now().to_rfc3339(),
+            }],
+        }
+    }
+}
+
+impl DiscordNotifier {
+    pub fn new(webhook_url: String) -> Self {
+        DiscordNotifier {
+            client: Client::new(),
+            webhook_url,
+        }
+    }
+}
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let payload = DiscordPayload { content: message };
+            post_json(&self.client, &self.webhook_url, &payload).await
+        })
+    }
+
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let payload = DiscordEmbedPayload::from_notification(notification);
+            post_json(&self.client, &self.webhook_url, &payload).await
+        })
+    }
+}
+
+/// Slack の Incoming Webhook
+#[derive(Clone)]
+pub struct SlackNotifier {
+    client: Client,
+    webhook_url: String,
+}
+
+#[derive(Serialize)]
+struct TextPayload<'a> {
+    text: &'a str,
+}
+
+impl SlackNotifier {
+    pub fn new(webhook_url: String) -> Self {
+        SlackNotifier {
+            client: Client::new(),
+            webhook_url,
+        }
+    }
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let payload = TextPayload { text: message };
+            post_json(&self.client, &self.webhook_url, &payload).await
+        })
+    }
+}
+
+/// Telegram Bot API の sendMessage
+#[derive(Clone)]
+pub struct TelegramNotifier {
+    client: Client,
+    bot_token: String,
+    chat_id: String,
+    /// 既定は https://api.telegram.org（テストではスタブに向ける）
+    api_base: String,
+}
+
+#[derive(Serialize)]
+struct TelegramPayload<'a> {
+    chat_id: &'a str,
+    text: &'a str,
+}
+
+impl TelegramNotifier {
+    pub fn new(bot_token: String, chat_id: String, api_base: String) -> Self {
+        TelegramNotifier {
+            client: Client::new(),
+            bot_token,
+            chat_id,
+            api_base,
+        }
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/bot{}/sendMessage",
+                self.api_base.trim_end_matches('/'),
+                self.bot_token
+            );
+            let payload = TelegramPayload {
+                chat_id: &self.chat_id,
+                text: message,
+            };
+            post_json(&self.client, &url, &payload).await
+        })
+    }
+}
+
+/// 任意の受け口へ {"source": "kong_ics", "text": ...} を送る。構造化通知は event などの項目も付ける
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    source: &'a str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pair: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<BTreeMap<&'a str, &'a str>>,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        WebhookNotifier {
+            client: Client::new(),
+            url,
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let payload = WebhookPayload {
+                source: "kong_ics",
+                text: message,
+                event: None,
+                severity: None,
+                pair: None,
+                fields: None,
+            };
+            post_json(&self.client, &self.url, &payload).await
+        })
+    }
+
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let text = notification.text();
+            let payload = WebhookPayload {
+                source: "kong_ics",
+                text: &text,
+                event: Some(&notification.event),
+                severity: Some(notification.severity.as_str()),
+                pair: notification.pair.as_deref(),
+                fields: Some(
+                    notification
+                        .fields
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str()))
+                        .collect(),
+                ),
+            };
+            post_json(&self.client, &self.url, &payload).await
+        })
+    }
+}
+
+/// 全ての送り先へ並行して送る。失敗した送り先があっても残りには届ける
+pub struct FanoutNotifier {
+    backends: Vec<Arc<dyn Notifier>>,
+}
+
+impl FanoutNotifier {
+    pub fn new(backends: Vec<Arc<dyn Notifier>>) -> Self {
+        FanoutNotifier { backends }
+    }
+
+    /// 失敗した送り先を警告ログに出し、まとめて 1 つのエラーにする
+    fn collect(&self, results: Vec<Result<(), NotifyError>>) -> Result<(), NotifyError> {
+        let errors: Vec<String> = self
+            .backends
+            .iter()
+            .zip(results)
+            .filter_map(|(b, r)| r.err().map(|e| format!("{}: {}", b.name(), e)))
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        for e in &errors {
+            warn!("通知失敗 {}", e);
+        }
+        Err(NotifyError::Partial(errors.join(" | ")))
+    }
+}
+
+impl Notifier for FanoutNotifier {
+    fn name(&self) -> &str {
+        "fanout"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let results = join_all(self.backends.iter().map(|b| b.notify(message))).await;
+            self.collect(results)
+        })
+    }
+
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let results = join_all(self.backends.iter().map(|b| b.send(notification))).await;
+            self.collect(results)
+        })
+    }
+}
+
+/// 警告・エラーと復旧を別の送り先へ回す。情報レベルと文字列の notify は routine へ送る
+pub struct SeverityRouter {
+    routine: Option<Arc<dyn Notifier>>,
+    alerts: Arc<dyn Notifier>,
+}
+
+impl SeverityRouter {
+    pub fn new(routine: Option<Arc<dyn Notifier>>, alerts: Arc<dyn Notifier>) -> Self {
+        SeverityRouter { routine, alerts }
+    }
+}
+
+impl Notifier for SeverityRouter {
+    fn name(&self) -> &str {
+        "router"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            match &self.routine {
+                Some(r) => r.notify(message).await,
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            if notification.severity != Severity::Info {
+                self.alerts.send(notification).await
+            } else if let Some(r) = &self.routine {
+                r.send(notification).await
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
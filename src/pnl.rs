@@ -0,0 +1,182 @@
+// どこで: 実現損益の計算と日次サマリ
+// 何を: swap 前後の残高差を ICP 換算して 1 トレードの損益を出し、約定量との差から実際に取られた手数料を求め、ペア/日ごとの集計を通知する
+// なぜ: 「利益見込み」だけでは手数料・スリッページ込みで実際に儲かったか分からないため
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration as ChronoDuration, Utc};
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::journal::{now_ms, Journal, PnlRecord, PnlSummaryRow, TradeFilter};
+use crate::notify::Notifier;
+use crate::report::Period;
+
+/// 取引に使う 2 トークンの残高 (e8)
+#[derive(Debug, Clone, Copy)]
+pub struct BalanceSnapshot {
+    pub icp_e8: u128,
+    pub sns_e8: u128,
+}
+
+/// 両レッグの約定量 (e8)。失敗して返金されたレッグは 0 のまま
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwapFills {
+    pub icp_paid_e8: u128,
+    pub icp_received_e8: u128,
+    pub sns_paid_e8: u128,
+    pub sns_received_e8: u128,
+    /// Kong の requests が返した受取量（約定したときだけ）
+    pub kong_fill_e8: Option<u128>,
+    /// ICPSwap の swap が返した受取量（約定したときだけ）
+    pub ics_fill_e8: Option<u128>,
+}
+
+/// swap 前後の残高から実現損益を組み立てる入力
+#[derive(Debug, Clone)]
+pub struct PnlInput {
+    pub pair: String,
+    pub direction: String,
+    pub swap_id: Option<i64>,
+    pub before: BalanceSnapshot,
+    pub after: BalanceSnapshot,
+    pub sns_price_icp: f64,
+    pub expected_profit_e8: f64,
+    pub fills: SwapFills,
+}
+
+/// ICP 差分 + SNS 差分 × 価格 を実現損益とする。
+/// 約定量どおりなら動くはずの残高と実際の差分のずれを、実際に取られた手数料とする
+/// （返金された失敗レッグの入出金の fee もここに入る）
+pub fn realized_pnl(input: &PnlInput) -> PnlRecord {
+    let icp_delta = input.after.icp_e8 as i128 - input.before.icp_e8 as i128;
+    let sns_delta = input.after.sns_e8 as i128 - input.before.sns_e8 as i128;
+    let realized = icp_delta as f64 + sns_delta as f64 * input.sns_price_icp;
+    let fills = &input.fills;
+    let icp_fees = fills.icp_received_e8 as i128 - fills.icp_paid_e8 as i128 - icp_delta;
+    let sns_fees = fills.sns_received_e8 as i128 - fills.sns_paid_e8 as i128 - sns_delta;
+    let fees = icp_fees as f64 + sns_fees as f64 * input.sns_price_icp;
+
+    PnlRecord {
+        swap_id: input.swap_id,
+        ts_ms: now_ms(),
+        pair: input.pair.clone(),
+        direction: input.direction.clone(),
+        icp_delta_e8: icp_delta as i64,
+        sns_delta_e8: sns_delta as i64,
+        sns_price_icp: input.sns_price_icp,
+        realized_pnl_e8: realized,
+        expected_profit_e8: input.expected_profit_e8,
+        fees_e8: fees,
+        slippage_e8: input.expected_profit_e8 - realized,
+        kong_fill_e8: fills.kong_fill_e8,
+        ics_fill_e8: fills.ics_fill_e8,
+    }
+}
+
+/// 同じ identity（同じ口座）で実行中の swap。残高の差分に他のペアの swap が混ざっていないかを見る
+#[derive(Default)]
+pub struct IdentitySwaps {
+    /// (実行中の数, これまでに始まった数)
+    state: Mutex<(usize, u64)>,
+}
+
+impl IdentitySwaps {
+    /// 事前残高を取る前に呼び、損益を記録し終えるまで戻り値を持っておく
+    pub fn begin(&self) -> SwapWindow<'_> {
+        let mut state = self.lock();
+        let alone = state.0 == 0;
+        state.0 += 1;
+        state.1 += 1;
+        SwapWindow {
+            swaps: self,
+            started: state.1,
+            alone,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (usize, u64)> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// begin から drop までの間、実行中の swap として数える
+pub struct SwapWindow<'a> {
+    swaps: &'a IdentitySwaps,
+    started: u64,
+    alone: bool,
+}
+
+impl SwapWindow<'_> {
+    /// begin の時点で他に実行中の swap が無く、その後も始まっていなければ true
+    pub fn exclusive(&self) -> bool {
+        self.alone && self.swaps.lock().1 == self.started
+    }
+}
+
+impl Drop for SwapWindow<'_> {
+    fn drop(&mut self) {
+        let mut state = self.swaps.lock();
+        state.0 = state.0.saturating_sub(1);
+    }
+}
+
+pub fn format_summary(title: &str, rows: &[PnlSummaryRow]) -> String {
+    format!("{}\n{}", title, summary_lines(rows))
+}
+
+/// format_summary の見出しを除いた本文。日次レポートの 1 項目にも使う
+pub fn summary_lines(rows: &[PnlSummaryRow]) -> String {
+    if rows.is_empty() {
+        return "取引なし".to_string();
+    }
+    let mut lines = Vec::new();
+    let mut total = 0f64;
+    let mut total_fees = 0f64;
+    for r in rows {
+        lines.push(format!(
+            "{} {}: {} 件 / 実現 {:+.4} ICP (見込み {:+.4}, 手数料 {:.4}, ずれ {:+.4})",
+            r.day,
+            r.pair,
+            r.trades,
+            r.realized_pnl_e8 / 1e8f64,
+            r.expected_profit_e8 / 1e8f64,
+            r.fees_e8 / 1e8f64,
+            r.slippage_e8 / 1e8f64
+        ));
+        total += r.realized_pnl_e8;
+        total_fees += r.fees_e8;
+    }
+    lines.push(format!(
+        "合計: 実現 {:+.4} ICP / 手数料 {:.4} ICP",
+        total / 1e8f64,
+        total_fees / 1e8f64
+    ));
+    lines.join("\n")
+}
+
+/// UTC の日付が変わるたびに前日分の損益サマリを通知する。日次レポートを送るときはそちらに含めるので起動しない
+pub async fn run_daily_summary(journal: Arc<Journal>, notifier: Arc<dyn Notifier>) {
+    loop {
+        let now = Utc::now();
+        let next_midnight = Period::Daily.next_boundary(now);
+        let wait = (next_midnight - now)
+            .to_std()
+            .unwrap_or(Duration::from_secs(1));
+        sleep(wait).await;
+
+        let end_ms = next_midnight.timestamp_millis();
+        let filter = TradeFilter::between(end_ms - 86_400_000, end_ms);
+        let day = (next_midnight - ChronoDuration::days(1)).format("%Y-%m-%d");
+        match journal.run(move |j| j.pnl_summary(&filter)).await {
+            Ok(rows) => {
+                let message = format_summary(&format!("日次損益 {} (UTC)", day), &rows);
+                info!("{}", message);
+                if let Err(e) = notifier.notify(&message).await {
+                    warn!("日次損益の通知失敗: {}", e);
+                }
+            }
+            Err(e) => warn!("日次損益の集計失敗: {}", e),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// どこで: backtest 用のプールスナップショット記録
+// 何を: tick ごとの Kong/ICS スナップショットをペア・日単位の JSONL に追記し、読み戻す
+// なぜ: 実資金を使わずにパラメータ変更を過去データで検証できるようにするため
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::ic_client::ics::IcsPoolSnapshot;
+use crate::ic_client::kong::KongPoolSnapshot;
+use crate::journal::now_ms;
+
+#[derive(Debug, Error)]
+pub enum RecorderError {
+    #[error("ファイル操作に失敗しました: {0}")]
+    Io(String),
+    #[error("スナップショットの解析に失敗しました: {0}")]
+    Parse(String),
+}
+
+/// JSONL 1 行分
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    pub ts_ms: i64,
+    pub pair: String,
+    pub kong: KongPoolSnapshot,
+    pub ics: IcsPoolSnapshot,
+}
+
+pub struct SnapshotRecorder {
+    dir: PathBuf,
+    last: Mutex<HashMap<String, (KongPoolSnapshot, IcsPoolSnapshot)>>,
+}
+
+impl SnapshotRecorder {
+    pub fn new(dir: &Path) -> Result<Self, RecorderError> {
+        fs::create_dir_all(dir).map_err(|e| RecorderError::Io(e.to_string()))?;
+        Ok(SnapshotRecorder {
+            dir: dir.to_path_buf(),
+            last: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 前回と同じ内容なら書かない（プールが動かない間のディスク消費を抑える）
+    pub fn record(&self, pair: &str, kong: &KongPoolSnapshot, ics: &IcsPoolSnapshot) {
+        {
+            let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((k, i)) = last.get(pair) {
+                if k == kong && i == ics {
+                    return;
+                }
+            }
+            last.insert(pair.to_string(), (kong.clone(), ics.clone()));
+        }
+
+        let record = SnapshotRecord {
+            ts_ms: now_ms(),
+            pair: pair.to_string(),
+            kong: kong.clone(),
+            ics: ics.clone(),
+        };
+        if let Err(e) = self.append(&record) {
+            warn!("{}: スナップショット記録失敗: {}", pair, e);
+        }
+    }
+
+    fn append(&self, record: &SnapshotRecord) -> Result<(), RecorderError> {
+        let day = DateTime::<Utc>::from_timestamp_millis(record.ts_ms)
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let path = self.dir.join(format!("{}-{}.jsonl", record.pair, day));
+        let line =
+            serde_json::to_string(record).map_err(|e| RecorderError::Parse(e.to_string()))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| RecorderError::Io(e.to_string()))?;
+        writeln!(file, "{}", line).map_err(|e| RecorderError::Io(e.to_string()))
+    }
+}
+
+/// dir 配下の `<pair>-*.jsonl` を読み、時刻順に並べて返す
+pub fn load_records(dir: &Path, pair: &str) -> Result<Vec<SnapshotRecord>, RecorderError> {
+    let prefix = format!("{}-", pair);
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| RecorderError::Io(e.to_string()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with(&prefix) && name.ends_with(".jsonl")
+        })
+        .collect();
+    files.sort();
+
+    let mut records = Vec::new();
+    for path in files {
+        let file = fs::File::open(&path).map_err(|e| RecorderError::Io(e.to_string()))?;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| RecorderError::Io(e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: SnapshotRecord = serde_json::from_str(&line).map_err(|e| {
+                RecorderError::Parse(format!("{}:{}: {}", path.display(), i + 1, e))
+            })?;
+            records.push(record);
+        }
+    }
+    records.sort_by_key(|r| r.ts_ms);
+    Ok(records)
+}
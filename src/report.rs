@@ -0,0 +1,257 @@
+// どこで: kong_ics の定期レポート
+// 何を: ペアごとの機会・実行・成否・実現損益・手数料をメモリ上で集計し、残高と allowance を添えて毎時/毎日通知する
+// なぜ: swap ごとの通知だけでは、一定期間に機会がどれだけあり、どれだけ実行して儲かったかが見えないため
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::arb::Trade;
+use crate::journal::{Journal, TradeFilter};
+use crate::notify::{Notification, Notifier, Severity};
+use crate::pnl::{summary_lines, BalanceSnapshot};
+
+/// 集計する期間。境界は UTC の正時 / 0 時
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Hourly,
+    Daily,
+}
+
+impl Period {
+    const ALL: [Period; 2] = [Period::Hourly, Period::Daily];
+
+    fn secs(&self) -> i64 {
+        match self {
+            Period::Hourly => 3_600,
+            Period::Daily => 86_400,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Period::Hourly => 0,
+            Period::Daily => 1,
+        }
+    }
+
+    /// now より後の最初の境界
+    pub fn next_boundary(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = self.secs();
+        let next = (now.timestamp().div_euclid(secs) + 1) * secs;
+        DateTime::from_timestamp(next, 0).unwrap_or(now)
+    }
+
+    fn title(&self, end: DateTime<Utc>) -> String {
+        let start = end - chrono::Duration::seconds(self.secs());
+        match self {
+            Period::Hourly => format!(
+                "毎時レポート {}–{} (UTC)",
+                start.format("%Y-%m-%d %H:%M"),
+                end.format("%H:%M")
+            ),
+            Period::Daily => format!("日次レポート {} (UTC)", start.format("%Y-%m-%d")),
+        }
+    }
+}
+
+/// 1 ペア・1 期間分の集計
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PairCounters {
+    /// 両方のスナップショットが揃って判定した回数
+    pub evaluated: u64,
+    /// 利益しきい値を超えた機会
+    pub opportunities: u64,
+    pub executed: u64,
+    /// 両レッグとも成功した swap
+    pub succeeded: u64,
+    /// 片側でも失敗した swap
+    pub failed: u64,
+    pub realized_pnl_e8: f64,
+    pub fees_e8: f64,
+}
+
+impl PairCounters {
+    fn add(&mut self, other: &PairCounters) {
+        self.evaluated += other.evaluated;
+        self.opportunities += other.opportunities;
+        self.executed += other.executed;
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+        self.realized_pnl_e8 += other.realized_pnl_e8;
+        self.fees_e8 += other.fees_e8;
+    }
+}
+
+/// 期間ごとにペア別の集計を持つ。レポートを出すとその期間の分だけ 0 に戻る
+#[derive(Default)]
+pub struct TradeStats {
+    periods: Mutex<[HashMap<String, PairCounters>; 2]>,
+}
+
+impl TradeStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 判定 1 回分。profitable ならしきい値超えの機会として数え、executed なら実行も数える
+    pub fn record_evaluation(&self, pair: &str, profitable: bool, executed: bool) {
+        self.update(pair, |c| {
+            c.evaluated += 1;
+            c.opportunities += profitable as u64;
+            c.executed += executed as u64;
+        });
+    }
+
+    pub fn record_swap(&self, pair: &str, succeeded: bool) {
+        self.update(pair, |c| {
+            if succeeded {
+                c.succeeded += 1;
+            } else {
+                c.failed += 1;
+            }
+        });
+    }
+
+    pub fn record_pnl(&self, pair: &str, realized_pnl_e8: f64, fees_e8: f64) {
+        self.update(pair, |c| {
+            c.realized_pnl_e8 += realized_pnl_e8;
+            c.fees_e8 += fees_e8;
+        });
+    }
+
+    /// その期間の集計を取り出して 0 に戻す
+    pub fn take(&self, period: Period) -> BTreeMap<String, PairCounters> {
+        let mut periods = self.periods.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut periods[period.index()])
+            .into_iter()
+            .collect()
+    }
+
+    fn update(&self, pair: &str, f: impl Fn(&mut PairCounters)) {
+        let mut periods = self.periods.lock().unwrap_or_else(|e| e.into_inner());
+        for period in Period::ALL {
+            f(periods[period.index()].entry(pair.to_string()).or_default());
+        }
+    }
+}
+
+/// レポート 1 ペア分。残高と allowance はレポート時点の値
+#[derive(Debug, Clone)]
+pub struct PairReport {
+    pub pair: String,
+    pub sns_label: String,
+    pub counters: PairCounters,
+    pub balances: Option<BalanceSnapshot>,
+    /// (トークン→送り先, allowance e8)。取得できなければ None
+    pub allowances: Vec<(String, Option<u128>)>,
+}
+
+pub fn compose_report(title: &str, event: &str, pairs: &[PairReport]) -> Notification {
+    let mut notification = Notification::new(event, Severity::Info, title);
+    let mut total = PairCounters::default();
+    for p in pairs {
+        let c = &p.counters;
+        total.add(c);
+        let mut lines = vec![
+            format!(
+                "機会 {} / 実行 {} (判定 {})",
+                c.opportunities, c.executed, c.evaluated
+            ),
+            format!("成功 {} / 失敗 {}", c.succeeded, c.failed),
+            format!(
+                "実現 {:+.4} ICP / 手数料 {:.4} ICP",
+                c.realized_pnl_e8 / 1e8f64,
+                c.fees_e8 / 1e8f64
+            ),
+        ];
+        lines.push(match p.balances {
+            Some(b) => format!(
+                "残高 ICP {:.4} / {} {:.4}",
+                b.icp_e8 as f64 / 1e8f64,
+                p.sns_label,
+                b.sns_e8 as f64 / 1e8f64
+            ),
+            None => "残高 取得失敗".to_string(),
+        });
+        if !p.allowances.is_empty() {
+            let allowances = p
+                .allowances
+                .iter()
+                .map(|(label, amount)| match amount {
+                    Some(a) => format!("{} {:.4}", label, *a as f64 / 1e8f64),
+                    None => format!("{} 取得失敗", label),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!("allowance {}", allowances));
+        }
+        notification = notification.with_field(&p.pair, lines.join("\n"));
+    }
+    notification.with_field(
+        "合計",
+        format!(
+            "機会 {} / 実行 {} / 成功 {} / 失敗 {}\n実現 {:+.4} ICP / 手数料 {:.4} ICP",
+            total.opportunities,
+            total.executed,
+            total.succeeded,
+            total.failed,
+            total.realized_pnl_e8 / 1e8f64,
+            total.fees_e8 / 1e8f64
+        ),
+    )
+}
+
+/// 期間の境界ごとに集計を取り出し、各ペアの残高と allowance を取り直してレポートを送る。
+/// 日次レポートには、再起動しても消えないジャーナルの実現損益も載せる
+pub async fn run_reports(
+    stats: Arc<TradeStats>,
+    trades: Vec<Arc<Trade>>,
+    notifier: Arc<dyn Notifier>,
+    journal: Option<Arc<Journal>>,
+    period: Period,
+) {
+    let event = match period {
+        Period::Hourly => "report_hourly",
+        Period::Daily => "report_daily",
+    };
+    loop {
+        let now = Utc::now();
+        let boundary = period.next_boundary(now);
+        let wait = (boundary - now).to_std().unwrap_or(Duration::from_secs(1));
+        sleep(wait).await;
+
+        let mut counters = stats.take(period);
+        let mut pairs = Vec::new();
+        for trade in &trades {
+            let (balances, allowances) =
+                tokio::join!(trade.fetch_balances(), trade.fetch_allowances());
+            pairs.push(PairReport {
+                pair: trade.symbol().to_string(),
+                sns_label: trade.sns_label().to_string(),
+                counters: counters.remove(trade.symbol()).unwrap_or_default(),
+                balances,
+                allowances,
+            });
+        }
+        let mut notification = compose_report(&period.title(boundary), event, &pairs);
+        if let (Period::Daily, Some(journal)) = (period, &journal) {
+            let end_ms = boundary.timestamp_millis();
+            let filter = TradeFilter::between(end_ms - period.secs() * 1_000, end_ms);
+            match journal.run(move |j| j.pnl_summary(&filter)).await {
+                Ok(rows) => {
+                    notification =
+                        notification.with_field("実現損益 (ジャーナル)", summary_lines(&rows))
+                }
+                Err(e) => warn!("日次損益の集計失敗: {}", e),
+            }
+        }
+        info!("{}", notification.text());
+        if let Err(e) = notifier.send(&notification).await {
+            warn!("レポートの通知失敗: {}", e);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// どこで: kong_ics の swap 実行前後
+// 何を: 日次の実現損失・直近 1 時間の投入額・同時に実行中の裁定・ペアごとの中間トークン在庫に上限を設け、超えたら見送り・停止・警告する
+// なぜ: 1 回の投入額 (ikiti_e8) 以外に上限が無く、負けが続いても在庫が偏っても取引を続けてしまうため
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use tokio::time::{sleep, Duration};
+use tracing::warn;
+
+use crate::alert::AlertManager;
+use crate::config::RiskConfig;
+use crate::control::{ControlSource, TradingControl, ALL_PAIRS};
+use crate::journal::now_ms;
+use crate::notify::{Notification, Severity};
+use crate::report::Period;
+
+const HOUR_MS: i64 = 3_600_000;
+const DAY_MS: i64 = 86_400_000;
+const DAILY_LOSS_KEY: &str = "risk_daily_loss";
+const NOTIONAL_KEY: &str = "risk_notional";
+
+/// swap を見送った理由（上限はいずれも全ペア合計）
+#[derive(Debug, Error)]
+pub enum RiskBreach {
+    #[error("直近 1 時間の投入額が上限を超えます ({used:.4} + {amount:.4} > {limit:.4} ICP)")]
+    Notional { used: f64, amount: f64, limit: f64 },
+    #[error("実行中の裁定が上限 {0} 件に達しています")]
+    InFlight(usize),
+}
+
+impl RiskBreach {
+    /// trades_refused_total の reason
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RiskBreach::Notional { .. } => "risk_notional",
+            RiskBreach::InFlight(_) => "risk_in_flight",
+        }
+    }
+}
+
+#[derive(Default)]
+struct RiskState {
+    /// 集計中の UTC の日（1970-01-01 からの日数）
+    day: i64,
+    daily_pnl_e8: f64,
+    /// (時刻, 投入額 e8)。1 時間より古いものは捨てる
+    notional: VecDeque<(i64, f64)>,
+    in_flight: usize,
+    /// 停止を掛けた超過のキー（同じ超過で何度も止めない）
+    tripped: HashSet<String>,
+}
+
+impl RiskState {
+    /// 日付が変わっていれば日次損失を 0 に戻す。損失上限で止めていたら true
+    fn roll_day(&mut self, now: i64) -> bool {
+        let day = now.div_euclid(DAY_MS);
+        if day == self.day {
+            return false;
+        }
+        self.day = day;
+        self.daily_pnl_e8 = 0f64;
+        self.tripped.remove(DAILY_LOSS_KEY)
+    }
+
+    fn notional_used(&mut self, now: i64) -> f64 {
+        while self
+            .notional
+            .front()
+            .is_some_and(|(ts, _)| now - ts >= HOUR_MS)
+        {
+            self.notional.pop_front();
+        }
+        self.notional.iter().map(|(_, amount)| amount).sum()
+    }
+}
+
+/// swap 1 回分の枠。持っている間は実行中の裁定として数える
+pub struct RiskPermit<'a> {
+    risk: &'a RiskManager,
+}
+
+impl Drop for RiskPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.risk.lock();
+        state.in_flight = state.in_flight.saturating_sub(1);
+    }
+}
+
+pub struct RiskManager {
+    config: RiskConfig,
+    control: Arc<TradingControl>,
+    alerts: Arc<AlertManager>,
+    state: Mutex<RiskState>,
+}
+
+impl RiskManager {
+    pub fn new(
+        config: RiskConfig,
+        control: Arc<TradingControl>,
+        alerts: Arc<AlertManager>,
+    ) -> Self {
+        RiskManager {
+            config,
+            control,
+            alerts,
+            state: Mutex::new(RiskState {
+                day: now_ms().div_euclid(DAY_MS),
+                ..RiskState::default()
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &RiskConfig {
+        &self.config
+    }
+
+    /// swap 1 回分の枠を取る。取れたら投入額を直近 1 時間の合計に加える（失敗しても戻さない）
+    pub async fn acquire(
+        &self,
+        pair: &str,
+        amount_in_e8: f64,
+    ) -> Result<RiskPermit<'_>, RiskBreach> {
+        let now = now_ms();
+        let (res, rolled) = {
+            let mut state = self.lock();
+            let rolled = state.roll_day(now);
+            let used = state.notional_used(now);
+            let limit = self.config.max_notional_per_hour_e8;
+            let res =
+                if self.config.max_in_flight > 0 && state.in_flight >= self.config.max_in_flight {
+                    Err(RiskBreach::InFlight(self.config.max_in_flight))
+                } else if limit > 0f64 && used + amount_in_e8 > limit {
+                    Err(RiskBreach::Notional {
+                        used: used / 1e8f64,
+                        amount: amount_in_e8 / 1e8f64,
+                        limit: limit / 1e8f64,
+                    })
+                } else {
+                    state.notional.push_back((now, amount_in_e8));
+                    state.in_flight += 1;
+                    Ok(())
+                };
+            (res, rolled)
+        };
+        if rolled {
+            self.daily_loss_reset().await;
+        }
+        match res {
+            Ok(()) => {
+                self.alerts
+                    .healthy(NOTIONAL_KEY, || {
+                        Notification::new(
+                            "risk_notional_recovered",
+                            Severity::Recovered,
+                            "直近 1 時間の投入額が上限を下回りました",
+                        )
+                        .with_pair(pair)
+                    })
+                    .await;
+                Ok(RiskPermit { risk: self })
+            }
+            Err(breach) => {
+                if let RiskBreach::Notional { used, limit, .. } = &breach {
+                    self.alerts
+                        .failing(NOTIONAL_KEY, 1, |_| {
+                            Notification::new(
+                                "risk_notional",
+                                Severity::Warning,
+                                "直近 1 時間の投入額が上限に達したため取引を見送っています",
+                            )
+                            .with_pair(pair)
+                            .with_field("投入額", format!("{:.4} ICP", used))
+                            .with_field("上限", format!("{:.4} ICP", limit))
+                        })
+                        .await;
+                }
+                Err(breach)
+            }
+        }
+    }
+
+    /// 実現損益を日次に足す。損失が上限に達したら全ペアを止めて警告する（同じ日には 1 回だけ）
+    pub async fn record_pnl(&self, pair: &str, realized_pnl_e8: f64) {
+        let limit = self.config.max_daily_loss_e8;
+        let (loss, trip, rolled) = {
+            let mut state = self.lock();
+            let rolled = state.roll_day(now_ms());
+            state.daily_pnl_e8 += realized_pnl_e8;
+            let loss = -state.daily_pnl_e8;
+            let trip = limit > 0f64 && loss >= limit && state.tripped.insert(DAILY_LOSS_KEY.into());
+            (loss, trip, rolled)
+        };
+        if rolled {
+            self.daily_loss_reset().await;
+        }
+        if !trip {
+            return;
+        }
+        let loss = format!("{:.4} ICP", loss / 1e8f64);
+        let limit = format!("{:.4} ICP", limit / 1e8f64);
+        let reason = format!("日次の実現損失 {} が上限 {} に達しました", loss, limit);
+        if let Err(e) = self.control.pause(ALL_PAIRS, ControlSource::Risk, &reason) {
+            warn!("リスク上限による停止に失敗: {}", e);
+        }
+        self.alerts
+            .failing(DAILY_LOSS_KEY, 1, |_| {
+                Notification::new(
+                    "risk_daily_loss",
+                    Severity::Error,
+                    "日次の損失上限に達したため全ペアの取引を停止しました",
+                )
+                .with_pair(pair)
+                .with_field("実現損失 (UTC 当日)", loss)
+                .with_field("上限", limit)
+            })
+            .await;
+    }
+
+    /// ペアの中間トークン残高を見る。上限を超えたらそのペアを止めて警告し、下回ったら復旧を送る（再開は手動）
+    pub async fn record_inventory(&self, pair: &str, token: &str, held_e8: u128) {
+        let Some(limit_e8) = self.config.max_inventory_e8(pair) else {
+            return;
+        };
+        let key = format!("risk_inventory|{}", pair);
+        let held = format!("{:.4} {}", held_e8 as f64 / 1e8f64, token);
+        let limit = format!("{:.4} {}", limit_e8 as f64 / 1e8f64, token);
+        if held_e8 > limit_e8 {
+            if !self.lock().tripped.insert(key.clone()) {
+                return;
+            }
+            let reason = format!("{} の在庫 {} が上限 {} を超えました", token, held, limit);
+            if let Err(e) = self.control.pause(pair, ControlSource::Risk, &reason) {
+                warn!("リスク上限による停止に失敗: {}", e);
+            }
+            self.alerts
+                .failing(&key, 1, |_| {
+                    Notification::new(
+                        "risk_inventory",
+                        Severity::Error,
+                        "中間トークンの在庫が上限を超えたため取引を停止しました",
+                    )
+                    .with_pair(pair)
+                    .with_field("在庫", held)
+                    .with_field("上限", limit)
+                })
+                .await;
+        } else if self.lock().tripped.remove(&key) {
+            self.alerts
+                .healthy(&key, || {
+                    Notification::new(
+                        "risk_inventory_recovered",
+                        Severity::Recovered,
+                        "中間トークンの在庫が上限を下回りました（取引の再開は /control/resume）",
+                    )
+                    .with_pair(pair)
+                    .with_field("在庫", held)
+                    .with_field("上限", limit)
+                })
+                .await;
+        }
+    }
+
+    /// UTC 当日の実現損益
+    pub fn daily_pnl_e8(&self) -> f64 {
+        self.lock().daily_pnl_e8
+    }
+
+    /// now_ms の時点で日付が変わっていれば日次損失を 0 に戻し、損失上限による停止を解く。
+    /// 停止中は acquire / record_pnl が呼ばれないので、run_day_rollover から定期的に呼ぶ
+    pub async fn roll_day(&self, now_ms: i64) {
+        let rolled = self.lock().roll_day(now_ms);
+        if rolled {
+            self.daily_loss_reset().await;
+        }
+    }
+
+    /// 損失上限で止めた全ペアの停止を解き、復旧を送る（管理 API や停止ファイルの停止はそのまま）
+    async fn daily_loss_reset(&self) {
+        if let Err(e) = self.control.resume(ALL_PAIRS, ControlSource::Risk) {
+            warn!("日次損失のリセット後の再開に失敗: {}", e);
+        }
+        self.alerts
+            .healthy(DAILY_LOSS_KEY, || {
+                Notification::new(
+                    "risk_daily_loss_recovered",
+                    Severity::Recovered,
+                    "日付が変わったので日次の損失をリセットし、取引を再開しました",
+                )
+            })
+            .await;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RiskState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// UTC 0 時ごとに日次損失を切り替える（損失上限で止めている間も日付の切り替えを逃さないように）
+pub async fn run_day_rollover(risk: Arc<RiskManager>) {
+    loop {
+        let now: DateTime<Utc> = Utc::now();
+        let boundary = Period::Daily.next_boundary(now);
+        let wait = (boundary - now).to_std().unwrap_or(Duration::from_secs(1));
+        sleep(wait).await;
+        risk.roll_day(now_ms()).await;
+    }
+}
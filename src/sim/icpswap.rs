@@ -0,0 +1,165 @@
+// どこで: シミュレータ内の ICPSwap プール canister
+// 何を: metadata / quote / depositFromAndSwap を全レンジ流動性（定数積）で再現する
+// なぜ: sqrtPriceX96 と liquidity から k を出す bot の計算と、実際の約定量を突き合わせるため
+
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
+use serde::Serialize;
+
+use crate::ic_client::ics::{PoolError as IcsError, PoolMetadata, Token};
+
+use super::ledger::{key_of, AccountKey, SimLedger};
+
+/// ICPSwap の 1 プール。token0 が SNS 側、token1 が ICP 側（bot の想定と同じ）
+#[derive(Debug, Clone)]
+pub struct SimIcsPool {
+    pub ledger0: String,
+    pub ledger1: String,
+    /// 全レンジ流動性の仮想リザーブ
+    pub reserve0: u128,
+    pub reserve1: u128,
+    /// 手数料 (pips)。3000 で 0.3%
+    pub fee_pips: u32,
+}
+
+impl SimIcsPool {
+    /// 出力量（出金時の transfer fee は引かない）
+    pub fn quote(&self, amount_in: u128, zero_for_one: bool) -> u128 {
+        let in_eff = amount_in * (1_000_000 - self.fee_pips as u128) / 1_000_000;
+        let (r_in, r_out) = if zero_for_one {
+            (self.reserve0, self.reserve1)
+        } else {
+            (self.reserve1, self.reserve0)
+        };
+        let k = r_in.saturating_mul(r_out);
+        r_out.saturating_sub(k / (r_in + in_eff).max(1))
+    }
+
+    fn metadata(&self) -> PoolMetadata {
+        let r0 = self.reserve0 as f64;
+        let r1 = self.reserve1 as f64;
+        let price = if r0 > 0f64 { r1 / r0 } else { 0f64 };
+        let sqrt_price_x96 = price.sqrt() * 2f64.powi(96);
+        PoolMetadata {
+            fee: Nat::from(self.fee_pips),
+            key: format!("{}_{}_{}", self.ledger0, self.ledger1, self.fee_pips),
+            liquidity: Nat::from((r0 * r1).sqrt() as u128),
+            max_liquidity_per_tick: Nat::from(u128::MAX),
+            next_position_id: Nat::from(1u32),
+            sqrt_price_x96: Nat::from(sqrt_price_x96 as u128),
+            // log_{1.0001}(price)
+            tick: Int::from(if price > 0f64 {
+                (price.ln() / 1.0001f64.ln()).floor() as i64
+            } else {
+                0
+            }),
+            token0: Token {
+                address: self.ledger0.clone(),
+                standard: "ICRC2".to_string(),
+            },
+            token1: Token {
+                address: self.ledger1.clone(),
+                standard: "ICRC2".to_string(),
+            },
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct SwapArgs {
+    #[serde(rename = "amountIn")]
+    pub amount_in: String,
+    #[serde(rename = "zeroForOne")]
+    pub zero_for_one: bool,
+    #[serde(rename = "amountOutMinimum")]
+    pub amount_out_minimum: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct DepositAndSwapArgs {
+    #[serde(rename = "tokenInFee")]
+    pub token_in_fee: Nat,
+    #[serde(rename = "amountIn")]
+    pub amount_in: String,
+    #[serde(rename = "zeroForOne")]
+    pub zero_for_one: bool,
+    #[serde(rename = "amountOutMinimum")]
+    pub amount_out_minimum: String,
+    #[serde(rename = "tokenOutFee")]
+    pub token_out_fee: Nat,
+}
+
+pub struct SimIcs {
+    pub canister: Principal,
+    pub pool: SimIcsPool,
+}
+
+impl SimIcs {
+    pub fn metadata(&self) -> PoolMetadata {
+        self.pool.metadata()
+    }
+
+    pub fn quote(&self, args: &SwapArgs) -> Result<u128, IcsError> {
+        let amount_in = parse_amount(&args.amount_in)?;
+        Ok(self.pool.quote(amount_in, args.zero_for_one))
+    }
+
+    /// transfer_from で入金し、そのまま swap して出金する。
+    /// swap できなかった入金は手元に戻す（入金・返金の transfer fee は失われる）
+    pub fn deposit_from_and_swap(
+        &mut self,
+        ledgers: &mut std::collections::HashMap<String, SimLedger>,
+        caller: AccountKey,
+        args: &DepositAndSwapArgs,
+    ) -> Result<u128, IcsError> {
+        let amount_in = parse_amount(&args.amount_in)?;
+        let min_out = parse_amount(&args.amount_out_minimum)?;
+        let (ledger_in, ledger_out) = if args.zero_for_one {
+            (&self.pool.ledger0, &self.pool.ledger1)
+        } else {
+            (&self.pool.ledger1, &self.pool.ledger0)
+        };
+        let (ledger_in, ledger_out) = (ledger_in.clone(), ledger_out.clone());
+        let fee_in = ledgers.get(&ledger_in).map(|l| l.fee).unwrap_or(0);
+        let fee_out = ledgers.get(&ledger_out).map(|l| l.fee).unwrap_or(0);
+        if super::nat_u128(&args.token_in_fee) != Some(fee_in)
+            || super::nat_u128(&args.token_out_fee) != Some(fee_out)
+        {
+            return Err(IcsError::InternalError(
+                "tokenInFee / tokenOutFee が台帳の手数料と一致しません".to_string(),
+            ));
+        }
+        let pool_account = key_of(self.canister, None);
+        ledgers
+            .get_mut(&ledger_in)
+            .ok_or_else(|| IcsError::UnsupportedToken(ledger_in.clone()))?
+            .transfer_from(pool_account, caller, pool_account, amount_in, None)
+            .map_err(|e| IcsError::InternalError(format!("deposit failed: {:?}", e)))?;
+
+        let out = self.pool.quote(amount_in, args.zero_for_one);
+        if out < min_out || out <= fee_out {
+            if let Some(l) = ledgers.get_mut(&ledger_in) {
+                let _ = l.transfer(pool_account, caller, amount_in.saturating_sub(fee_in), None);
+            }
+            return Err(IcsError::InternalError(format!(
+                "Slippage is over range: amountOut {} < amountOutMinimum {}",
+                out, min_out
+            )));
+        }
+        if args.zero_for_one {
+            self.pool.reserve0 += amount_in;
+            self.pool.reserve1 -= out;
+        } else {
+            self.pool.reserve1 += amount_in;
+            self.pool.reserve0 -= out;
+        }
+        if let Some(l) = ledgers.get_mut(&ledger_out) {
+            let _ = l.transfer(pool_account, caller, out - fee_out, None);
+        }
+        Ok(out)
+    }
+}
+
+fn parse_amount(text: &str) -> Result<u128, IcsError> {
+    text.parse::<u128>()
+        .map_err(|e| IcsError::InternalError(format!("amount {}: {}", text, e)))
+}
@@ -0,0 +1,282 @@
+// どこで: シミュレータ内の Kong canister
+// 何を: pools / swap_async / requests を定数積プールで再現し、約定でリザーブと LP fee を動かす
+// なぜ: 判定ロジックと実際の約定結果のずれ（スリッページ・手数料）をネットワーク無しで確かめるため
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+
+use super::ledger::{AccountKey, SimLedger};
+
+/// Kong の 1 プール。token_0 が SNS 側、token_1 が ICP 側（bot の想定と同じ）
+#[derive(Debug, Clone)]
+pub struct SimKongPool {
+    /// "BOB_ICP" のようなプール名（pools の絞り込みに使う）
+    pub symbol: String,
+    pub symbol_0: String,
+    pub symbol_1: String,
+    /// 台帳 canister id
+    pub ledger_0: String,
+    pub ledger_1: String,
+    pub balance_0: u128,
+    pub balance_1: u128,
+    pub lp_fee_0: u128,
+    pub lp_fee_1: u128,
+    pub lp_fee_bps: u8,
+}
+
+impl SimKongPool {
+    /// 手数料込みのリザーブ比（token_1 per token_0）
+    pub fn price(&self) -> f64 {
+        let r0 = self.balance_0 + self.lp_fee_0;
+        let r1 = self.balance_1 + self.lp_fee_1;
+        if r0 == 0 {
+            0f64
+        } else {
+            r1 as f64 / r0 as f64
+        }
+    }
+
+    /// token 指定（"IC.<canister>"・canister id・シンボル）が 0 / 1 のどちらかを返す
+    fn side(&self, token: &str) -> Option<usize> {
+        let id = token.strip_prefix("IC.").unwrap_or(token);
+        if id == self.ledger_0 || token == self.symbol_0 {
+            Some(0)
+        } else if id == self.ledger_1 || token == self.symbol_1 {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    /// LP fee を差し引いた定数積で出力量を出す（出力側の transfer fee は引かない）
+    fn quote(&self, pay_side: usize, pay_amount: u128) -> (u128, u128) {
+        let amount_eff = pay_amount * (10_000 - self.lp_fee_bps as u128) / 10_000;
+        let r0 = self.balance_0 + self.lp_fee_0;
+        let r1 = self.balance_1 + self.lp_fee_1;
+        let (r_in, r_out) = if pay_side == 0 { (r0, r1) } else { (r1, r0) };
+        let k = r_in.saturating_mul(r_out);
+        let out = r_out.saturating_sub(k / (r_in + amount_eff).max(1));
+        (amount_eff, out)
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SwapArgs {
+    pub pay_token: String,
+    pub pay_amount: Nat,
+    pub pay_tx_id: Option<candid::Reserved>,
+    pub receive_token: String,
+    pub receive_amount: Option<Nat>,
+    pub receive_address: Option<String>,
+    pub max_slippage: Option<f64>,
+    pub referred_by: Option<String>,
+}
+
+#[derive(CandidType, Debug, Clone)]
+pub struct PoolReply {
+    pub pool_id: u32,
+    pub name: String,
+    pub symbol: String,
+    pub chain_0: String,
+    pub symbol_0: String,
+    pub address_0: String,
+    pub balance_0: Nat,
+    pub lp_fee_0: Nat,
+    pub chain_1: String,
+    pub symbol_1: String,
+    pub address_1: String,
+    pub balance_1: Nat,
+    pub lp_fee_1: Nat,
+    pub price: f64,
+    pub lp_fee_bps: u8,
+    pub lp_token_symbol: String,
+    pub is_removed: bool,
+}
+
+#[derive(CandidType, Debug, Clone)]
+pub struct SwapReply {
+    pub request_id: u64,
+    pub status: String,
+    pub pay_symbol: String,
+    pub pay_amount: Nat,
+    pub receive_symbol: String,
+    pub receive_amount: Nat,
+    pub price: f64,
+    pub ts: u64,
+}
+
+#[derive(CandidType, Debug, Clone)]
+pub enum Reply {
+    Pending,
+    Swap(SwapReply),
+}
+
+#[derive(CandidType, Debug, Clone)]
+pub struct RequestsReply {
+    pub request_id: u64,
+    pub statuses: Vec<String>,
+    pub reply: Reply,
+    pub ts: u64,
+}
+
+pub struct SimKong {
+    pub canister: Principal,
+    pub pools: Vec<SimKongPool>,
+    pub requests: Vec<RequestsReply>,
+}
+
+impl SimKong {
+    pub fn new(canister: Principal) -> Self {
+        SimKong {
+            canister,
+            pools: Vec::new(),
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn pools_reply(&self, symbol: Option<&str>) -> Vec<PoolReply> {
+        self.pools
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| symbol.is_none_or(|s| s == p.symbol))
+            .map(|(i, p)| PoolReply {
+                pool_id: i as u32 + 1,
+                name: format!("{} Liquidity Pool", p.symbol),
+                symbol: p.symbol.clone(),
+                chain_0: "IC".to_string(),
+                symbol_0: p.symbol_0.clone(),
+                address_0: p.ledger_0.clone(),
+                balance_0: Nat::from(p.balance_0),
+                lp_fee_0: Nat::from(p.lp_fee_0),
+                chain_1: "IC".to_string(),
+                symbol_1: p.symbol_1.clone(),
+                address_1: p.ledger_1.clone(),
+                balance_1: Nat::from(p.balance_1),
+                lp_fee_1: Nat::from(p.lp_fee_1),
+                price: p.price(),
+                lp_fee_bps: p.lp_fee_bps,
+                lp_token_symbol: p.symbol.clone(),
+                is_removed: false,
+            })
+            .collect()
+    }
+
+    /// swap_async。引数と支払いの検証に失敗したら Err、通ったら request id を返す。
+    /// スリッページ超過は Kong と同じく request の状態に残し、支払いを（transfer fee を引いて）返金する
+    pub fn swap_async(
+        &mut self,
+        ledgers: &mut std::collections::HashMap<String, SimLedger>,
+        caller: AccountKey,
+        args: &SwapArgs,
+        ts: u64,
+    ) -> Result<u64, String> {
+        let pay_amount = super::nat_u128(&args.pay_amount).ok_or("pay_amount が大きすぎます")?;
+        let min_receive = match &args.receive_amount {
+            Some(n) => super::nat_u128(n).ok_or("receive_amount が大きすぎます")?,
+            None => 0,
+        };
+        let (pool_idx, pay_side) = self
+            .pools
+            .iter()
+            .enumerate()
+            .find_map(|(i, p)| {
+                let pay = p.side(&args.pay_token)?;
+                (p.side(&args.receive_token)? == 1 - pay).then_some((i, pay))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Pool not found: {} -> {}",
+                    args.pay_token, args.receive_token
+                )
+            })?;
+        if pay_amount == 0 {
+            return Err("Pay amount is zero".to_string());
+        }
+        let pool = &self.pools[pool_idx];
+        let (pay_ledger, receive_ledger) = if pay_side == 0 {
+            (pool.ledger_0.clone(), pool.ledger_1.clone())
+        } else {
+            (pool.ledger_1.clone(), pool.ledger_0.clone())
+        };
+        let kong_account = super::ledger::key_of(self.canister, None);
+        ledgers
+            .get_mut(&pay_ledger)
+            .ok_or_else(|| format!("ledger が未登録です: {}", pay_ledger))?
+            .transfer_from(kong_account, caller, kong_account, pay_amount, None)
+            .map_err(|e| format!("transfer_from failed: {:?}", e))?;
+
+        let request_id = self.requests.len() as u64 + 1;
+        let receive_fee = ledgers.get(&receive_ledger).map(|l| l.fee).unwrap_or(0);
+        let (amount_eff, out) = pool.quote(pay_side, pay_amount);
+        let receive_amount = out.saturating_sub(receive_fee);
+        let price = pool.price();
+        let (pay_symbol, receive_symbol) = if pay_side == 0 {
+            (pool.symbol_0.clone(), pool.symbol_1.clone())
+        } else {
+            (pool.symbol_1.clone(), pool.symbol_0.clone())
+        };
+
+        let mut statuses = vec!["Started".to_string(), "Receive token requested".to_string()];
+        let status = if receive_amount < min_receive || receive_amount == 0 {
+            // 受け取った支払いを返す（返金の transfer fee は利用者持ちなので fee 分少なく戻る）
+            let pay_fee = ledgers.get(&pay_ledger).map(|l| l.fee).unwrap_or(0);
+            let refund = pay_amount.saturating_sub(pay_fee);
+            if let Some(l) = ledgers.get_mut(&pay_ledger) {
+                let _ = l.transfer(kong_account, caller, refund, None);
+            }
+            statuses.push(format!(
+                "Slippage exceeded. Can only receive {} {} with {} max slippage",
+                receive_amount, receive_symbol, min_receive
+            ));
+            statuses.push("Returning pay token".to_string());
+            statuses.push("Failed".to_string());
+            "Failed"
+        } else {
+            let pool = &mut self.pools[pool_idx];
+            let lp_fee = pay_amount - amount_eff;
+            if pay_side == 0 {
+                pool.balance_0 += amount_eff;
+                pool.lp_fee_0 += lp_fee;
+                pool.balance_1 = pool.balance_1.saturating_sub(out);
+            } else {
+                pool.balance_1 += amount_eff;
+                pool.lp_fee_1 += lp_fee;
+                pool.balance_0 = pool.balance_0.saturating_sub(out);
+            }
+            if let Some(l) = ledgers.get_mut(&receive_ledger) {
+                let _ = l.transfer(kong_account, caller, receive_amount, None);
+            }
+            statuses.push("Swap success".to_string());
+            statuses.push("Receive token sent".to_string());
+            statuses.push("Success".to_string());
+            "Success"
+        };
+        self.requests.push(RequestsReply {
+            request_id,
+            statuses,
+            reply: Reply::Swap(SwapReply {
+                request_id,
+                status: status.to_string(),
+                pay_symbol,
+                pay_amount: Nat::from(pay_amount),
+                receive_symbol,
+                receive_amount: Nat::from(if status == "Success" {
+                    receive_amount
+                } else {
+                    0
+                }),
+                price,
+                ts,
+            }),
+            ts,
+        });
+        Ok(request_id)
+    }
+
+    pub fn requests_reply(&self, request_id: Option<u64>) -> Vec<RequestsReply> {
+        self.requests
+            .iter()
+            .filter(|r| request_id.is_none_or(|id| id == r.request_id))
+            .cloned()
+            .collect()
+    }
+}
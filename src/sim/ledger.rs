@@ -0,0 +1,203 @@
+// どこで: シミュレータ内の ICRC-1/2 台帳
+// 何を: 残高・allowance・手数料を持ち、transfer / approve / transfer_from を台帳と同じ規則で処理する
+// なぜ: swap や approve_manager の結果を残高の動きとして確かめられるようにするため
+
+use std::collections::HashMap;
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+
+/// 残高の持ち主。subaccount 無しは 0 埋め 32 バイトと同じ扱い
+pub type AccountKey = (Principal, [u8; 32]);
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl Account {
+    pub fn key(&self) -> AccountKey {
+        key_of(self.owner, self.subaccount.as_deref())
+    }
+}
+
+pub fn key_of(owner: Principal, subaccount: Option<&[u8]>) -> AccountKey {
+    let mut sub = [0u8; 32];
+    if let Some(s) = subaccount {
+        let n = s.len().min(32);
+        sub[..n].copy_from_slice(&s[..n]);
+    }
+    (owner, sub)
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+/// icrc1_transfer / icrc2_approve / icrc2_transfer_from の Err で使う分だけ
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum LedgerReject {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+}
+
+pub struct SimLedger {
+    pub fee: u128,
+    balances: HashMap<AccountKey, u128>,
+    allowances: HashMap<(AccountKey, AccountKey), u128>,
+    next_block: u128,
+}
+
+impl SimLedger {
+    pub fn new(fee: u128) -> Self {
+        SimLedger {
+            fee,
+            balances: HashMap::new(),
+            allowances: HashMap::new(),
+            next_block: 0,
+        }
+    }
+
+    pub fn mint(&mut self, to: AccountKey, amount: u128) {
+        *self.balances.entry(to).or_default() += amount;
+    }
+
+    pub fn balance(&self, account: &AccountKey) -> u128 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    pub fn allowance(&self, owner: &AccountKey, spender: &AccountKey) -> u128 {
+        self.allowances
+            .get(&(*owner, *spender))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// 送り手が amount + fee を払う
+    pub fn transfer(
+        &mut self,
+        from: AccountKey,
+        to: AccountKey,
+        amount: u128,
+        fee: Option<u128>,
+    ) -> Result<u128, LedgerReject> {
+        self.check_fee(fee)?;
+        self.debit(&from, amount + self.fee)?;
+        self.mint(to, amount);
+        Ok(self.block())
+    }
+
+    /// allowance を上書きする。手数料は owner の残高から引く
+    pub fn approve(
+        &mut self,
+        owner: AccountKey,
+        spender: AccountKey,
+        amount: u128,
+        expected_allowance: Option<u128>,
+        fee: Option<u128>,
+    ) -> Result<u128, LedgerReject> {
+        self.check_fee(fee)?;
+        let current = self.allowance(&owner, &spender);
+        if let Some(expected) = expected_allowance {
+            if expected != current {
+                return Err(LedgerReject::AllowanceChanged {
+                    current_allowance: Nat::from(current),
+                });
+            }
+        }
+        self.debit(&owner, self.fee)?;
+        self.allowances.insert((owner, spender), amount);
+        Ok(self.block())
+    }
+
+    /// allowance は amount + fee 分だけ消費する
+    pub fn transfer_from(
+        &mut self,
+        spender: AccountKey,
+        from: AccountKey,
+        to: AccountKey,
+        amount: u128,
+        fee: Option<u128>,
+    ) -> Result<u128, LedgerReject> {
+        self.check_fee(fee)?;
+        let allowance = self.allowance(&from, &spender);
+        let total = amount + self.fee;
+        if allowance < total {
+            return Err(LedgerReject::InsufficientAllowance {
+                allowance: Nat::from(allowance),
+            });
+        }
+        self.debit(&from, total)?;
+        self.allowances.insert((from, spender), allowance - total);
+        self.mint(to, amount);
+        Ok(self.block())
+    }
+
+    fn check_fee(&self, fee: Option<u128>) -> Result<(), LedgerReject> {
+        match fee {
+            Some(f) if f != self.fee => Err(LedgerReject::BadFee {
+                expected_fee: Nat::from(self.fee),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn debit(&mut self, account: &AccountKey, amount: u128) -> Result<(), LedgerReject> {
+        let balance = self.balance(account);
+        if balance < amount {
+            return Err(LedgerReject::InsufficientFunds {
+                balance: Nat::from(balance),
+            });
+        }
+        self.balances.insert(*account, balance - amount);
+        Ok(())
+    }
+
+    fn block(&mut self) -> u128 {
+        self.next_block += 1;
+        self.next_block - 1
+    }
+}
@@ -0,0 +1,466 @@
+// どこで: テストやオフライン実行で IcClient の下に差し込むシミュレータ
+// 何を: ICRC-1/2 台帳・Kong・ICPSwap プールをメモリ内で動かし、CanisterTransport として candid で応答する
+// なぜ: リザーブの変化・スリッページ・手数料・allowance を含む一連の流れを、ネットワーク無しで再現するため
+
+pub mod icpswap;
+pub mod kong;
+pub mod ledger;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use ic_agent::agent::RejectCode;
+use ic_agent::RequestId;
+use serde::Deserialize;
+
+use crate::ic_client::agent::IcClientError;
+use crate::ic_client::transport::{BoxFuture, CanisterTransport};
+use crate::journal::now_ms;
+
+use crate::ic_client::ics::{IcsResult, PoolError as IcsError};
+use icpswap::{DepositAndSwapArgs, SimIcs, SimIcsPool, SwapArgs as IcsSwapArgs};
+use kong::{RequestsReply, SimKong, SimKongPool, SwapArgs as KongSwapArgs};
+use ledger::{
+    key_of, Account, Allowance, AllowanceArgs, ApproveArgs, SimLedger, TransferArg,
+    TransferFromArgs,
+};
+
+fn ics_result<T>(res: Result<T, IcsError>) -> IcsResult<T> {
+    match res {
+        Ok(v) => IcsResult::Ok(v),
+        Err(e) => IcsResult::Err(e),
+    }
+}
+
+#[derive(Default)]
+struct SimState {
+    ledgers: HashMap<String, SimLedger>,
+    kong: HashMap<String, SimKong>,
+    ics: HashMap<String, SimIcs>,
+    /// submit 済みで wait 待ちの結果（RequestId は Hash を持たないので hex で引く）
+    pending: HashMap<String, Result<Vec<u8>, IcClientError>>,
+    /// canister に届く前に返す障害（通信エラーなど）
+    injected: HashMap<(String, String), VecDeque<IcClientError>>,
+    calls: Vec<(String, String)>,
+    next_request: u64,
+}
+
+/// bot の principal から呼ばれた体で各 canister を処理する。
+/// update は submit 時点で実行し、結果は wait で返す
+pub struct Simulator {
+    state: Mutex<SimState>,
+    principal: Principal,
+    verifies_queries: bool,
+}
+
+impl Simulator {
+    pub fn new(principal: Principal) -> Self {
+        Simulator {
+            state: Mutex::new(SimState::default()),
+            principal,
+            verifies_queries: true,
+        }
+    }
+
+    /// 署名検証をしない agent を模す（既定は検証済み扱い。検証失敗は inject_error で Certificate を積む）
+    pub fn without_query_verification(mut self) -> Self {
+        self.verifies_queries = false;
+        self
+    }
+
+    pub fn add_ledger(&self, canister: &str, fee: u128) {
+        self.state()
+            .ledgers
+            .insert(canister.to_string(), SimLedger::new(fee));
+    }
+
+    pub fn mint(&self, ledger: &str, owner: Principal, amount: u128) {
+        if let Some(l) = self.state().ledgers.get_mut(ledger) {
+            l.mint(key_of(owner, None), amount);
+        }
+    }
+
+    pub fn mint_to_subaccount(
+        &self,
+        ledger: &str,
+        owner: Principal,
+        subaccount: Option<&[u8]>,
+        amount: u128,
+    ) {
+        if let Some(l) = self.state().ledgers.get_mut(ledger) {
+            l.mint(key_of(owner, subaccount), amount);
+        }
+    }
+
+    pub fn balance(&self, ledger: &str, owner: Principal) -> u128 {
+        self.state()
+            .ledgers
+            .get(ledger)
+            .map(|l| l.balance(&key_of(owner, None)))
+            .unwrap_or(0)
+    }
+
+    pub fn allowance(&self, ledger: &str, owner: Principal, spender: &str) -> u128 {
+        let Ok(spender) = Principal::from_text(spender) else {
+            return 0;
+        };
+        self.state()
+            .ledgers
+            .get(ledger)
+            .map(|l| l.allowance(&key_of(owner, None), &key_of(spender, None)))
+            .unwrap_or(0)
+    }
+
+    /// Kong にプールを足し、リザーブと LP fee 分を Kong の口座に発行する
+    pub fn add_kong_pool(&self, kong_canister: &str, pool: SimKongPool) {
+        let canister = parse_principal(kong_canister);
+        let mut state = self.state();
+        let account = key_of(canister, None);
+        if let Some(l) = state.ledgers.get_mut(&pool.ledger_0) {
+            l.mint(account, pool.balance_0 + pool.lp_fee_0);
+        }
+        if let Some(l) = state.ledgers.get_mut(&pool.ledger_1) {
+            l.mint(account, pool.balance_1 + pool.lp_fee_1);
+        }
+        state
+            .kong
+            .entry(kong_canister.to_string())
+            .or_insert_with(|| SimKong::new(canister))
+            .pools
+            .push(pool);
+    }
+
+    /// ICPSwap プールを足し、リザーブ分をプールの口座に発行する
+    pub fn add_ics_pool(&self, lp_canister: &str, pool: SimIcsPool) {
+        let canister = parse_principal(lp_canister);
+        let mut state = self.state();
+        let account = key_of(canister, None);
+        if let Some(l) = state.ledgers.get_mut(&pool.ledger0) {
+            l.mint(account, pool.reserve0);
+        }
+        if let Some(l) = state.ledgers.get_mut(&pool.ledger1) {
+            l.mint(account, pool.reserve1);
+        }
+        state
+            .ics
+            .insert(lp_canister.to_string(), SimIcs { canister, pool });
+    }
+
+    pub fn kong_pool(&self, kong_canister: &str, symbol: &str) -> Option<SimKongPool> {
+        self.state()
+            .kong
+            .get(kong_canister)?
+            .pools
+            .iter()
+            .find(|p| p.symbol == symbol)
+            .cloned()
+    }
+
+    pub fn ics_pool(&self, lp_canister: &str) -> Option<SimIcsPool> {
+        self.state().ics.get(lp_canister).map(|s| s.pool.clone())
+    }
+
+    /// 他の参加者の取引を模してプールを書き換える（台帳残高は動かさない）
+    pub fn update_kong_pool(
+        &self,
+        kong_canister: &str,
+        symbol: &str,
+        f: impl FnOnce(&mut SimKongPool),
+    ) {
+        if let Some(pool) = self
+            .state()
+            .kong
+            .get_mut(kong_canister)
+            .and_then(|k| k.pools.iter_mut().find(|p| p.symbol == symbol))
+        {
+            f(pool);
+        }
+    }
+
+    /// 他の参加者の取引を模してプールを書き換える（台帳残高は動かさない）
+    pub fn update_ics_pool(&self, lp_canister: &str, f: impl FnOnce(&mut SimIcsPool)) {
+        if let Some(ics) = self.state().ics.get_mut(lp_canister) {
+            f(&mut ics.pool);
+        }
+    }
+
+    pub fn kong_requests(&self, kong_canister: &str) -> Vec<RequestsReply> {
+        self.state()
+            .kong
+            .get(kong_canister)
+            .map(|k| k.requests.clone())
+            .unwrap_or_default()
+    }
+
+    /// 次の (canister, method) 呼び出しを canister に届けずに err で失敗させる
+    pub fn inject_error(&self, canister: &str, method: &str, err: IcClientError) {
+        self.state()
+            .injected
+            .entry((canister.to_string(), method.to_string()))
+            .or_default()
+            .push_back(err);
+    }
+
+    /// 指定 method の呼び出し回数（注入した障害も含む）
+    pub fn call_count(&self, method: &str) -> usize {
+        self.state()
+            .calls
+            .iter()
+            .filter(|(_, m)| m == method)
+            .count()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, SimState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn call(
+        &self,
+        canister: &str,
+        method: &str,
+        args: &[u8],
+        update: bool,
+    ) -> Result<Vec<u8>, IcClientError> {
+        let mut state = self.state();
+        state.calls.push((canister.to_string(), method.to_string()));
+        let key = (canister.to_string(), method.to_string());
+        if let Some(err) = state.injected.get_mut(&key).and_then(|q| q.pop_front()) {
+            return Err(err);
+        }
+        let caller = key_of(self.principal, None);
+        let SimState {
+            ledgers, kong, ics, ..
+        } = &mut *state;
+        let res = if let Some(ledger) = ledgers.get_mut(canister) {
+            handle_ledger(ledger, caller, method, args, update)
+        } else if let Some(kong) = kong.get_mut(canister) {
+            handle_kong(kong, ledgers, caller, method, args, update)
+        } else if let Some(ics) = ics.get_mut(canister) {
+            handle_ics(ics, ledgers, caller, method, args, update)
+        } else {
+            return Err(reject(
+                method,
+                RejectCode::DestinationInvalid,
+                format!("canister が存在しません: {}", canister),
+            ));
+        };
+        res.map_err(|msg| reject(method, RejectCode::CanisterError, msg))
+    }
+}
+
+impl CanisterTransport for Simulator {
+    fn query<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        _call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move { self.call(canister, method, &args, false) })
+    }
+
+    fn submit<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        _ingress_expiry: Duration,
+        _call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<RequestId, IcClientError>> {
+        Box::pin(async move {
+            let res = self.call(canister, method, &args, true);
+            // 送信自体の障害（注入したもの）は request id を返さない
+            if let Err(e @ IcClientError::Transport { .. }) = res {
+                return Err(e);
+            }
+            let mut state = self.state();
+            state.next_request += 1;
+            let mut id = [0u8; 32];
+            id[24..].copy_from_slice(&state.next_request.to_be_bytes());
+            let request_id = RequestId::new(&id);
+            state.pending.insert(String::from(request_id), res);
+            Ok(request_id)
+        })
+    }
+
+    fn wait<'a>(
+        &'a self,
+        _canister: &'a str,
+        method: &'a str,
+        request_id: RequestId,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move {
+            self.state()
+                .pending
+                .remove(&String::from(request_id))
+                .unwrap_or_else(|| {
+                    Err(reject(
+                        method,
+                        RejectCode::DestinationInvalid,
+                        format!("不明な request id {}", String::from(request_id)),
+                    ))
+                })
+        })
+    }
+
+    fn principal(&self) -> Result<Principal, String> {
+        Ok(self.principal)
+    }
+
+    fn verifies_queries(&self) -> bool {
+        self.verifies_queries
+    }
+}
+
+fn handle_ledger(
+    ledger: &mut SimLedger,
+    caller: ledger::AccountKey,
+    method: &str,
+    args: &[u8],
+    update: bool,
+) -> Result<Vec<u8>, String> {
+    match method {
+        "icrc1_fee" => encode(&Nat::from(ledger.fee)),
+        "icrc1_balance_of" => {
+            let account = decode::<Account>(args)?;
+            encode(&Nat::from(ledger.balance(&account.key())))
+        }
+        "icrc2_allowance" => {
+            let a = decode::<AllowanceArgs>(args)?;
+            encode(&Allowance {
+                allowance: Nat::from(ledger.allowance(&a.account.key(), &a.spender.key())),
+                expires_at: None,
+            })
+        }
+        "icrc1_transfer" if update => {
+            let a = decode::<TransferArg>(args)?;
+            let from = key_of(caller.0, a.from_subaccount.as_deref());
+            let res = ledger
+                .transfer(from, a.to.key(), nat(&a.amount)?, opt_nat(&a.fee)?)
+                .map(Nat::from);
+            encode(&res)
+        }
+        "icrc2_approve" if update => {
+            let a = decode::<ApproveArgs>(args)?;
+            let owner = key_of(caller.0, a.from_subaccount.as_deref());
+            let res = ledger
+                .approve(
+                    owner,
+                    a.spender.key(),
+                    nat(&a.amount)?,
+                    opt_nat(&a.expected_allowance)?,
+                    opt_nat(&a.fee)?,
+                )
+                .map(Nat::from);
+            encode(&res)
+        }
+        "icrc2_transfer_from" if update => {
+            let a = decode::<TransferFromArgs>(args)?;
+            let spender = key_of(caller.0, a.spender_subaccount.as_deref());
+            let res = ledger
+                .transfer_from(
+                    spender,
+                    a.from.key(),
+                    a.to.key(),
+                    nat(&a.amount)?,
+                    opt_nat(&a.fee)?,
+                )
+                .map(Nat::from);
+            encode(&res)
+        }
+        _ => Err(unknown_method(method, update)),
+    }
+}
+
+fn handle_kong(
+    kong: &mut SimKong,
+    ledgers: &mut HashMap<String, SimLedger>,
+    caller: ledger::AccountKey,
+    method: &str,
+    args: &[u8],
+    update: bool,
+) -> Result<Vec<u8>, String> {
+    match method {
+        "pools" => {
+            let symbol = decode::<Option<String>>(args)?;
+            let res: Result<_, String> = Ok(kong.pools_reply(symbol.as_deref()));
+            encode(&res)
+        }
+        "requests" => {
+            let request_id = decode::<Option<u64>>(args)?;
+            let res: Result<_, String> = Ok(kong.requests_reply(request_id));
+            encode(&res)
+        }
+        "swap_async" if update => {
+            let a = decode::<KongSwapArgs>(args)?;
+            let ts = now_ms() as u64 * 1_000_000;
+            encode(&kong.swap_async(ledgers, caller, &a, ts))
+        }
+        _ => Err(unknown_method(method, update)),
+    }
+}
+
+fn handle_ics(
+    ics: &mut SimIcs,
+    ledgers: &mut HashMap<String, SimLedger>,
+    caller: ledger::AccountKey,
+    method: &str,
+    args: &[u8],
+    update: bool,
+) -> Result<Vec<u8>, String> {
+    match method {
+        "metadata" => encode(&IcsResult::Ok(ics.metadata())),
+        "quote" => {
+            let a = decode::<IcsSwapArgs>(args)?;
+            encode(&ics_result(ics.quote(&a).map(Nat::from)))
+        }
+        "depositFromAndSwap" if update => {
+            let a = decode::<DepositAndSwapArgs>(args)?;
+            let res = ics.deposit_from_and_swap(ledgers, caller, &a);
+            encode(&ics_result(res.map(Nat::from)))
+        }
+        _ => Err(unknown_method(method, update)),
+    }
+}
+
+fn unknown_method(method: &str, update: bool) -> String {
+    if update {
+        format!("method が存在しません: {}", method)
+    } else {
+        format!("method が存在しないか query として呼べません: {}", method)
+    }
+}
+
+fn decode<T: CandidType + for<'de> Deserialize<'de>>(args: &[u8]) -> Result<T, String> {
+    Decode!(args, T).map_err(|e| format!("引数のデコードに失敗: {}", e))
+}
+
+fn encode<T: CandidType>(value: &T) -> Result<Vec<u8>, String> {
+    Encode!(value).map_err(|e| format!("応答のエンコードに失敗: {}", e))
+}
+
+fn nat(n: &Nat) -> Result<u128, String> {
+    nat_u128(n).ok_or_else(|| format!("nat が大きすぎます: {}", n))
+}
+
+fn opt_nat(n: &Option<Nat>) -> Result<Option<u128>, String> {
+    n.as_ref().map(nat).transpose()
+}
+
+pub(crate) fn nat_u128(n: &Nat) -> Option<u128> {
+    n.0.to_string().parse::<u128>().ok()
+}
+
+fn reject(method: &str, code: RejectCode, message: String) -> IcClientError {
+    IcClientError::Reject {
+        method: method.to_string(),
+        code,
+        message,
+    }
+}
+
+fn parse_principal(canister: &str) -> Principal {
+    Principal::from_text(canister).unwrap_or_else(|_| Principal::anonymous())
+}
@@ -0,0 +1,81 @@
+// どこで: cargo test で動く設定の読み取りテスト
+// 何を: IDENTITY_PROFILES・PAIR_IDENTITIES の各項目の読み取りと、読めない項目のエラー、取引に使えない subaccount の拒否を確かめる
+// なぜ: subaccount を書き間違えた項目を黙って落とすと、意図しない口座や既定 identity で動いてしまうため
+
+use kong_ics::config::{parse_pair_identities, parse_profiles, parse_subaccount, IdentityConfig};
+
+#[test]
+fn profiles_are_parsed_with_subaccounts() {
+    let profiles =
+        parse_profiles(" alice=/keys/alice.pem , bob=/keys/bob.pem@1,carol=/keys/c@d.pem@0x0a,")
+            .unwrap();
+    let names: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["alice", "bob", "carol"]);
+    assert_eq!(profiles[0].pem_path, "/keys/alice.pem");
+    assert_eq!(profiles[0].subaccount, None);
+    assert_eq!(profiles[1].pem_path, "/keys/bob.pem");
+    assert_eq!(profiles[1].subaccount, parse_subaccount("1"));
+    // パスに @ があっても最後の @ 以降だけを subaccount とみなす
+    assert_eq!(profiles[2].pem_path, "/keys/c@d.pem");
+    let sub = profiles[2].subaccount.as_ref().unwrap();
+    assert_eq!(sub.len(), 32);
+    assert_eq!(sub[31], 0x0a);
+
+    assert!(parse_profiles("").unwrap().is_empty());
+}
+
+#[test]
+fn invalid_entries_are_named_in_the_error() {
+    let err = parse_profiles("alice=/keys/alice.pem,bob=/keys/bob.pem@xyz").unwrap_err();
+    assert!(err.contains("bob=/keys/bob.pem@xyz"), "{}", err);
+    assert!(err.contains("subaccount"), "{}", err);
+
+    let err = parse_profiles("alice=/keys/alice.pem@").unwrap_err();
+    assert!(err.contains("alice=/keys/alice.pem@"), "{}", err);
+
+    for entry in ["/keys/no-name.pem", "=/keys/empty-name.pem"] {
+        let err = parse_profiles(entry).unwrap_err();
+        assert!(err.contains(entry), "{}", err);
+    }
+}
+
+#[test]
+fn trading_profile_refuses_a_non_default_subaccount() {
+    let identity = IdentityConfig {
+        pem_path: "/keys/default.pem".to_string(),
+        passphrase_env_key: "IDENTITY_PASSPHRASE".to_string(),
+        passphrase_fd: None,
+        passphrase_prompt: false,
+        profiles: parse_profiles("default=/keys/default.pem,alice=/keys/a.pem@0,bob=/keys/b.pem@1")
+            .unwrap(),
+        invalid: None,
+    };
+    assert!(identity.trading_profile_named("BOB_ICP", "default").is_ok());
+    // 0 の subaccount は既定 subaccount と同じ口座
+    assert!(identity.trading_profile_named("BOB_ICP", "alice").is_ok());
+
+    let err = identity
+        .trading_profile_named("KONG_ICP", "bob")
+        .unwrap_err();
+    assert!(err.contains("KONG_ICP") && err.contains("bob"), "{}", err);
+    let err = identity.trading_profile_named("kong", "carol").unwrap_err();
+    assert!(err.contains("carol"), "{}", err);
+}
+
+#[test]
+fn pair_identities_reject_malformed_entries() {
+    let entries = parse_pair_identities(" BOB_ICP = alice ,KONG_ICP=bob,").unwrap();
+    assert_eq!(
+        entries,
+        [
+            ("BOB_ICP".to_string(), "alice".to_string()),
+            ("KONG_ICP".to_string(), "bob".to_string())
+        ]
+    );
+    assert!(parse_pair_identities("").unwrap().is_empty());
+
+    for entry in ["BOB_ICP", "BOB_ICP=", "=alice"] {
+        let err = parse_pair_identities(&format!("KONG_ICP=bob,{}", entry)).unwrap_err();
+        assert!(err.contains(entry), "{}", err);
+    }
+}
@@ -0,0 +1,92 @@
+// どこで: cargo test で動く EndpointPool の結合テスト
+// 何を: 速いエンドポイントを選び、失敗したものを後回し・除外し、除外が明けたら再び選ぶことを確かめる
+// なぜ: boundary node の障害時に、壊れたエンドポイントへ送り続けたり、復帰したものを使わなくなったりしないようにするため
+
+use std::time::Duration;
+
+use ic_agent::Agent;
+use kong_ics::ic_client::endpoint::{Endpoint, EndpointPool};
+
+const A: usize = 0;
+const B: usize = 1;
+const C: usize = 2;
+
+/// 接続はしないので URL はどこでもよい
+fn pool(cooldown_ms: i64) -> EndpointPool {
+    let endpoints = [
+        "http://127.0.0.1:1",
+        "http://127.0.0.1:2",
+        "http://127.0.0.1:3",
+    ]
+    .into_iter()
+    .map(|url| {
+        let agent = Agent::builder().with_url(url).build().expect("agent");
+        Endpoint::new(url.to_string(), agent)
+    })
+    .collect();
+    let pool = EndpointPool::new(endpoints).with_cooldown_ms(cooldown_ms);
+    for (idx, ms) in [(A, 10), (B, 50), (C, 100)] {
+        pool.record_ok(idx, Some(Duration::from_millis(ms)));
+    }
+    pool
+}
+
+fn healthy(pool: &EndpointPool, idx: usize) -> bool {
+    pool.health()[idx].healthy
+}
+
+#[test]
+fn picks_the_fastest_and_skips_a_failing_endpoint() {
+    let pool = pool(30_000);
+    assert_eq!(pool.pick(), A);
+
+    // 1 回失敗しただけでも、再試行は次に速いものへ向かう
+    pool.record_failure(A, "connection reset");
+    assert_eq!(pool.pick(), B);
+    assert!(healthy(&pool, A));
+
+    // 3 回続けて失敗したら除外する
+    pool.record_failure(A, "connection reset");
+    pool.record_failure(A, "connection reset");
+    assert!(!healthy(&pool, A));
+    assert_eq!(pool.health()[A].consecutive_failures, 3);
+    pool.record_failure(B, "timeout");
+    assert_eq!(pool.pick(), C);
+
+    // 成功すれば除外中でもすぐ戻す
+    pool.record_ok(A, Some(Duration::from_millis(10)));
+    assert!(healthy(&pool, A));
+    assert_eq!(pool.pick(), A);
+    assert_eq!(pool.health()[A].errors, 3);
+}
+
+#[tokio::test]
+async fn excluded_endpoint_comes_back_after_cooldown() {
+    let pool = pool(50);
+    for _ in 0..3 {
+        pool.record_failure(A, "503");
+    }
+    assert!(!healthy(&pool, A));
+    assert_eq!(pool.pick(), B);
+
+    tokio::time::sleep(Duration::from_millis(80)).await;
+    assert!(healthy(&pool, A));
+    // 明けた直後は直前に失敗したものとして後回し。他も失敗していればレイテンシで選ぶ
+    assert_eq!(pool.pick(), B);
+    pool.record_failure(B, "503");
+    pool.record_failure(C, "503");
+    assert_eq!(pool.pick(), A);
+}
+
+#[test]
+fn all_excluded_picks_the_first_to_recover() {
+    let pool = pool(30_000);
+    for idx in [B, A, C] {
+        for _ in 0..3 {
+            pool.record_failure(idx, "503");
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!((0..3).all(|idx| !healthy(&pool, idx)));
+    assert_eq!(pool.pick(), B);
+}
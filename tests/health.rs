@@ -0,0 +1,89 @@
+// どこで: cargo test で動く HealthState のテスト
+// 何を: identity ごとの IcClient が揃って IC に届くまで /readyz が準備完了にならないことを確かめる
+// なぜ: 先頭の identity だけを見ていると、別 identity の接続が死んでいても ready を返してしまうため
+
+use std::sync::Arc;
+
+use candid::Encode;
+use kong_ics::config::RetryPolicy;
+use kong_ics::health::HealthState;
+use kong_ics::ic_client::agent::{IcClient, IcClientError};
+use kong_ics::ic_client::mock::MockTransport;
+
+const CANISTER: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+const NO_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 1,
+    base_delay_ms: 1,
+    max_delay_ms: 1,
+};
+
+fn client() -> (Arc<MockTransport>, IcClient) {
+    let mock = Arc::new(MockTransport::new());
+    mock.reply(CANISTER, "ping", Encode!(&()).unwrap());
+    let client = IcClient::from_transport(mock.clone());
+    (mock, client)
+}
+
+#[tokio::test]
+async fn ready_only_when_every_identity_reaches_the_ic() {
+    let (_, alice) = client();
+    let (_, bob) = client();
+    let health = HealthState::new(
+        "alice=..., bob=...".to_string(),
+        vec![
+            ("alice".to_string(), alice.clone()),
+            ("bob".to_string(), bob.clone()),
+        ],
+        60,
+    );
+    health.register("BOB_ICP");
+    health.ok("BOB_ICP");
+    assert!(health.is_live());
+
+    alice
+        .query_raw(CANISTER, "ping", Encode!(&()).unwrap())
+        .await
+        .unwrap();
+    assert!(!health.is_ready(), "bob が一度も IC に届いていない");
+
+    bob.query_raw(CANISTER, "ping", Encode!(&()).unwrap())
+        .await
+        .unwrap();
+    assert!(health.is_ready());
+}
+
+#[tokio::test]
+async fn unreachable_identity_keeps_it_unready() {
+    let (_, alice) = client();
+    let (bob_mock, bob) = client();
+    // bob の接続は通信エラーしか返さない（canister の reject と違い IC に届いていない）
+    for _ in 0..5 {
+        bob_mock.push_reply(
+            CANISTER,
+            "ping",
+            Err(IcClientError::Transport {
+                method: "ping".to_string(),
+                status: None,
+                message: "connection refused".to_string(),
+            }),
+        );
+    }
+    let bob = bob.with_retry(NO_RETRY, NO_RETRY);
+    let health = HealthState::new(
+        String::new(),
+        vec![
+            ("alice".to_string(), alice.clone()),
+            ("bob".to_string(), bob.clone()),
+        ],
+        60,
+    );
+    alice
+        .query_raw(CANISTER, "ping", Encode!(&()).unwrap())
+        .await
+        .unwrap();
+    assert!(bob
+        .query_raw(CANISTER, "ping", Encode!(&()).unwrap())
+        .await
+        .is_err());
+    assert!(!health.is_ready());
+}
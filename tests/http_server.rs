@@ -0,0 +1,108 @@
+// どこで: cargo test で動く運用向け HTTP サーバのテスト
+// 何を: ループバックで serve を立て、通常の応答・読み込みタイムアウト・大きすぎるヘッダの扱いと /control のトークン認証を確かめる
+// なぜ: 送ってこない接続や巨大なヘッダで接続タスクが溜まり続けないことを保証するため
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use kong_ics::control::TradingControl;
+use kong_ics::http::{serve, HttpResponse, Router};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+
+/// 空いているポートで serve を立て、接続できるようになるまで待つ
+async fn start(router: Router) -> String {
+    let addr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let serve_addr = addr.clone();
+    tokio::spawn(async move { serve(&serve_addr, router).await });
+    for _ in 0..100 {
+        if TcpStream::connect(&addr).await.is_ok() {
+            return addr;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("HTTP サーバが起動しません: {}", addr);
+}
+
+fn router() -> Router {
+    Router::new()
+        .route("GET", "/ping", |_| HttpResponse::text(200, "pong\n"))
+        .with_read_timeout(Duration::from_millis(200))
+}
+
+async fn send(addr: &str, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn routes_and_unknown_paths() {
+    let addr = start(router()).await;
+    let ok = send(&addr, b"GET /ping?x=1 HTTP/1.1\r\nHost: test\r\n\r\n").await;
+    assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"), "{}", ok);
+    assert!(ok.ends_with("\r\n\r\npong\n"), "{}", ok);
+
+    let missing = send(&addr, b"GET /nope HTTP/1.1\r\n\r\n").await;
+    assert!(missing.starts_with("HTTP/1.1 404 "), "{}", missing);
+}
+
+#[tokio::test]
+async fn silent_client_times_out() {
+    let addr = start(router()).await;
+    let started = Instant::now();
+    // 途中までしか送らない
+    let response = send(&addr, b"GET /ping HTTP/1.1\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn oversized_header_is_refused() {
+    let addr = start(router()).await;
+    // 上限 (8 KiB) ちょうどまで送っても空行が来ない。読み残しがあると RST で応答が消えるので超えては送らない
+    let mut request = b"GET /ping HTTP/1.1\r\nX-Padding: ".to_vec();
+    request.resize(8 * 1024, b'a');
+    let response = send(&addr, &request).await;
+    assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+}
+
+#[tokio::test]
+async fn control_requires_the_bearer_token() {
+    let control =
+        Arc::new(TradingControl::new(None, vec!["BOB_ICP".to_string()]).with_auth_token("s3cret"));
+    let addr = start(control.routes(Router::new())).await;
+
+    for request in [
+        "GET /control HTTP/1.1\r\n\r\n",
+        "POST /control/pause HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n",
+        "POST /control/pause HTTP/1.1\r\nAuthorization: s3cret\r\n\r\n",
+    ] {
+        let response = send(&addr, request.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 401 "), "{}", response);
+        assert!(!response.contains("paused"), "{}", response);
+    }
+    assert!(control.paused("BOB_ICP").is_none());
+
+    let response = send(
+        &addr,
+        b"POST /control/pause?pair=BOB_ICP HTTP/1.1\r\nauthorization: Bearer s3cret\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+    assert!(control.paused("BOB_ICP").is_some());
+}
+
+#[tokio::test]
+async fn control_without_a_token_stays_open() {
+    let control = Arc::new(TradingControl::new(None, vec!["BOB_ICP".to_string()]));
+    let addr = start(control.routes(Router::new())).await;
+    let response = send(&addr, b"GET /control HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+}
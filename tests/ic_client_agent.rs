@@ -0,0 +1,280 @@
+// どこで: cargo test で動く IcClient の結合テスト
+// 何を: ic-agent のエラーの分類、query は再試行し update の送信は投げ直さないこと、応答しない相手を時間内に打ち切ること、締め切りを過ぎた update の結果確認を確かめる
+// なぜ: 分類を誤ると、一時的な障害で取引を止めたり、届いていたかもしれない swap を二重に送ったりするため
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use candid::{Encode, Principal};
+use ic_agent::agent::agent_error::HttpErrorPayload;
+use ic_agent::agent::{RejectCode, RejectResponse};
+use ic_agent::identity::AnonymousIdentity;
+use ic_agent::{AgentError, RequestId};
+use kong_ics::config::{CallTimeouts, RetryPolicy, ICP_LEDGER_RAW};
+use kong_ics::ic_client::agent::{IcClient, IcClientError, SubmittedUpdate};
+use kong_ics::ic_client::mock::MockTransport;
+use kong_ics::ic_client::transport::{BoxFuture, CanisterTransport};
+use kong_ics::journal::now_ms;
+
+/// 結果が出るまで delay かかる update（締め切りより遅く、最低確認時間より早い）
+struct SlowUpdates {
+    mock: Arc<MockTransport>,
+    delay: Duration,
+}
+
+impl CanisterTransport for SlowUpdates {
+    fn query<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        self.mock.query(canister, method, args, call_timeout)
+    }
+
+    fn submit<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        args: Vec<u8>,
+        ingress_expiry: Duration,
+        call_timeout: Duration,
+    ) -> BoxFuture<'a, Result<RequestId, IcClientError>> {
+        self.mock
+            .submit(canister, method, args, ingress_expiry, call_timeout)
+    }
+
+    fn wait<'a>(
+        &'a self,
+        canister: &'a str,
+        method: &'a str,
+        request_id: RequestId,
+    ) -> BoxFuture<'a, Result<Vec<u8>, IcClientError>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.delay).await;
+            self.mock.wait(canister, method, request_id).await
+        })
+    }
+
+    fn principal(&self) -> Result<Principal, String> {
+        self.mock.principal()
+    }
+}
+
+fn reject(code: RejectCode) -> AgentError {
+    AgentError::ReplicaError(RejectResponse {
+        reject_code: code,
+        reject_message: "rejected".to_string(),
+        error_code: None,
+    })
+}
+
+fn http(status: u16) -> AgentError {
+    AgentError::HttpError(HttpErrorPayload {
+        status,
+        content_type: None,
+        content: Vec::new(),
+    })
+}
+
+fn classify(err: AgentError) -> (&'static str, bool, bool) {
+    let e = IcClientError::from_agent("pools", err);
+    (e.class(), e.is_transient(), e.is_endpoint_failure())
+}
+
+/// どのリクエストにも 503 を返すエンドポイント。受けたリクエストのパスを返す
+fn unavailable_endpoint() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let seen = paths.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap_or(0);
+            let head = String::from_utf8_lossy(&buf[..n]);
+            let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+            seen.lock().unwrap().push(path);
+            let _ = stream.write_all(
+                b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+    });
+    (url, paths)
+}
+
+/// 接続は受けるが何も返さないエンドポイント
+fn silent_endpoint() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let mut held = Vec::new();
+        for stream in listener.incoming().flatten() {
+            held.push(stream);
+        }
+    });
+    url
+}
+
+/// endpoint (query / call / read_state) ごとの受信数
+fn count(paths: &Mutex<Vec<String>>, endpoint: &str) -> usize {
+    paths
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|p| p.ends_with(endpoint))
+        .count()
+}
+
+async fn client(url: &str) -> IcClient {
+    let fast = RetryPolicy {
+        max_attempts: 3,
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+    };
+    IcClient::new(&[url.to_string()], Arc::new(AnonymousIdentity), false, true)
+        .await
+        .unwrap()
+        .with_retry(fast, fast)
+}
+
+#[test]
+fn agent_errors_are_classified() {
+    // (分類, 再試行する, エンドポイントを切り替える)
+    assert_eq!(
+        classify(reject(RejectCode::SysTransient)),
+        ("reject", true, false)
+    );
+    assert_eq!(
+        classify(reject(RejectCode::CanisterError)),
+        ("reject", false, false)
+    );
+    assert_eq!(classify(http(503)), ("transport", true, true));
+    assert_eq!(classify(http(429)), ("transport", true, true));
+    assert_eq!(classify(http(400)), ("transport", false, true));
+    assert_eq!(
+        classify(AgentError::TransportError("connection reset".into())),
+        ("transport", true, true)
+    );
+    assert_eq!(
+        classify(AgentError::TimeoutWaitingForResponse()),
+        ("timeout", true, true)
+    );
+    assert_eq!(
+        classify(AgentError::CertificateVerificationFailed()),
+        ("certificate", false, true)
+    );
+    assert_eq!(
+        classify(AgentError::QuerySignatureVerificationFailed),
+        ("certificate", false, true)
+    );
+    assert_eq!(
+        classify(AgentError::ResponseSizeExceededLimit()),
+        ("decode", false, false)
+    );
+    assert_eq!(
+        classify(AgentError::MessageError("boom".to_string())),
+        ("other", false, false)
+    );
+
+    let e = IcClientError::from_agent("pools", http(503));
+    assert!(matches!(
+        e,
+        IcClientError::Transport {
+            status: Some(503),
+            ..
+        }
+    ));
+    assert!(
+        IcClientError::from_agent("metadata", AgentError::MissingSignature)
+            .is_verification_failure()
+    );
+}
+
+#[tokio::test]
+async fn transient_query_is_retried() {
+    let (url, paths) = unavailable_endpoint();
+    let err = client(&url)
+        .await
+        .query_raw(ICP_LEDGER_RAW, "icrc1_fee", Encode!().unwrap())
+        .await
+        .unwrap_err();
+    assert!(err.is_transient(), "{}", err);
+    assert_eq!(count(&paths, "/query"), 3);
+}
+
+#[tokio::test]
+async fn update_submit_is_not_resent() {
+    let (url, paths) = unavailable_endpoint();
+    // 一時的な通信エラーでも、届いていれば二重実行になるので投げ直さない
+    let err = client(&url)
+        .await
+        .update_raw(ICP_LEDGER_RAW, "icrc2_approve", Encode!().unwrap())
+        .await
+        .unwrap_err();
+    assert!(err.is_transient(), "{}", err);
+    assert_eq!(count(&paths, "/call"), 1);
+}
+
+#[tokio::test]
+async fn silent_endpoint_is_cut_off_by_the_timeouts() {
+    let client = client(&silent_endpoint())
+        .await
+        .with_timeouts(CallTimeouts {
+            query_timeout_ms: 100,
+            update_timeout_ms: 100,
+            ..CallTimeouts::default()
+        });
+    let started = Instant::now();
+    let err = client
+        .query_raw(ICP_LEDGER_RAW, "icrc1_fee", Encode!().unwrap())
+        .await
+        .unwrap_err();
+    // タイムアウトは一時的な失敗なので query は 3 回まで試してから返す
+    assert!(matches!(err, IcClientError::Timeout(_)), "{}", err);
+    let err = client
+        .update_raw(ICP_LEDGER_RAW, "icrc2_approve", Encode!().unwrap())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, IcClientError::Timeout(_)), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn poll_outlives_a_deadline_shorter_than_the_minimum() {
+    let mock = Arc::new(MockTransport::new());
+    mock.reply(ICP_LEDGER_RAW, "icrc1_fee", Encode!(&10_000u64).unwrap());
+    let client = IcClient::from_transport(Arc::new(SlowUpdates {
+        mock: mock.clone(),
+        delay: Duration::from_millis(200),
+    }))
+    .with_timeouts(CallTimeouts {
+        update_timeout_ms: 20,
+        ..CallTimeouts::default()
+    });
+
+    // 締め切り (20ms) より結果が遅くても、最低確認時間の間は待って結果を返す
+    let submitted = client
+        .submit_update(ICP_LEDGER_RAW, "icrc1_fee", Encode!().unwrap())
+        .await
+        .unwrap();
+    assert!(client.poll_update(&submitted).await.is_ok());
+
+    // 再起動後に復元した、締め切りをとうに過ぎた update も結果を確認する
+    let submitted = client
+        .submit_update(ICP_LEDGER_RAW, "icrc1_fee", Encode!().unwrap())
+        .await
+        .unwrap();
+    let restored = SubmittedUpdate::restore(
+        &submitted.canister,
+        &submitted.method,
+        &submitted.request_id_hex(),
+        now_ms() - 3_600_000,
+    )
+    .unwrap();
+    assert!(client.poll_update(&restored).await.is_ok());
+}
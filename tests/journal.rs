@@ -0,0 +1,391 @@
+// どこで: cargo test で動く取引ジャーナル (SQLite) のテスト
+// 何を: スキーマの作成と古い DB の移行、機会・swap・送信済みレッグ・損益の保存と読み出し、損益集計、TradeFilter の絞り込み、blocking スレッドでの実行を確かめる
+// なぜ: ジャーナルは再起動後の照合と損益集計の元なので、書いた内容がそのまま読み戻せることを保証するため
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use kong_ics::journal::{
+    Journal, JournalError, OpportunityRecord, PnlRecord, RequestStatus, SwapRecord,
+    SwapRequestRecord, TradeFilter,
+};
+
+fn temp_db(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "kong_ics_journal_{}_{}.db",
+        name,
+        std::process::id()
+    ));
+    remove_db(&path);
+    path
+}
+
+fn remove_db(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+/// YYYY-MM-DD (UTC) の 0 時 + offset_ms
+fn at(day: &str, offset_ms: i64) -> i64 {
+    TradeFilter::from_dates(None, Some(day), None)
+        .unwrap()
+        .from_ms
+        .unwrap()
+        + offset_ms
+}
+
+fn swap(pair: &str, started_ms: i64, kong_error: Option<&str>) -> SwapRecord {
+    SwapRecord {
+        id: 0,
+        opportunity_id: None,
+        pair: pair.to_string(),
+        direction: "kong_to_ics".to_string(),
+        amount_in_e8: 100_000_000,
+        expected_mid_e8: 199_000_000.0,
+        expected_final_e8: 101_000_000.0,
+        min_mid_e8: 197_000_000,
+        min_final_e8: 100_000_000,
+        kong_reply: kong_error.is_none().then(|| "Ok(7)".to_string()),
+        kong_error: kong_error.map(str::to_string),
+        ics_reply: Some("ok(101000000)".to_string()),
+        ics_error: None,
+        started_ms,
+        finished_ms: started_ms + 1_500,
+    }
+}
+
+fn pnl(pair: &str, ts_ms: i64, realized: f64, fees: f64) -> PnlRecord {
+    PnlRecord {
+        swap_id: None,
+        ts_ms,
+        pair: pair.to_string(),
+        direction: "kong_to_ics".to_string(),
+        icp_delta_e8: realized as i64,
+        sns_delta_e8: 0,
+        sns_price_icp: 0.5,
+        realized_pnl_e8: realized,
+        expected_profit_e8: 1_000_000.0,
+        fees_e8: fees,
+        slippage_e8: 1_000_000.0 - realized,
+        kong_fill_e8: Some(199_000_000),
+        ics_fill_e8: Some(101_000_000),
+    }
+}
+
+fn opportunity(pair: &str, ts_ms: i64, executed: bool) -> OpportunityRecord {
+    OpportunityRecord {
+        ts_ms,
+        pair: pair.to_string(),
+        direction: "kong_to_ics".to_string(),
+        amount_in_e8: 100_000_000.0,
+        expected_mid_e8: 199_000_000.0,
+        expected_final_e8: 101_000_000.0,
+        expected_profit_e8: 1_000_000.0,
+        executed,
+    }
+}
+
+#[test]
+fn schema_is_created_and_reopened() {
+    let path = temp_db("schema");
+    let journal = Journal::open(&path).expect("新規作成");
+    journal
+        .record_pnl(&pnl("BOB_ICP", at("2026-01-02", 0), 1.0, 1.0))
+        .unwrap();
+    drop(journal);
+
+    // 2 回目の open でもスキーマ作成・移行は何もせず、既存の行も残る
+    let journal = Journal::open(&path).expect("再オープン");
+    let rows = journal.pnl_summary(&TradeFilter::default()).unwrap();
+    assert_eq!(rows.len(), 1);
+    drop(journal);
+    remove_db(&path);
+}
+
+#[test]
+fn old_pnl_table_gets_kong_fill_column() {
+    let path = temp_db("migrate");
+    {
+        // kong_fill_e8 が無かった頃の pnl テーブル
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pnl (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                swap_id INTEGER,
+                ts_ms INTEGER NOT NULL,
+                pair TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                icp_delta_e8 INTEGER NOT NULL,
+                sns_delta_e8 INTEGER NOT NULL,
+                sns_price_icp REAL NOT NULL,
+                realized_pnl_e8 REAL NOT NULL,
+                expected_profit_e8 REAL NOT NULL,
+                fees_e8 REAL NOT NULL,
+                slippage_e8 REAL NOT NULL,
+                ics_fill_e8 INTEGER
+            );
+            INSERT INTO pnl (ts_ms, pair, direction, icp_delta_e8, sns_delta_e8, sns_price_icp,
+                             realized_pnl_e8, expected_profit_e8, fees_e8, slippage_e8)
+            VALUES (0, 'BOB_ICP', 'kong_to_ics', 5, 0, 0.5, 5.0, 10.0, 1.0, 5.0);",
+        )
+        .unwrap();
+    }
+
+    let journal = Journal::open(&path).expect("移行");
+    journal
+        .record_pnl(&pnl("BOB_ICP", at("2026-01-02", 0), 1.0, 1.0))
+        .unwrap();
+    let rows = journal.pnl_summary(&TradeFilter::default()).unwrap();
+    assert_eq!(rows.iter().map(|r| r.trades).sum::<u64>(), 2);
+    drop(journal);
+
+    // 移行済みの DB をもう一度開いても列の追加は走らない
+    Journal::open(&path).expect("移行後の再オープン");
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let kong_fill: Option<i64> = conn
+        .query_row(
+            "SELECT kong_fill_e8 FROM pnl WHERE realized_pnl_e8 = 1.0",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(kong_fill, Some(199_000_000));
+    drop(conn);
+    remove_db(&path);
+}
+
+#[test]
+fn swap_round_trips() {
+    let path = temp_db("swap");
+    let journal = Journal::open(&path).unwrap();
+    let opportunity_id = journal
+        .record_opportunity(&opportunity("BOB_ICP", at("2026-01-02", 0), true))
+        .unwrap();
+    let mut ok = swap("BOB_ICP", at("2026-01-02", 1_000), None);
+    ok.opportunity_id = Some(opportunity_id);
+    let ok_id = journal.record_swap(&ok).unwrap();
+    journal
+        .record_swap(&swap("BOB_ICP", at("2026-01-02", 2_000), Some("slippage")))
+        .unwrap();
+
+    let swaps = journal.swaps(&TradeFilter::default()).unwrap();
+    assert_eq!(swaps.len(), 2);
+    let read = &swaps[0];
+    assert_eq!(read.id, ok_id);
+    assert_eq!(read.opportunity_id, Some(opportunity_id));
+    assert_eq!(read.pair, ok.pair);
+    assert_eq!(read.direction, ok.direction);
+    assert_eq!(read.amount_in_e8, ok.amount_in_e8);
+    assert_eq!(read.expected_mid_e8, ok.expected_mid_e8);
+    assert_eq!(read.expected_final_e8, ok.expected_final_e8);
+    assert_eq!(read.min_mid_e8, ok.min_mid_e8);
+    assert_eq!(read.min_final_e8, ok.min_final_e8);
+    assert_eq!(read.kong_reply, ok.kong_reply);
+    assert_eq!(read.ics_reply, ok.ics_reply);
+    assert_eq!(read.started_ms, ok.started_ms);
+    assert_eq!(read.finished_ms, ok.finished_ms);
+    assert!(read.succeeded());
+    assert!(!swaps[1].succeeded());
+    assert_eq!(swaps[1].kong_error.as_deref(), Some("slippage"));
+    drop(journal);
+    remove_db(&path);
+}
+
+#[test]
+fn trade_filter_narrows_by_pair_and_dates() {
+    let path = temp_db("filter");
+    let journal = Journal::open(&path).unwrap();
+    for (pair, ts) in [
+        ("BOB_ICP", at("2026-01-01", 86_400_000 - 1)),
+        ("BOB_ICP", at("2026-01-02", 0)),
+        ("ALT_ICP", at("2026-01-02", 43_200_000)),
+        ("BOB_ICP", at("2026-01-03", 86_400_000 - 1)),
+        ("BOB_ICP", at("2026-01-04", 0)),
+    ] {
+        journal.record_swap(&swap(pair, ts, None)).unwrap();
+        journal
+            .record_opportunity(&opportunity(pair, ts, pair == "BOB_ICP"))
+            .unwrap();
+    }
+    journal
+        .record_opportunity(&opportunity("BOB_ICP", at("2026-01-02", 1), false))
+        .unwrap();
+
+    // to の日は終わりまで含み、前後の日は含まない
+    let range = TradeFilter::from_dates(None, Some("2026-01-02"), Some("2026-01-03")).unwrap();
+    assert_eq!(journal.swaps(&range).unwrap().len(), 3);
+    assert_eq!(journal.opportunity_counts(&range).unwrap(), (4, 2));
+
+    let bob = TradeFilter::from_dates(
+        Some("BOB_ICP".to_string()),
+        Some("2026-01-02"),
+        Some("2026-01-03"),
+    )
+    .unwrap();
+    let swaps = journal.swaps(&bob).unwrap();
+    assert_eq!(swaps.len(), 2);
+    assert!(swaps.iter().all(|s| s.pair == "BOB_ICP"));
+    assert_eq!(journal.opportunity_counts(&bob).unwrap(), (3, 2));
+
+    let open_ended = TradeFilter::from_dates(None, Some("2026-01-03"), None).unwrap();
+    assert_eq!(journal.swaps(&open_ended).unwrap().len(), 2);
+    let empty = TradeFilter::from_dates(None, Some("2030-01-01"), None).unwrap();
+    assert_eq!(journal.opportunity_counts(&empty).unwrap(), (0, 0));
+
+    assert!(matches!(
+        TradeFilter::from_dates(None, Some("2026/01/02"), None),
+        Err(JournalError::Date(_))
+    ));
+    assert!(matches!(
+        TradeFilter::from_dates(None, None, Some("2026-13-01")),
+        Err(JournalError::Date(_))
+    ));
+    drop(journal);
+    remove_db(&path);
+}
+
+#[test]
+fn requests_are_linked_and_resolved() {
+    let path = temp_db("requests");
+    let journal = Journal::open(&path).unwrap();
+    let request = |leg: &str| SwapRequestRecord {
+        id: 0,
+        swap_id: None,
+        pair: "BOB_ICP".to_string(),
+        leg: leg.to_string(),
+        canister: "canister".to_string(),
+        method: "swap".to_string(),
+        request_id: format!("{}-request", leg),
+        submitted_ms: at("2026-01-02", 0),
+        status: RequestStatus::Pending.as_str().to_string(),
+        reply: None,
+        error: None,
+        finished_ms: None,
+    };
+    let kong = journal.record_request(&request("kong")).unwrap();
+    let ics = journal.record_request(&request("ics")).unwrap();
+    let swap_id = journal
+        .record_swap(&swap("BOB_ICP", at("2026-01-02", 0), None))
+        .unwrap();
+    journal.link_requests(swap_id, &[kong, ics]).unwrap();
+    journal
+        .finish_request(kong, RequestStatus::Replied, Some("Ok(7)"), None)
+        .unwrap();
+    journal
+        .finish_request(ics, RequestStatus::Unknown, None, Some("timeout"))
+        .unwrap();
+
+    // 結果の確定した kong は対象外、unknown の ics だけが残る
+    let unresolved = journal.unresolved_requests("BOB_ICP", 0).unwrap();
+    assert_eq!(unresolved.len(), 1);
+    let row = &unresolved[0];
+    assert_eq!(row.id, ics);
+    assert_eq!(row.swap_id, Some(swap_id));
+    assert_eq!(row.request_id, "ics-request");
+    assert_eq!(row.status, "unknown");
+    assert_eq!(row.error.as_deref(), Some("timeout"));
+    assert!(row.finished_ms.is_some());
+    assert!(journal
+        .unresolved_requests("BOB_ICP", at("2026-01-03", 0))
+        .unwrap()
+        .is_empty());
+    assert!(journal
+        .unresolved_requests("ALT_ICP", 0)
+        .unwrap()
+        .is_empty());
+    drop(journal);
+    remove_db(&path);
+}
+
+#[test]
+fn pnl_summary_groups_by_pair_and_day() {
+    let path = temp_db("pnl");
+    let journal = Journal::open(&path).unwrap();
+    journal
+        .record_pnl(&pnl(
+            "BOB_ICP",
+            at("2026-01-02", 1_000),
+            600_000.0,
+            20_000.0,
+        ))
+        .unwrap();
+    journal
+        .record_pnl(&pnl(
+            "BOB_ICP",
+            at("2026-01-02", 2_000),
+            400_000.0,
+            30_000.0,
+        ))
+        .unwrap();
+    journal
+        .record_pnl(&pnl(
+            "ALT_ICP",
+            at("2026-01-02", 3_000),
+            -100_000.0,
+            10_000.0,
+        ))
+        .unwrap();
+    journal
+        .record_pnl(&pnl("BOB_ICP", at("2026-01-03", 0), 200_000.0, 20_000.0))
+        .unwrap();
+
+    let rows = journal.pnl_summary(&TradeFilter::default()).unwrap();
+    let keys: Vec<(&str, &str, u64)> = rows
+        .iter()
+        .map(|r| (r.day.as_str(), r.pair.as_str(), r.trades))
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            ("2026-01-02", "ALT_ICP", 1),
+            ("2026-01-02", "BOB_ICP", 2),
+            ("2026-01-03", "BOB_ICP", 1),
+        ]
+    );
+    let bob = &rows[1];
+    assert_eq!(bob.realized_pnl_e8, 1_000_000.0);
+    assert_eq!(bob.expected_profit_e8, 2_000_000.0);
+    assert_eq!(bob.fees_e8, 50_000.0);
+    assert_eq!(bob.slippage_e8, 1_000_000.0);
+
+    // 日次サマリと同じ [0 時, 翌 0 時) の範囲
+    let day = TradeFilter::between(at("2026-01-02", 0), at("2026-01-03", 0));
+    let rows = journal.pnl_summary(&day).unwrap();
+    assert_eq!(rows.iter().map(|r| r.trades).sum::<u64>(), 3);
+    drop(journal);
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn run_executes_on_the_blocking_pool() {
+    let path = temp_db("run");
+    let journal = Arc::new(Journal::open(&path).unwrap());
+    let record = swap("BOB_ICP", at("2026-01-02", 0), None);
+    let id = journal
+        .run(move |j| j.record_swap(&record))
+        .await
+        .expect("記録");
+    let swaps = journal
+        .run(|j| j.swaps(&TradeFilter::default()))
+        .await
+        .unwrap();
+    assert_eq!(swaps.len(), 1);
+    assert_eq!(swaps[0].id, id);
+
+    // 処理中の panic は JoinError として返り、ジャーナルはその後も使える
+    let panicked = journal
+        .run(|_| -> Result<(), JournalError> { panic!("テスト用の panic") })
+        .await;
+    assert!(matches!(panicked, Err(JournalError::Task(_))));
+    assert_eq!(
+        journal
+            .run(|j| j.swaps(&TradeFilter::default()))
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    drop(journal);
+    remove_db(&path);
+}
@@ -0,0 +1,242 @@
+// どこで: cargo test で動く MockTransport の結合テスト
+// 何を: 用意した candid 応答だけで Trade::tick を swap まで通し、push_reply で積んだ失敗の再試行を確かめる
+// なぜ: シミュレータを使わずに、呼び出しの順番・種類・引数をそのまま検査できることを保証するため
+
+use std::sync::Arc;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Int, Nat};
+use kong_ics::arb::{Refresh, Trade};
+use kong_ics::config::{
+    PairConfig, RetryPolicy, DEFAULT_IDENTITY, ICP_LEDGER_IC, ICP_LEDGER_RAW, KONG_CANISTER,
+};
+use kong_ics::ic_client::agent::{IcClient, IcClientError};
+use kong_ics::ic_client::kong::fetch_pool_snapshot as fetch_kong;
+use kong_ics::ic_client::mock::MockTransport;
+
+const BOB: &str = "7pail-xaaaa-aaaas-aabmq-cai";
+const BOB_ICS_LP: &str = "ybilh-nqaaa-aaaag-qkhzq-cai";
+const E8: u128 = 100_000_000;
+
+/// Kong の pools 応答 1 件（canister の .did と同じフィールド名）
+#[derive(CandidType)]
+struct PoolReply {
+    symbol: String,
+    symbol_0: String,
+    address_0: String,
+    balance_0: Nat,
+    lp_fee_0: Nat,
+    symbol_1: String,
+    address_1: String,
+    balance_1: Nat,
+    lp_fee_1: Nat,
+    price: f64,
+    lp_fee_bps: u8,
+}
+
+#[derive(CandidType)]
+struct Token {
+    address: String,
+    standard: String,
+}
+
+/// ICPSwap の metadata 応答（canister の .did と同じフィールド名）
+#[derive(CandidType)]
+#[allow(non_snake_case)]
+struct PoolMetadata {
+    fee: Nat,
+    key: String,
+    liquidity: Nat,
+    maxLiquidityPerTick: Nat,
+    nextPositionId: Nat,
+    sqrtPriceX96: Nat,
+    tick: Int,
+    token0: Token,
+    token1: Token,
+}
+
+#[derive(CandidType, Deserialize)]
+enum IcsResult<T> {
+    #[serde(rename = "ok")]
+    Ok(T),
+}
+
+/// Kong の swap_async の引数のうち確かめる部分
+#[derive(CandidType, Deserialize)]
+struct KongSwapArgs {
+    pay_token: String,
+    pay_amount: Nat,
+    receive_token: String,
+}
+
+/// Kong は 0.5 ICP/BOB、ICPSwap は 0.55 ICP/BOB
+fn mock() -> Arc<MockTransport> {
+    let mock = Arc::new(MockTransport::new());
+    mock.reply(
+        KONG_CANISTER,
+        "pools",
+        pools_reply(200_000 * E8, 100_000 * E8),
+    );
+    mock.reply(
+        BOB_ICS_LP,
+        "metadata",
+        metadata_reply(200_000 * E8, 110_000 * E8),
+    );
+    mock.reply(
+        KONG_CANISTER,
+        "swap_async",
+        Encode!(&Ok::<u64, String>(7)).unwrap(),
+    );
+    mock.reply(
+        BOB_ICS_LP,
+        "depositFromAndSwap",
+        Encode!(&IcsResult::<Nat>::Ok(Nat::from(101 * E8))).unwrap(),
+    );
+    mock
+}
+
+fn pools_reply(bob: u128, icp: u128) -> Vec<u8> {
+    let pools: Result<Vec<PoolReply>, String> = Ok(vec![PoolReply {
+        symbol: "BOB_ICP".to_string(),
+        symbol_0: "BOB".to_string(),
+        address_0: BOB.to_string(),
+        balance_0: Nat::from(bob),
+        lp_fee_0: Nat::from(0u32),
+        symbol_1: "ICP".to_string(),
+        address_1: ICP_LEDGER_RAW.to_string(),
+        balance_1: Nat::from(icp),
+        lp_fee_1: Nat::from(0u32),
+        price: icp as f64 / bob as f64,
+        lp_fee_bps: 30,
+    }]);
+    Encode!(&pools).unwrap()
+}
+
+/// 全レンジ流動性の (reserve0, reserve1) から sqrtPriceX96 と liquidity を作る
+fn metadata_reply(reserve0: u128, reserve1: u128) -> Vec<u8> {
+    let (r0, r1) = (reserve0 as f64, reserve1 as f64);
+    let meta = PoolMetadata {
+        fee: Nat::from(3_000u32),
+        key: format!("{}_{}_3000", BOB, ICP_LEDGER_RAW),
+        liquidity: Nat::from((r0 * r1).sqrt() as u128),
+        maxLiquidityPerTick: Nat::from(u128::MAX),
+        nextPositionId: Nat::from(1u32),
+        sqrtPriceX96: Nat::from(((r1 / r0).sqrt() * 2f64.powi(96)) as u128),
+        tick: Int::from(0),
+        token0: Token {
+            address: BOB.to_string(),
+            standard: "ICRC2".to_string(),
+        },
+        token1: Token {
+            address: ICP_LEDGER_RAW.to_string(),
+            standard: "ICRC2".to_string(),
+        },
+    };
+    Encode!(&IcsResult::Ok(meta)).unwrap()
+}
+
+fn client(mock: &Arc<MockTransport>) -> Arc<IcClient> {
+    let fast = RetryPolicy {
+        max_attempts: 3,
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+    };
+    Arc::new(IcClient::from_transport(mock.clone()).with_retry(fast, fast))
+}
+
+fn trade(client: Arc<IcClient>) -> Trade {
+    let pair = PairConfig {
+        token_icp: ICP_LEDGER_IC.to_string(),
+        token_sns: BOB.to_string(),
+        kong_canister: KONG_CANISTER.to_string(),
+        icpswap_lp: BOB_ICS_LP.to_string(),
+        symbol: "BOB_ICP".to_string(),
+        ikiti_e8: 100 * E8,
+        sns_fee_e8: 10_000,
+        identity: DEFAULT_IDENTITY.to_string(),
+    };
+    Trade::new(pair, client, None, 0.003, 0.99, 0f64, 1_000)
+}
+
+fn transient(method: &str) -> IcClientError {
+    IcClientError::Transport {
+        method: method.to_string(),
+        status: Some(503),
+        message: "service unavailable".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn tick_runs_through_to_swap() {
+    let mock = mock();
+    trade(client(&mock)).tick().await.expect("tick");
+
+    let calls: Vec<(String, &str)> = mock
+        .calls()
+        .iter()
+        .map(|c| (c.method.clone(), c.kind))
+        .collect();
+    let position = |method: &str| calls.iter().position(|(m, _)| m == method).unwrap();
+    assert!(calls.contains(&("pools".to_string(), "query")));
+    assert!(calls.contains(&("metadata".to_string(), "query")));
+    assert!(calls.contains(&("swap_async".to_string(), "update")));
+    assert!(calls.contains(&("depositFromAndSwap".to_string(), "update")));
+    assert!(position("pools") < position("swap_async"));
+    assert!(position("metadata") < position("depositFromAndSwap"));
+
+    // Kong のほうが安いので、Kong で ICP を払って BOB を受け取る
+    let swap = mock
+        .calls()
+        .into_iter()
+        .find(|c| c.method == "swap_async")
+        .unwrap();
+    let args = Decode!(&swap.args, KongSwapArgs).unwrap();
+    assert_eq!(args.pay_token, ICP_LEDGER_IC);
+    assert_eq!(args.receive_token, BOB);
+    let pay_amount: u128 = args.pay_amount.0.try_into().unwrap();
+    assert!(pay_amount > 0 && pay_amount <= 100 * E8);
+}
+
+#[tokio::test]
+async fn pushed_transient_error_is_retried_once() {
+    let mock = mock();
+    let client = client(&mock);
+    mock.push_reply(KONG_CANISTER, "pools", Err(transient("pools")));
+
+    let snapshot = fetch_kong(&client, KONG_CANISTER, "BOB_ICP")
+        .await
+        .expect("2 回目で成功する");
+    assert_eq!(snapshot.sns_raw, 200_000 * E8);
+    assert_eq!(mock.call_count("pools"), 2);
+
+    // 積んだ応答は 1 回だけ使い、その後は reply の応答に戻る
+    mock.push_reply(
+        KONG_CANISTER,
+        "pools",
+        Ok(pools_reply(190_000 * E8, 100_000 * E8)),
+    );
+    let trade = trade(client.clone());
+    assert_eq!(trade.update_kong_cache().await, Refresh::Updated);
+    let snapshot = fetch_kong(&client, KONG_CANISTER, "BOB_ICP").await.unwrap();
+    assert_eq!(snapshot.sns_raw, 200_000 * E8);
+}
+
+#[tokio::test]
+async fn failed_swap_leg_is_not_resent() {
+    let mock = mock();
+    // 結果の取得で失敗したレッグも、届いているかもしれないので送り直さない
+    mock.push_reply(KONG_CANISTER, "swap_async", Err(transient("swap_async")));
+
+    assert!(trade(client(&mock)).tick().await.is_err());
+    assert_eq!(mock.call_count("swap_async"), 1);
+    assert_eq!(mock.call_count("depositFromAndSwap"), 1);
+}
+
+#[tokio::test]
+async fn unset_reply_is_rejected() {
+    let mock = Arc::new(MockTransport::new());
+    let err = fetch_kong(&client(&mock), KONG_CANISTER, "BOB_ICP")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("応答が未設定です"), "{}", err);
+    assert_eq!(mock.call_count("pools"), 1);
+}
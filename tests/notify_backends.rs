@@ -0,0 +1,410 @@
+// どこで: cargo test で動く通知クライアントの結合テスト
+// 何を: ローカルの HTTP スタブに各送り先から POST させ、パスと JSON 本文を確かめる
+// なぜ: 実際の Discord / Slack / Telegram に送らずに、送信形式とファンアウトの振る舞いを保証するため
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use kong_ics::config::{NotifierConfig, NotifyConfig};
+use kong_ics::notify::dispatch::NotifyDispatcher;
+use kong_ics::notify::{
+    self, DiscordNotifier, Notification, Notifier, NotifyError, Severity, SlackNotifier,
+    WebhookNotifier,
+};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// 受け取ったリクエスト（パスと JSON 本文）を記録する。/fail で始まるパスには 500 を返し、
+/// /limited で始まるパスには最初の 1 回だけ 429 を返す
+struct Stub {
+    base: String,
+    received: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl Stub {
+    async fn start() -> Stub {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let Some((path, body)) = read_request(&mut stream).await else {
+                        return;
+                    };
+                    let first = {
+                        let mut log = log.lock().unwrap();
+                        let first = !log.iter().any(|(p, _)| *p == path);
+                        log.push((
+                            path.clone(),
+                            serde_json::from_slice(&body).unwrap_or(Value::Null),
+                        ));
+                        first
+                    };
+                    let (status, extra, body) = if path.starts_with("/fail") {
+                        ("500 Internal Server Error", "", "")
+                    } else if path.starts_with("/limited") && first {
+                        (
+                            "429 Too Many Requests",
+                            "retry-after: 5\r\ncontent-type: application/json\r\n",
+                            "{\"retry_after\": 0.3}",
+                        )
+                    } else {
+                        ("200 OK", "", "")
+                    };
+                    let res = format!(
+                        "HTTP/1.1 {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        extra,
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(res.as_bytes()).await;
+                });
+            }
+        });
+        Stub { base, received }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    fn received(&self) -> Vec<(String, Value)> {
+        let mut v = self.received.lock().unwrap().clone();
+        v.sort_by(|a, b| a.0.cmp(&b.0));
+        v
+    }
+}
+
+/// ヘッダを読み、content-length 分の本文を読む
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let header_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let path = head.split_whitespace().nth(1)?.to_string();
+    let len = head
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < header_end + len {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Some((path, buf[header_end..header_end + len].to_vec()))
+}
+
+#[tokio::test]
+async fn each_backend_posts_its_own_payload() {
+    let stub = Stub::start().await;
+    let cfg = NotifyConfig {
+        backends: vec![
+            NotifierConfig::Discord {
+                webhook_url: stub.url("/discord"),
+            },
+            NotifierConfig::Slack {
+                webhook_url: stub.url("/slack"),
+            },
+            NotifierConfig::Telegram {
+                bot_token: "123:abc".to_string(),
+                chat_id: "-10042".to_string(),
+                api_base: stub.url("/"),
+            },
+            NotifierConfig::Webhook {
+                url: stub.url("/hook"),
+            },
+        ],
+        alert_backends: vec![],
+        dedup_window_secs: 0,
+        queue_size: 16,
+    };
+    let notifier = notify::from_config(&cfg).expect("送り先があるので Some");
+    notifier
+        .notify("BOB が ics→kong で swap 実行")
+        .await
+        .unwrap();
+
+    let received = stub.received();
+    let paths: Vec<&str> = received.iter().map(|(p, _)| p.as_str()).collect();
+    assert_eq!(
+        paths,
+        ["/bot123:abc/sendMessage", "/discord", "/hook", "/slack"]
+    );
+    let text = "BOB が ics→kong で swap 実行";
+    assert_eq!(received[0].1["chat_id"], "-10042");
+    assert_eq!(received[0].1["text"], text);
+    assert_eq!(received[1].1["content"], text);
+    assert_eq!(received[2].1["text"], text);
+    assert_eq!(received[2].1["source"], "kong_ics");
+    assert_eq!(received[3].1["text"], text);
+}
+
+#[tokio::test]
+async fn fanout_delivers_to_the_rest_when_one_backend_fails() {
+    let stub = Stub::start().await;
+    let cfg = NotifyConfig {
+        backends: vec![
+            NotifierConfig::Slack {
+                webhook_url: stub.url("/fail-slack"),
+            },
+            NotifierConfig::Discord {
+                webhook_url: stub.url("/discord"),
+            },
+        ],
+        alert_backends: vec![],
+        dedup_window_secs: 0,
+        queue_size: 16,
+    };
+    let notifier = notify::from_config(&cfg).unwrap();
+    let err = notifier.notify("approve sent").await.unwrap_err();
+
+    match err {
+        NotifyError::Partial(msg) => {
+            assert!(msg.contains("slack"), "{}", msg);
+            assert!(!msg.contains("discord"), "{}", msg);
+        }
+        other => panic!("Partial を期待: {}", other),
+    }
+    let received = stub.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].0, "/discord");
+    assert_eq!(received[0].1["content"], "approve sent");
+}
+
+#[tokio::test]
+async fn no_backends_means_no_notifier() {
+    assert!(notify::from_config(&NotifyConfig {
+        backends: vec![],
+        alert_backends: vec![],
+        dedup_window_secs: 0,
+        queue_size: 16,
+    })
+    .is_none());
+}
+
+#[tokio::test]
+async fn single_backend_reports_http_status() {
+    let stub = Stub::start().await;
+    let hook = WebhookNotifier::new(stub.url("/fail"));
+    let err = hook.notify("x").await.unwrap_err();
+    assert!(
+        matches!(err, NotifyError::Http(ref m) if m.contains("500")),
+        "{}",
+        err
+    );
+}
+
+fn swap_failed() -> Notification {
+    Notification::new("swap_failed", Severity::Error, "swap 失敗")
+        .with_pair("BOB_ICP")
+        .with_field("方向", "kong→ics")
+        .with_field("kong request id", "ab12")
+}
+
+#[tokio::test]
+async fn discord_sends_embed_with_severity_colour() {
+    let stub = Stub::start().await;
+    let discord = DiscordNotifier::new(stub.url("/discord"));
+    discord.send(&swap_failed()).await.unwrap();
+
+    let received = stub.received();
+    let embed = &received[0].1["embeds"][0];
+    assert_eq!(embed["title"], "BOB_ICP swap 失敗");
+    assert_eq!(embed["color"], 0xe74c3c);
+    assert_eq!(embed["fields"][0]["name"], "方向");
+    assert_eq!(embed["fields"][0]["value"], "kong→ics");
+    assert_eq!(embed["fields"][1]["value"], "ab12");
+    assert_eq!(embed["footer"]["text"], "swap_failed / error");
+    assert!(received[0].1.get("content").is_none());
+}
+
+#[tokio::test]
+async fn text_backends_render_structured_notification() {
+    let stub = Stub::start().await;
+    let slack = SlackNotifier::new(stub.url("/slack"));
+    let hook = WebhookNotifier::new(stub.url("/hook"));
+    slack.send(&swap_failed()).await.unwrap();
+    hook.send(&swap_failed()).await.unwrap();
+
+    let received = stub.received();
+    let text = "[error] BOB_ICP swap 失敗\n方向: kong→ics\nkong request id: ab12";
+    assert_eq!(received[0].0, "/hook");
+    assert_eq!(received[0].1["text"], text);
+    assert_eq!(received[0].1["event"], "swap_failed");
+    assert_eq!(received[0].1["severity"], "error");
+    assert_eq!(received[0].1["pair"], "BOB_ICP");
+    assert_eq!(received[0].1["fields"]["kong request id"], "ab12");
+    assert_eq!(received[1].1["text"], text);
+}
+
+#[tokio::test]
+async fn warnings_and_errors_go_to_the_alert_webhook() {
+    let stub = Stub::start().await;
+    let cfg = NotifyConfig {
+        backends: vec![NotifierConfig::Discord {
+            webhook_url: stub.url("/routine"),
+        }],
+        alert_backends: vec![NotifierConfig::Discord {
+            webhook_url: stub.url("/alerts"),
+        }],
+        dedup_window_secs: 0,
+        queue_size: 16,
+    };
+    let notifier = notify::from_config(&cfg).unwrap();
+    notifier
+        .send(&Notification::new(
+            "swap_executed",
+            Severity::Info,
+            "swap 実行",
+        ))
+        .await
+        .unwrap();
+    notifier
+        .send(&Notification::new(
+            "approve_failed",
+            Severity::Warning,
+            "approve 失敗",
+        ))
+        .await
+        .unwrap();
+    notifier.send(&swap_failed()).await.unwrap();
+    notifier.notify("日次サマリ").await.unwrap();
+
+    let received = stub.received();
+    let routed: Vec<(&str, String)> = received
+        .iter()
+        .map(|(p, body)| {
+            let label = body["embeds"][0]["footer"]["text"]
+                .as_str()
+                .or(body["content"].as_str())
+                .unwrap()
+                .to_string();
+            (p.as_str(), label)
+        })
+        .collect();
+    assert_eq!(routed.len(), 4);
+    let to = |path: &str| {
+        let mut v: Vec<String> = routed
+            .iter()
+            .filter(|(p, _)| *p == path)
+            .map(|(_, l)| l.clone())
+            .collect();
+        v.sort();
+        v
+    };
+    assert_eq!(
+        to("/alerts"),
+        ["approve_failed / warning", "swap_failed / error"]
+    );
+    assert_eq!(to("/routine"), ["swap_executed / info", "日次サマリ"]);
+}
+
+#[tokio::test]
+async fn rate_limited_request_is_retried_after_the_given_delay() {
+    let stub = Stub::start().await;
+    let discord = DiscordNotifier::new(stub.url("/limited"));
+    let started = Instant::now();
+    discord.notify("429 の後に届く").await.unwrap();
+
+    // ヘッダの 5 秒ではなく本文の retry_after (0.3 秒) を使う
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    let received = stub.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[1].1["content"], "429 の後に届く");
+}
+
+/// 送信に時間がかかる送り先
+struct Slow;
+
+impl Notifier for Slow {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    fn notify<'a>(&'a self, _message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            Ok(())
+        })
+    }
+}
+
+#[tokio::test]
+async fn dispatcher_returns_without_waiting_and_drops_when_full() {
+    let dispatcher = NotifyDispatcher::start(Arc::new(Slow), Duration::ZERO, 2);
+    let started = Instant::now();
+    let mut results = Vec::new();
+    for i in 0..5 {
+        results.push(dispatcher.notify(&format!("m{}", i)).await);
+    }
+    assert!(started.elapsed() < Duration::from_millis(100));
+    assert!(results[0].is_ok());
+    assert!(results
+        .iter()
+        .any(|r| matches!(r, Err(NotifyError::QueueFull))));
+}
+
+#[tokio::test]
+async fn repeated_notifications_are_batched_into_a_digest() {
+    let stub = Stub::start().await;
+    let inner = Arc::new(DiscordNotifier::new(stub.url("/discord")));
+    let dispatcher = NotifyDispatcher::start(inner, Duration::from_secs(1), 16);
+    for i in 0..5 {
+        let n = swap_failed().with_field("回", i.to_string());
+        dispatcher.send(&n).await.unwrap();
+    }
+    dispatcher
+        .send(&Notification::new("swap_executed", Severity::Info, "swap 実行").with_pair("BOB_ICP"))
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let first: Vec<String> = stub
+        .received()
+        .iter()
+        .map(|(_, b)| b["embeds"][0]["title"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(first.len(), 2, "{:?}", first);
+    assert!(first.contains(&"BOB_ICP swap 失敗".to_string()));
+
+    tokio::time::sleep(Duration::from_millis(1_500)).await;
+    let received = stub.received();
+    assert_eq!(received.len(), 3);
+    let digest = received
+        .iter()
+        .map(|(_, b)| &b["embeds"][0])
+        .find(|e| e["title"] == "BOB_ICP swap 失敗 (他に 4 件)")
+        .expect("ダイジェストが届く");
+    let fields = digest["fields"].as_array().unwrap();
+    // 最後の 1 件の内容と、まとめた件数を載せる
+    assert!(fields
+        .iter()
+        .any(|f| f["name"] == "回" && f["value"] == "4"));
+    assert!(fields
+        .iter()
+        .any(|f| f["name"] == "まとめた件数" && f["value"] == "4"));
+}
@@ -0,0 +1,829 @@
+// どこで: cargo test で動く結合シナリオ
+// 何を: シミュレータ上で Trade::tick と approve の流れを通し、残高・リザーブの動きを確かめる
+// なぜ: 判定から約定・返金までをネットワーク無しで再現できることを保証するため
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use candid::Principal;
+use futures::future::BoxFuture;
+use kong_ics::alert::AlertManager;
+use kong_ics::arb::{evaluate, Refresh, Trade};
+use kong_ics::breaker::{PriceAnomaly, PriceBreaker};
+use kong_ics::config::{
+    parse_subaccount, AlertConfig, BreakerConfig, PairConfig, RetryPolicy, RiskConfig,
+    DEFAULT_IDENTITY, ICP_LEDGER_IC, ICP_LEDGER_RAW, KONG_CANISTER,
+};
+use kong_ics::control::{ControlError, ControlSource, TradingControl, ALL_PAIRS};
+use kong_ics::ic_client::agent::{IcClient, IcClientError};
+use kong_ics::ic_client::ics::fetch_pool_snapshot as fetch_ics;
+use kong_ics::ic_client::kong::fetch_pool_snapshot as fetch_kong;
+use kong_ics::ic_client::ledger::{approve, approve_from, fetch_allowance, fetch_balance, Account};
+use kong_ics::ic_client::swap::submit_swap_kong;
+use kong_ics::journal::now_ms;
+use kong_ics::journal::{Journal, RequestStatus, SwapRequestRecord};
+use kong_ics::notify::{Notification, Notifier, NotifyError, Severity};
+use kong_ics::pnl::IdentitySwaps;
+use kong_ics::report::{compose_report, PairReport, Period, TradeStats};
+use kong_ics::risk::{RiskBreach, RiskManager};
+use kong_ics::sim::icpswap::SimIcsPool;
+use kong_ics::sim::kong::{Reply, SimKongPool};
+use kong_ics::sim::Simulator;
+
+const BOB: &str = "7pail-xaaaa-aaaas-aabmq-cai";
+// 実際の BOB は 0.01 BOB だが、ICS レッグの最低受取は中間トークンの fee を考慮しないので小さくしておく
+const BOB_FEE: u128 = 10_000;
+const BOB_ICS_LP: &str = "ybilh-nqaaa-aaaag-qkhzq-cai";
+const ICP_FEE: u128 = 10_000;
+const E8: u128 = 100_000_000;
+
+fn bot() -> Principal {
+    Principal::self_authenticating(b"sim-bot")
+}
+
+/// Kong は 0.5 ICP/BOB、ICPSwap は 0.55 ICP/BOB。bot は ICP と BOB の在庫と allowance を持つ
+fn setup() -> Arc<Simulator> {
+    setup_with(Simulator::new(bot()))
+}
+
+fn setup_with(sim: Simulator) -> Arc<Simulator> {
+    let sim = Arc::new(sim);
+    sim.add_ledger(ICP_LEDGER_RAW, ICP_FEE);
+    sim.add_ledger(BOB, BOB_FEE);
+    sim.add_kong_pool(
+        KONG_CANISTER,
+        SimKongPool {
+            symbol: "BOB_ICP".to_string(),
+            symbol_0: "BOB".to_string(),
+            symbol_1: "ICP".to_string(),
+            ledger_0: BOB.to_string(),
+            ledger_1: ICP_LEDGER_RAW.to_string(),
+            balance_0: 200_000 * E8,
+            balance_1: 100_000 * E8,
+            lp_fee_0: 0,
+            lp_fee_1: 0,
+            lp_fee_bps: 30,
+        },
+    );
+    sim.add_ics_pool(
+        BOB_ICS_LP,
+        SimIcsPool {
+            ledger0: BOB.to_string(),
+            ledger1: ICP_LEDGER_RAW.to_string(),
+            reserve0: 200_000 * E8,
+            reserve1: 110_000 * E8,
+            fee_pips: 3_000,
+        },
+    );
+    sim.mint(ICP_LEDGER_RAW, bot(), 1_000 * E8);
+    sim.mint(BOB, bot(), 1_000 * E8);
+    sim
+}
+
+fn client(sim: &Arc<Simulator>) -> Arc<IcClient> {
+    let fast = RetryPolicy {
+        max_attempts: 3,
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+    };
+    Arc::new(IcClient::from_transport(sim.clone()).with_retry(fast, fast))
+}
+
+async fn approve_all(client: &IcClient) {
+    for (ledger, spender) in [
+        (ICP_LEDGER_RAW, KONG_CANISTER),
+        (ICP_LEDGER_RAW, BOB_ICS_LP),
+        (BOB, KONG_CANISTER),
+        (BOB, BOB_ICS_LP),
+    ] {
+        approve(client, ledger, spender, 1_000 * E8)
+            .await
+            .expect("approve");
+    }
+}
+
+fn trade(client: Arc<IcClient>, min_receive_factor: f64) -> Trade {
+    let pair = PairConfig {
+        token_icp: ICP_LEDGER_IC.to_string(),
+        token_sns: BOB.to_string(),
+        kong_canister: KONG_CANISTER.to_string(),
+        icpswap_lp: BOB_ICS_LP.to_string(),
+        symbol: "BOB_ICP".to_string(),
+        ikiti_e8: 100 * E8,
+        sns_fee_e8: BOB_FEE,
+        identity: DEFAULT_IDENTITY.to_string(),
+    };
+    Trade::new(pair, client, None, 0.003, min_receive_factor, 0f64, 1_000)
+}
+
+#[tokio::test]
+async fn approve_sets_allowance_and_charges_fee() {
+    let sim = setup();
+    let client = client(&sim);
+    let before = fetch_balance(&client, ICP_LEDGER_RAW, bot()).await.unwrap();
+    assert_eq!(
+        fetch_allowance(&client, ICP_LEDGER_RAW, bot(), KONG_CANISTER)
+            .await
+            .unwrap(),
+        0
+    );
+
+    approve(&client, ICP_LEDGER_RAW, KONG_CANISTER, 50 * E8)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        fetch_allowance(&client, ICP_LEDGER_RAW, bot(), KONG_CANISTER)
+            .await
+            .unwrap(),
+        50 * E8
+    );
+    let after = fetch_balance(&client, ICP_LEDGER_RAW, bot()).await.unwrap();
+    assert_eq!(before - after, ICP_FEE);
+}
+
+#[tokio::test]
+async fn approve_from_subaccount_is_separate_from_default() {
+    let sim = setup();
+    let client = client(&sim);
+    let sub = parse_subaccount("1");
+    let account = Account::new(bot(), sub.clone());
+    sim.mint_to_subaccount(ICP_LEDGER_RAW, bot(), sub.as_deref(), 10 * E8);
+
+    approve_from(&client, ICP_LEDGER_RAW, sub.clone(), KONG_CANISTER, 5 * E8)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        fetch_allowance(&client, ICP_LEDGER_RAW, account.clone(), KONG_CANISTER)
+            .await
+            .unwrap(),
+        5 * E8
+    );
+    assert_eq!(
+        fetch_allowance(&client, ICP_LEDGER_RAW, bot(), KONG_CANISTER)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        fetch_balance(&client, ICP_LEDGER_RAW, account)
+            .await
+            .unwrap(),
+        10 * E8 - ICP_FEE
+    );
+}
+
+#[tokio::test]
+async fn tick_executes_arbitrage_and_moves_reserves() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let icp_before = sim.balance(ICP_LEDGER_RAW, bot());
+
+    trade(client, 0.99).tick().await.expect("tick");
+
+    // Kong で ICP→BOB、ICPSwap で BOB→ICP
+    let kong = sim.kong_pool(KONG_CANISTER, "BOB_ICP").unwrap();
+    assert!(kong.balance_1 > 100_000 * E8);
+    assert!(kong.lp_fee_1 > 0);
+    let ics = sim.ics_pool(BOB_ICS_LP).unwrap();
+    assert!(ics.reserve0 > 200_000 * E8);
+    assert!(ics.reserve1 < 110_000 * E8);
+    let requests = sim.kong_requests(KONG_CANISTER);
+    assert_eq!(requests.len(), 1);
+    assert!(matches!(&requests[0].reply, Reply::Swap(r) if r.status == "Success"));
+    assert!(sim.balance(ICP_LEDGER_RAW, bot()) > icp_before);
+}
+
+#[tokio::test]
+async fn slippage_rejects_refund_and_leave_pools_untouched() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let icp_before = sim.balance(ICP_LEDGER_RAW, bot());
+    let bob_before = sim.balance(BOB, bot());
+
+    // 見込みより多い最低受取を要求して両レッグともスリッページで弾かせる
+    assert!(trade(client, 1.2).tick().await.is_err());
+
+    let kong = sim.kong_pool(KONG_CANISTER, "BOB_ICP").unwrap();
+    assert_eq!(kong.balance_1, 100_000 * E8);
+    let ics = sim.ics_pool(BOB_ICS_LP).unwrap();
+    assert_eq!(ics.reserve0, 200_000 * E8);
+    let requests = sim.kong_requests(KONG_CANISTER);
+    assert!(matches!(&requests[0].reply, Reply::Swap(r) if r.status == "Failed"));
+    // transfer_from と返金の transfer fee だけ減る
+    assert_eq!(icp_before - sim.balance(ICP_LEDGER_RAW, bot()), 2 * ICP_FEE);
+    assert_eq!(bob_before - sim.balance(BOB, bot()), 2 * BOB_FEE);
+}
+
+/// テストごとの一時 DB（WAL のファイルも含めて消す）
+fn temp_db(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("kong_ics_{}_{}.db", name, std::process::id()));
+    remove_db(&path);
+    path
+}
+
+fn remove_db(path: &std::path::Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn unresolved_request_is_recovered_after_restart() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let path = temp_db("recover");
+
+    // 送信して request id を残したところでプロセスが落ちた
+    let submitted = submit_swap_kong(&client, KONG_CANISTER, ICP_LEDGER_IC, BOB, E8, 0)
+        .await
+        .expect("submit");
+    {
+        let journal = Journal::open(&path).unwrap();
+        journal
+            .record_request(&SwapRequestRecord {
+                id: 0,
+                swap_id: None,
+                pair: "BOB_ICP".to_string(),
+                leg: "kong".to_string(),
+                canister: submitted.canister.clone(),
+                method: submitted.method.clone(),
+                request_id: submitted.request_id_hex(),
+                submitted_ms: submitted.submitted_ms,
+                status: RequestStatus::Pending.as_str().to_string(),
+                reply: None,
+                error: None,
+                finished_ms: None,
+            })
+            .unwrap();
+    }
+
+    // 再起動後は保存した request id から結果を確認し、ジャーナルの状態を確定させる
+    let journal = Arc::new(Journal::open(&path).unwrap());
+    let unresolved = journal.unresolved_requests("BOB_ICP", 0).unwrap();
+    assert_eq!(unresolved.len(), 1);
+    assert_eq!(unresolved[0].request_id, submitted.request_id_hex());
+    trade(client, 0.99)
+        .with_journal(journal.clone(), false)
+        .recover_unresolved_swaps()
+        .await;
+    assert!(journal
+        .unresolved_requests("BOB_ICP", 0)
+        .unwrap()
+        .is_empty());
+    assert_eq!(sim.kong_requests(KONG_CANISTER).len(), 1);
+
+    drop(journal);
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn transient_query_failure_is_retried() {
+    let sim = setup();
+    let client = client(&sim);
+    sim.inject_error(
+        KONG_CANISTER,
+        "pools",
+        IcClientError::Transport {
+            method: "pools".to_string(),
+            status: Some(503),
+            message: "service unavailable".to_string(),
+        },
+    );
+
+    let trade = trade(client, 0.99);
+    assert_eq!(trade.update_kong_cache().await, Refresh::Updated);
+    assert_eq!(sim.call_count("pools"), 2);
+}
+
+#[tokio::test]
+async fn unverified_snapshot_refuses_to_trade() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let trade = trade(client, 0.99).with_verified_queries(true);
+
+    // 前回のキャッシュが残っていても、この tick の検証に失敗したら swap しない
+    assert_eq!(trade.update_ics_cache().await, Refresh::Updated);
+    sim.inject_error(
+        BOB_ICS_LP,
+        "metadata",
+        IcClientError::Certificate {
+            method: "metadata".to_string(),
+            message: "Query signature verification failed.".to_string(),
+        },
+    );
+    // 見送るだけで tick の失敗にはしない
+    trade.tick().await.expect("tick");
+    assert_eq!(
+        trade.verification_refused(),
+        Some("query 応答の署名検証に失敗")
+    );
+    assert_eq!(sim.call_count("swap_async"), 0);
+    assert_eq!(sim.call_count("depositFromAndSwap"), 0);
+
+    // 次の tick で検証が通れば取引する
+    trade.tick().await.expect("tick");
+    assert_eq!(trade.verification_refused(), None);
+    assert_eq!(sim.call_count("swap_async"), 1);
+}
+
+#[tokio::test]
+async fn disabled_verification_refuses_to_trade_when_required() {
+    let sim = setup_with(Simulator::new(bot()).without_query_verification());
+    let client = client(&sim);
+    approve_all(&client).await;
+
+    let required = trade(client.clone(), 0.99).with_verified_queries(true);
+    required.tick().await.expect("tick");
+    assert!(required.verification_refused().is_some());
+    assert_eq!(sim.call_count("swap_async"), 0);
+
+    // 要求しなければ従来どおり取引する
+    trade(client, 0.99).tick().await.expect("tick");
+    assert_eq!(sim.call_count("swap_async"), 1);
+}
+
+#[tokio::test]
+async fn stats_count_executions_and_report_allowances() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let stats = Arc::new(TradeStats::new());
+    let ok = trade(client.clone(), 0.99).with_stats(stats.clone());
+    let rejected = trade(client, 1.2).with_stats(stats.clone());
+
+    ok.tick().await.expect("tick");
+    assert!(rejected.tick().await.is_err());
+
+    let daily = stats.take(Period::Daily);
+    let c = &daily["BOB_ICP"];
+    assert_eq!((c.evaluated, c.opportunities, c.executed), (2, 2, 2));
+    assert_eq!((c.succeeded, c.failed), (1, 1));
+    assert!(c.fees_e8 > 0f64);
+    // 日次を取り出しても毎時の集計は残る
+    assert!(stats.take(Period::Daily).is_empty());
+    assert_eq!(stats.take(Period::Hourly)["BOB_ICP"], *c);
+
+    let allowances = ok.fetch_allowances().await;
+    let labels: Vec<&str> = allowances.iter().map(|(l, _)| l.as_str()).collect();
+    assert_eq!(
+        labels,
+        ["ICP→kong", "ICP→icpswap", "BOB→kong", "BOB→icpswap"]
+    );
+    assert!(allowances.iter().all(|(_, a)| a.is_some()));
+
+    let report = compose_report(
+        "日次レポート",
+        "report_daily",
+        &[PairReport {
+            pair: "BOB_ICP".to_string(),
+            sns_label: ok.sns_label().to_string(),
+            counters: c.clone(),
+            balances: ok.fetch_balances().await,
+            allowances,
+        }],
+    );
+    let text = report.text();
+    assert!(text.contains("機会 2 / 実行 2 (判定 2)"), "{}", text);
+    assert!(text.contains("成功 1 / 失敗 1"), "{}", text);
+    assert!(text.contains("allowance ICP→kong"), "{}", text);
+}
+
+/// 送られた通知を溜めるだけの送り先
+#[derive(Default)]
+struct Captured(Mutex<Vec<Notification>>);
+
+impl Captured {
+    fn events(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| n.event.clone())
+            .collect()
+    }
+}
+
+impl Notifier for Captured {
+    fn name(&self) -> &str {
+        "captured"
+    }
+
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<(), NotifyError>> {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Notification::new("text", Severity::Info, message));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        self.0.lock().unwrap().push(notification.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[tokio::test]
+async fn realized_pnl_uses_fills_and_skips_overlapping_swaps() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let stats = Arc::new(TradeStats::new());
+
+    // Kong→ICS: Kong の入金で ICP の fee 1 回、ICS の入金で BOB の fee 1 回、出金で ICP の fee 1 回
+    trade(client.clone(), 0.99)
+        .with_stats(stats.clone())
+        .tick()
+        .await
+        .expect("tick");
+    let requests = sim.kong_requests(KONG_CANISTER);
+    assert_eq!(requests.len(), 1);
+    let c = &stats.take(Period::Daily)["BOB_ICP"];
+    assert_eq!(c.succeeded, 1);
+    let fees = 2f64 * ICP_FEE as f64 + BOB_FEE as f64 * 0.5;
+    assert!((c.fees_e8 - fees).abs() < 100f64, "fees {}", c.fees_e8);
+    assert!(c.realized_pnl_e8 != 0f64);
+
+    // 同じ identity の別ペアが swap 中なら、残高の差分に混ざるので記録しない
+    let shared = Arc::new(IdentitySwaps::default());
+    let other = shared.begin();
+    trade(client, 0.99)
+        .with_stats(stats.clone())
+        .with_identity_swaps(shared.clone())
+        .tick()
+        .await
+        .expect("tick");
+    drop(other);
+    let c = &stats.take(Period::Daily)["BOB_ICP"];
+    assert_eq!(c.succeeded, 1);
+    assert_eq!((c.realized_pnl_e8, c.fees_e8), (0f64, 0f64));
+    assert_eq!(sim.kong_requests(KONG_CANISTER).len(), 2);
+}
+
+#[tokio::test]
+async fn alerts_fire_once_and_recover() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let captured = Arc::new(Captured::default());
+    let alerts = Arc::new(AlertManager::new(
+        Some(captured.clone()),
+        AlertConfig {
+            tick_failures: 2,
+            swap_failures: 1,
+            approve_failures: 1,
+            snapshot_stale_secs: 0,
+            min_balances_e8: vec![("ICP".to_string(), 2_000 * E8)],
+        },
+    ));
+    let rejected = trade(client.clone(), 1.2).with_alerts(alerts.clone());
+    let ok = trade(client, 0.99).with_alerts(alerts.clone());
+
+    // 1 回目: swap の警告だけ（tick はしきい値 2 に届かない）
+    assert!(rejected.tick().await.is_err());
+    let events = captured.events();
+    assert_eq!(events.len(), 1);
+    assert!(
+        ["swap_failed", "swap_partial"].contains(&events[0].as_str()),
+        "{:?}",
+        events
+    );
+    // 2 回目: tick の警告が増え、swap の警告は繰り返さない
+    assert!(rejected.tick().await.is_err());
+    assert_eq!(captured.events()[1..], ["tick_failures"]);
+    assert_eq!(
+        alerts.active(),
+        ["swap_failed|BOB_ICP", "tick_failures|BOB_ICP"]
+    );
+
+    ok.tick().await.expect("tick");
+    let events = captured.events();
+    assert!(
+        events.contains(&"swap_failed_recovered".to_string()),
+        "{:?}",
+        events
+    );
+    assert!(
+        events.contains(&"tick_failures_recovered".to_string()),
+        "{:?}",
+        events
+    );
+    assert!(alerts.active().is_empty());
+
+    // ICP 残高 (約 1,000) は下限 2,000 を下回る。2 回見ても警告は 1 回
+    ok.fetch_balances().await.expect("balances");
+    ok.fetch_balances().await.expect("balances");
+    let low: Vec<Notification> = captured
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|n| n.event == "balance_low")
+        .cloned()
+        .collect();
+    assert_eq!(low.len(), 1);
+    assert_eq!(low[0].severity, Severity::Warning);
+}
+
+#[tokio::test]
+async fn pause_stops_swaps_but_keeps_refreshing() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let captured = Arc::new(Captured::default());
+    let control = Arc::new(TradingControl::new(
+        Some(captured.clone()),
+        vec!["BOB_ICP".to_string()],
+    ));
+    let trade = trade(client, 0.99).with_control(control.clone());
+
+    // ペア単位の停止: スナップショットは更新するが swap しない
+    assert!(control
+        .pause("BOB_ICP", ControlSource::Http, "点検")
+        .unwrap());
+    assert!(!control
+        .pause("BOB_ICP", ControlSource::Http, "点検")
+        .unwrap());
+    assert!(matches!(
+        control.pause("ETH_ICP", ControlSource::Http, "点検"),
+        Err(ControlError::UnknownPair(_))
+    ));
+    trade.tick().await.expect("tick");
+    assert_eq!(sim.call_count("pools"), 1);
+    assert_eq!(sim.call_count("swap_async"), 0);
+
+    // 停止ファイルによる全ペア停止は、ファイルを消すまで管理 API から解除できない
+    let path = std::env::temp_dir().join(format!("kong_ics_pause_{}", std::process::id()));
+    std::fs::write(&path, "# メンテナンス中\n").unwrap();
+    control.sync_pause_file(&path);
+    assert!(control.resume("BOB_ICP", ControlSource::Http).unwrap());
+    assert!(matches!(
+        control.resume(ALL_PAIRS, ControlSource::Http),
+        Err(ControlError::HeldByFile(_))
+    ));
+    trade.tick().await.expect("tick");
+    assert_eq!(sim.call_count("swap_async"), 0);
+
+    std::fs::remove_file(&path).unwrap();
+    control.sync_pause_file(&path);
+    assert!(control.status().is_empty());
+    trade.tick().await.expect("tick");
+    assert_eq!(sim.call_count("swap_async"), 1);
+
+    // 通知は送信を待たずに投げているので、届くまで少し待つ
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(
+        captured.events(),
+        [
+            "trading_paused",
+            "trading_paused",
+            "trading_resumed",
+            "trading_resumed"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unknown_pair_in_pause_file_pauses_everything() {
+    let control = TradingControl::new(None, vec!["BOB_ICP".to_string(), "KONG_ICP".to_string()]);
+    let path = std::env::temp_dir().join(format!("kong_ics_pause_unknown_{}", std::process::id()));
+
+    // BOB_ICP の書き間違い。どれを止めたかったか分からないので全ペアを止める
+    std::fs::write(&path, "BOB_IPC\n").unwrap();
+    control.sync_pause_file(&path);
+    let pause = control.paused("KONG_ICP").expect("全ペア停止");
+    assert_eq!(pause.source, ControlSource::File);
+    assert!(pause.reason.contains("BOB_IPC"), "{}", pause.reason);
+    assert!(control.status().contains_key(ALL_PAIRS));
+
+    // 直せば書いたペアだけの停止に戻る
+    std::fs::write(&path, "BOB_ICP\n").unwrap();
+    control.sync_pause_file(&path);
+    assert!(control.paused("BOB_ICP").is_some());
+    assert!(control.paused("KONG_ICP").is_none());
+
+    std::fs::remove_file(&path).unwrap();
+    control.sync_pause_file(&path);
+    assert!(control.status().is_empty());
+}
+
+#[tokio::test]
+async fn risk_limits_refuse_and_pause() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+    let captured = Arc::new(Captured::default());
+    let alerts = Arc::new(AlertManager::new(
+        Some(captured.clone()),
+        AlertConfig {
+            tick_failures: 0,
+            swap_failures: 0,
+            approve_failures: 0,
+            snapshot_stale_secs: 0,
+            min_balances_e8: Vec::new(),
+        },
+    ));
+    let control = Arc::new(TradingControl::new(None, vec!["BOB_ICP".to_string()]));
+    let risk = Arc::new(RiskManager::new(
+        RiskConfig {
+            max_daily_loss_e8: (E8 / 10) as f64,
+            max_notional_per_hour_e8: 1f64,
+            max_in_flight: 1,
+            max_inventory_e8: vec![("BOB_ICP".to_string(), 10 * E8)],
+        },
+        control.clone(),
+        alerts.clone(),
+    ));
+    let trade = trade(client, 0.99).with_risk(risk.clone());
+
+    // 投入額の上限を超えるので swap せず、警告は 2 回見送っても 1 回
+    trade.tick().await.expect("tick");
+    trade.tick().await.expect("tick");
+    assert_eq!(sim.call_count("swap_async"), 0);
+    assert_eq!(captured.events(), ["risk_notional"]);
+
+    // 実行中の枠は permit を手放すまで埋まる
+    let unlimited = RiskManager::new(
+        RiskConfig {
+            max_notional_per_hour_e8: 0f64,
+            ..risk.config().clone()
+        },
+        control.clone(),
+        Arc::new(AlertManager::new(None, alerts.config().clone())),
+    );
+    let permit = unlimited.acquire("BOB_ICP", 1f64).await.expect("permit");
+    assert!(matches!(
+        unlimited.acquire("BOB_ICP", 1f64).await,
+        Err(RiskBreach::InFlight(1))
+    ));
+    drop(permit);
+    assert!(unlimited.acquire("BOB_ICP", 1f64).await.is_ok());
+
+    // 日次損失が上限に達したら全ペアを止める（警告は 1 回）
+    risk.record_pnl("BOB_ICP", -0.06 * E8 as f64).await;
+    assert!(control.paused("BOB_ICP").is_none());
+    risk.record_pnl("BOB_ICP", -0.06 * E8 as f64).await;
+    risk.record_pnl("BOB_ICP", -0.06 * E8 as f64).await;
+    assert!(control.status().contains_key(ALL_PAIRS));
+    // 日付が変わると損失上限による停止だけ解く（管理 API の停止は残す）
+    control
+        .pause("BOB_ICP", ControlSource::Http, "点検")
+        .unwrap();
+    risk.roll_day(now_ms() + 86_400_000).await;
+    assert!(!control.status().contains_key(ALL_PAIRS));
+    assert_eq!(risk.daily_pnl_e8(), 0f64);
+    assert!(control.resume("BOB_ICP", ControlSource::Http).unwrap());
+    // 在庫の上限を超えたペアも止め、下回ったら復旧だけ送る
+    risk.record_inventory("BOB_ICP", "BOB", 11 * E8).await;
+    risk.record_inventory("BOB_ICP", "BOB", 11 * E8).await;
+    assert!(control.status().contains_key("BOB_ICP"));
+    risk.record_inventory("BOB_ICP", "BOB", 9 * E8).await;
+    assert!(control.status().contains_key("BOB_ICP"));
+    assert_eq!(
+        captured.events()[1..],
+        [
+            "risk_daily_loss",
+            "risk_daily_loss_recovered",
+            "risk_inventory",
+            "risk_inventory_recovered"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn operator_pause_over_a_risk_pause_survives_the_day_rollover() {
+    let control = Arc::new(TradingControl::new(
+        None,
+        vec!["BOB_ICP".to_string(), "KONG_ICP".to_string()],
+    ));
+    let risk = RiskManager::new(
+        RiskConfig {
+            max_daily_loss_e8: (E8 / 10) as f64,
+            max_notional_per_hour_e8: 0f64,
+            max_in_flight: 0,
+            max_inventory_e8: Vec::new(),
+        },
+        control.clone(),
+        Arc::new(AlertManager::new(
+            None,
+            AlertConfig {
+                tick_failures: 0,
+                swap_failures: 0,
+                approve_failures: 0,
+                snapshot_stale_secs: 0,
+                min_balances_e8: Vec::new(),
+            },
+        )),
+    );
+
+    // 損失上限で全ペアが止まった後に、運用者が同じ全ペアを管理 API で止める
+    risk.record_pnl("BOB_ICP", -0.2 * E8 as f64).await;
+    assert_eq!(control.status()[ALL_PAIRS].source, ControlSource::Risk);
+    assert!(!control
+        .pause(ALL_PAIRS, ControlSource::Http, "調査中")
+        .unwrap());
+    let pause = &control.status()[ALL_PAIRS];
+    assert_eq!(pause.source, ControlSource::Http);
+    assert_eq!(pause.reason, "調査中");
+
+    // 日付が変わっても運用者の停止は残り、管理 API からは解ける
+    risk.roll_day(now_ms() + 86_400_000).await;
+    assert_eq!(risk.daily_pnl_e8(), 0f64);
+    assert!(control.paused("KONG_ICP").is_some());
+    assert!(control.resume(ALL_PAIRS, ControlSource::Http).unwrap());
+    assert!(control.status().is_empty());
+}
+
+#[tokio::test]
+async fn price_breaker_refuses_abnormal_prices() {
+    let sim = setup();
+    let client = client(&sim);
+    approve_all(&client).await;
+
+    // ICS の価格は sqrtPriceX96 から読む（0.55 ICP/BOB）
+    let ics = fetch_ics(&client, BOB_ICS_LP).await.expect("ics");
+    assert!((ics.price_icp_per_sns - 0.55).abs() < 1e-6);
+    let kong = fetch_kong(&client, KONG_CANISTER, "BOB_ICP")
+        .await
+        .expect("kong");
+    assert!((kong.price_icp_per_sns - 0.5).abs() < 1e-6);
+
+    // 2 会場の乖離 (約 950 bps) が上限を超えるので swap せず、警告は 1 回
+    let captured = Arc::new(Captured::default());
+    let alerts = Arc::new(AlertManager::new(
+        Some(captured.clone()),
+        AlertConfig {
+            tick_failures: 0,
+            swap_failures: 0,
+            approve_failures: 0,
+            snapshot_stale_secs: 0,
+            min_balances_e8: Vec::new(),
+        },
+    ));
+    let config = BreakerConfig {
+        max_divergence_bps: 500.0,
+        max_jump_bps: 0.0,
+        max_edge_bps: 0.0,
+        median_window_secs: 60,
+    };
+    let trade = trade(client.clone(), 0.99)
+        .with_alerts(alerts)
+        .with_price_breaker(config.clone());
+    trade.tick().await.expect("tick");
+    trade.tick().await.expect("tick");
+    assert_eq!(sim.call_count("swap_async"), 0);
+    assert_eq!(captured.events(), ["price_anomaly"]);
+
+    // 中央値から急に動いた価格と、大きすぎる見込みエッジも止める
+    let opportunity = evaluate(&kong, &ics, &trade.decision_params());
+    let breaker = PriceBreaker::new(BreakerConfig {
+        max_divergence_bps: 0.0,
+        max_jump_bps: 300.0,
+        ..config.clone()
+    });
+    let mut jumped = ics.clone();
+    jumped.price_icp_per_sns *= 1.2;
+    let t0 = now_ms();
+    // 取り直せずキャッシュを使った tick は中央値の材料にしない
+    for n in 0..4 {
+        assert!(breaker
+            .check(t0 + n, true, &kong, &ics, &opportunity)
+            .is_ok());
+    }
+    for n in 4..20 {
+        assert!(breaker
+            .check(t0 + n, false, &kong, &ics, &opportunity)
+            .is_ok());
+    }
+    assert!(breaker
+        .check(t0 + 20, false, &kong, &jumped, &opportunity)
+        .is_ok());
+    assert!(breaker
+        .check(t0 + 21, true, &kong, &ics, &opportunity)
+        .is_ok());
+    assert!(matches!(
+        breaker.check(t0 + 22, false, &kong, &jumped, &opportunity),
+        Err(PriceAnomaly::Jump { venue: "ICS", .. })
+    ));
+    // 窓より古い価格は捨てるので、また 5 件たまるまでは見ない
+    assert!(breaker
+        .check(t0 + 61_000, false, &kong, &jumped, &opportunity)
+        .is_ok());
+    let breaker = PriceBreaker::new(BreakerConfig {
+        max_divergence_bps: 0.0,
+        max_edge_bps: 1.0,
+        ..config
+    });
+    assert!(matches!(
+        breaker.check(now_ms(), true, &kong, &ics, &opportunity),
+        Err(PriceAnomaly::Edge { .. })
+    ));
+}